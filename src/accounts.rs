@@ -0,0 +1,484 @@
+use axum::http::StatusCode;
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use std::time::Duration;
+use crate::{
+  db::{email_collation, is_duplicate_key, AppState},
+  auth::{check_password_policy, hash_password, record_security_event},
+  handlers::{ensure_role_exists, revoke_all_sessions},
+  notifications::{send_security_alert, SecurityAlert},
+  models::{
+    ActionToken, Invitation, Permission, RefreshToken, Role, SecurityEvent, SecurityEventKind, Session, Task, User,
+    ADMIN_ROLE,
+  },
+  utils::{ResultExt, AppError},
+};
+
+// How often deleted accounts are checked for an expired grace period
+const ANONYMISE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Whoever holds all of these can manage accounts and roles, and so let everyone else back in
+const ADMIN_PERMISSIONS: [Permission; 2] = [Permission::UsersWrite, Permission::RolesWrite];
+
+/// What happens to the tasks of a deleted account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskPolicy {
+  Delete,
+  ReassignTo(ObjectId),
+}
+
+/// An account about to be created by an admin, the CLI or the seed at startup
+pub struct NewAccount {
+  pub full_name: String,
+  pub email: String,
+  pub password: String,
+  pub role: String,
+  // Skip the verification email; for accounts set up by an operator
+  pub verified: bool,
+}
+
+/// Validate and store a new account, hashing its password; the returned user has its ID set
+pub async fn create_account(
+  app_state: &AppState,
+  account: NewAccount,
+  actor_id: Option<ObjectId>,
+) -> Result<User, AppError> {
+  let users = app_state.db.collection::<User>("users");
+  let existing_user = users
+    .find_one(doc! { "email": &account.email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  if existing_user.is_some() {
+    return Err(AppError::conflict("User already exists"));
+  }
+
+  account.email.parse::<lettre::Address>()
+      .bad_request("Invalid email address")?;
+  if account.full_name.trim().is_empty() {
+    return Err(AppError::bad_request("Full name cannot be empty"));
+  }
+  ensure_role_exists(app_state, &account.role).await?;
+  check_password_policy(
+    &app_state.config.password_policy,
+    "password",
+    &account.password,
+    &[&account.email, &account.full_name],
+  ).await?;
+
+  let now = Utc::now();
+  let mut user = User {
+    id: None,
+    full_name: account.full_name,
+    email: account.email,
+    password: hash_password(&app_state.config.password_hash, account.password).await?,
+    role: account.role,
+    created_by: actor_id,
+    updated_by: actor_id,
+    deleted: false,
+    deleted_at: None,
+    anonymized_at: None,
+    verified: account.verified,
+    verification_sent_at: (!account.verified).then_some(now),
+    calendar_token_hash: None,
+    token_generation: 0,
+    mfa: None,
+    oidc_identity: None,
+    preferences: Default::default(),
+    created_at: Some(now),
+    updated_at: Some(now),
+  };
+  let result = match users.insert_one(&user).await {
+    Ok(result) => result,
+    // The unique email index caught an account created since the check above
+    Err(e) if is_duplicate_key(&e) => return Err(AppError::conflict("User already exists")),
+    Err(_) => return Err(AppError::internal_error("Failed to insert user into database")),
+  };
+  user.id = Some(result.inserted_id.as_object_id()
+      .ok_or_else(|| AppError::internal_error("Failed to get inserted user ID"))?);
+  Ok(user)
+}
+
+/// Give `email` a new password and log it out everywhere
+pub async fn set_password(app_state: &AppState, email: &str, password: String) -> Result<User, AppError> {
+  let users = app_state.db.collection::<User>("users");
+  let user = users.find_one(doc! { "email": email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  check_password_policy(&app_state.config.password_policy, "password", &password, &[&user.email, &user.full_name]).await?;
+
+  let password = hash_password(&app_state.config.password_hash, password).await?;
+  users
+    .update_one(doc! { "_id": user_id, "deleted": false }, doc! { "$set": {
+      "password": password,
+      "updated_at": mongodb::bson::to_bson(&Utc::now()).internal_error("Failed to encode timestamp")?,
+    } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  revoke_all_sessions(app_state, user_id).await?;
+  send_security_alert(app_state, &user, SecurityAlert::PasswordChanged).await;
+  Ok(user)
+}
+
+/// Move `email` to another role; the last admin cannot be demoted
+pub async fn set_role(app_state: &AppState, email: &str, role: &str) -> Result<User, AppError> {
+  ensure_role_exists(app_state, role).await?;
+  let users = app_state.db.collection::<User>("users");
+  let mut user = users.find_one(doc! { "email": email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+  ensure_not_last_admin(app_state, &user, Some(role), "Cannot demote the last admin").await?;
+
+  users
+    .update_one(doc! { "_id": user.id, "deleted": false }, doc! { "$set": {
+      "role": role,
+      "updated_at": mongodb::bson::to_bson(&Utc::now()).internal_error("Failed to encode timestamp")?,
+    } })
+    .await
+    .internal_error("Failed to update user")?;
+  user.role = role.to_string();
+  Ok(user)
+}
+
+/// Refuse to move `user` to `new_role`, or to delete it when that is `None`, if it is the last
+/// account whose role grants every `ADMIN_PERMISSIONS`, whatever that role is called
+pub async fn ensure_not_last_admin(
+  app_state: &AppState,
+  user: &User,
+  new_role: Option<&str>,
+  message: &str,
+) -> Result<(), AppError> {
+  let permissions = mongodb::bson::to_bson(&ADMIN_PERMISSIONS)
+      .internal_error("Failed to encode permissions")?;
+  let mut roles = app_state.db.collection::<Role>("roles")
+    .find(doc! { "permissions": { "$all": permissions } })
+    .await
+    .internal_error("Failed to query roles")?;
+  let mut admin_roles = Vec::new();
+  while roles.advance().await.internal_error("Failed to query roles")? {
+    admin_roles.push(roles.deserialize_current().internal_error("Failed to read role")?.name);
+  }
+
+  let stays_admin = new_role.is_some_and(|role| admin_roles.iter().any(|name| name == role));
+  if !admin_roles.contains(&user.role) || stays_admin {
+    return Ok(());
+  }
+  let admins = app_state.db.collection::<User>("users")
+    .count_documents(doc! { "role": { "$in": &admin_roles }, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?;
+  if admins <= 1 {
+    return Err(AppError::conflict(message));
+  }
+  Ok(())
+}
+
+/// Create the `SEED_ADMIN_*` account on a database that has never had a user
+/// Once anyone has signed up, or the seed admin was deleted, `user create --admin` is the way back in
+pub async fn seed_admin(app_state: &AppState) -> Result<(), AppError> {
+  let Some(seed) = app_state.config.seed_admin.clone() else {
+    return Ok(());
+  };
+  // Deleted accounts count too, so removing the seed admin does not bring it back on restart
+  let users = app_state.db.collection::<User>("users")
+    .estimated_document_count()
+    .await
+    .internal_error("Failed to query database")?;
+  if users > 0 {
+    return Ok(());
+  }
+
+  let account = NewAccount {
+    full_name: seed.full_name,
+    email: seed.email,
+    password: seed.password,
+    role: ADMIN_ROLE.to_string(),
+    verified: true,
+  };
+  match create_account(app_state, account, None).await {
+    Ok(user) => tracing::info!(user = %user.email, "created seed admin"),
+    // Another instance starting against the same database got there first
+    Err(e) if e.status == StatusCode::CONFLICT => tracing::warn!("seed admin already exists; skipping"),
+    Err(e) => return Err(e),
+  }
+  Ok(())
+}
+
+/// Soft-delete an account: it can no longer log in, every session and API token stops working,
+/// and its tasks are deleted or handed over; personal data stays until `anonymise_deleted_accounts`
+pub async fn delete_account(
+  app_state: &AppState,
+  user_id: ObjectId,
+  tasks: TaskPolicy,
+  actor_id: ObjectId,
+) -> Result<(), AppError> {
+  let users = app_state.db.collection::<User>("users");
+  let user = users.find_one(doc! { "_id": user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+  ensure_not_last_admin(app_state, &user, None, "Cannot delete the last admin").await?;
+  if let TaskPolicy::ReassignTo(new_owner) = tasks {
+    let exists = new_owner != user_id && users
+      .find_one(doc! { "_id": new_owner, "deleted": false })
+      .await
+      .internal_error("Failed to query database")?
+      .is_some();
+    if !exists {
+      return Err(AppError::bad_request("Tasks can only be reassigned to another existing user"));
+    }
+  }
+
+  let now = Utc::now();
+  let updated_at = mongodb::bson::to_bson(&now)
+      .internal_error("Failed to encode timestamp")?;
+  let result = users
+    .update_one(
+      doc! { "_id": user_id, "deleted": false },
+      doc! {
+        "$set": {
+          "deleted": true,
+          "deleted_at": mongodb::bson::DateTime::from_chrono(now),
+          "updated_by": actor_id,
+          "updated_at": updated_at.clone(),
+        },
+        // The calendar feed URL would otherwise keep serving the tasks
+        "$unset": { "calendar_token_hash": "" },
+      },
+    )
+    .await
+    .internal_error("Failed to delete user")?;
+  if result.modified_count == 0 {
+    return Err(AppError::not_found("User not found"));
+  }
+
+  let task_update = match tasks {
+    TaskPolicy::Delete => doc! { "$set": { "deleted": true, "updated_at": updated_at } },
+    // The CalDAV resource name belonged to the old owner's collection
+    TaskPolicy::ReassignTo(new_owner) => doc! {
+      "$set": { "user_id": new_owner, "updated_by": actor_id, "updated_at": updated_at },
+      "$unset": { "dav_resource": "" },
+    },
+  };
+  app_state.db.collection::<Task>("tasks")
+    .update_many(doc! { "user_id": user_id, "deleted": false }, task_update)
+    .await
+    .internal_error("Failed to update tasks")?;
+
+  revoke_all_sessions(app_state, user_id).await?;
+  app_state.db.collection::<ActionToken>("action_tokens")
+    .delete_many(doc! { "user_id": user_id })
+    .await
+    .internal_error("Failed to revoke pending links")?;
+
+  let mut event = SecurityEvent::new(SecurityEventKind::AccountDeleted);
+  event.user_id = Some(user_id);
+  event.email = Some(user.email);
+  event.actor_id = Some(actor_id);
+  record_security_event(app_state, event).await;
+
+  Ok(())
+}
+
+/// Erase the personal data of accounts deleted longer ago than `ACCOUNT_DELETION_GRACE_DAYS`
+/// The user document stays, renamed, so `created_by` / `updated_by` references still resolve
+pub async fn anonymise_deleted_accounts(app_state: &AppState) -> Result<u64, AppError> {
+  let cutoff = Utc::now() - app_state.config.account_deletion_grace;
+  let users = app_state.db.collection::<Document>("users");
+  let mut expired = users
+    .find(doc! {
+      "deleted": true,
+      "anonymized_at": null,
+      "deleted_at": { "$lte": mongodb::bson::DateTime::from_chrono(cutoff) },
+    })
+    .projection(doc! { "_id": 1 })
+    .await
+    .internal_error("Failed to query database")?;
+
+  let mut count = 0;
+  while expired.advance().await.internal_error("Failed to query database")? {
+    let user_id = expired.deserialize_current()
+      .internal_error("Failed to read user")?
+      .get_object_id("_id")
+      .internal_error("Failed to read user")?;
+    anonymise_account(app_state, user_id).await?;
+    count += 1;
+  }
+  Ok(count)
+}
+
+async fn anonymise_account(app_state: &AppState, user_id: ObjectId) -> Result<(), AppError> {
+  let now = Utc::now();
+  let users = app_state.db.collection::<User>("users");
+  let Some(user) = users.find_one(doc! { "_id": user_id, "deleted": true })
+    .await
+    .internal_error("Failed to query database")?
+  else {
+    return Ok(());
+  };
+  // Unique, and can never receive mail or match a login
+  let anonymised_email = format!("deleted-{}@invalid", user_id.to_hex());
+  users
+    .update_one(
+      doc! { "_id": user_id, "deleted": true },
+      doc! {
+        "$set": {
+          "full_name": "Deleted user",
+          "email": &anonymised_email,
+          "password": "",
+          "anonymized_at": mongodb::bson::DateTime::from_chrono(now),
+        },
+        "$unset": {
+          "verification_sent_at": "",
+          "mfa": "",
+          "oidc_identity": "",
+          "preferences": "",
+        },
+      },
+    )
+    .await
+    .internal_error("Failed to anonymise user")?;
+
+  // Tasks that were reassigned live on with their new owner
+  app_state.db.collection::<Task>("tasks")
+    .delete_many(doc! { "user_id": user_id })
+    .await
+    .internal_error("Failed to delete tasks")?;
+  app_state.db.collection::<Session>("sessions")
+    .delete_many(doc! { "user_id": user_id })
+    .await
+    .internal_error("Failed to delete sessions")?;
+  app_state.db.collection::<RefreshToken>("refresh_tokens")
+    .delete_many(doc! { "user_id": user_id })
+    .await
+    .internal_error("Failed to delete refresh tokens")?;
+  app_state.db.collection::<SecurityEvent>("security_events")
+    .update_many(doc! { "user_id": user_id }, doc! { "$unset": { "email": "", "ip": "" } })
+    .await
+    .internal_error("Failed to anonymise security events")?;
+  // Invitations sent to the address; the inviter and dates stay for the audit trail
+  app_state.db.collection::<Invitation>("invitations")
+    .update_many(doc! { "email": &user.email }, doc! { "$set": { "email": &anonymised_email } })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to anonymise invitations")?;
+
+  let mut event = SecurityEvent::new(SecurityEventKind::AccountAnonymized);
+  event.user_id = Some(user_id);
+  record_security_event(app_state, event).await;
+
+  Ok(())
+}
+
+/// Run `anonymise_deleted_accounts` in the background for the life of the server
+pub fn spawn_anonymiser(app_state: AppState) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(ANONYMISE_INTERVAL);
+    loop {
+      interval.tick().await;
+      match anonymise_deleted_accounts(&app_state).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "anonymised deleted accounts"),
+        Err(e) => tracing::error!(error = %e.message, "failed to anonymise deleted accounts"),
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    config::SeedAdminConfig,
+    models::USER_ROLE,
+    test_support::{create_user, test_state, test_state_with, PASSWORD},
+  };
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn the_last_admin_is_found_by_permission() {
+    let app_state = test_state().await;
+    app_state.db.collection::<Document>("roles")
+      .insert_one(doc! { "name": "owner", "permissions": ["users:write", "roles:write"], "built_in": false })
+      .await
+      .unwrap();
+    let owner = create_user(&app_state, "owner@example.com", "owner").await;
+    let member = create_user(&app_state, "member@example.com", USER_ROLE).await;
+
+    let error = delete_account(&app_state, owner.id.unwrap(), TaskPolicy::Delete, member.id.unwrap()).await.unwrap_err();
+    assert_eq!(error.status, StatusCode::CONFLICT);
+    let error = set_role(&app_state, "owner@example.com", USER_ROLE).await.unwrap_err();
+    assert_eq!(error.status, StatusCode::CONFLICT);
+    set_role(&app_state, "owner@example.com", ADMIN_ROLE).await.unwrap();
+
+    create_user(&app_state, "admin@example.com", ADMIN_ROLE).await;
+    delete_account(&app_state, owner.id.unwrap(), TaskPolicy::Delete, member.id.unwrap()).await.unwrap();
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn anonymising_scrubs_invitations_to_the_address() {
+    let app_state = test_state().await;
+    let admin = create_user(&app_state, "admin@example.com", ADMIN_ROLE).await;
+    let member = create_user(&app_state, "member@example.com", USER_ROLE).await;
+    let member_id = member.id.unwrap();
+    let invitations = app_state.db.collection::<Document>("invitations");
+    invitations.insert_one(doc! { "email": "Member@example.com", "role": USER_ROLE, "invited_by": admin.id.unwrap() })
+      .await
+      .unwrap();
+
+    delete_account(&app_state, member_id, TaskPolicy::Delete, admin.id.unwrap()).await.unwrap();
+    anonymise_account(&app_state, member_id).await.unwrap();
+
+    let invitation = invitations.find_one(doc! {}).await.unwrap().unwrap();
+    assert_eq!(invitation.get_str("email").unwrap(), format!("deleted-{}@invalid", member_id.to_hex()));
+    assert_eq!(invitation.get_object_id("invited_by").unwrap(), admin.id.unwrap());
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn the_seed_admin_is_only_created_on_a_fresh_database() {
+    let app_state = test_state_with(|config| {
+      config.seed_admin = Some(SeedAdminConfig {
+        email: "seed@example.com".to_string(),
+        full_name: "Seed Admin".to_string(),
+        password: PASSWORD.to_string(),
+      });
+    }).await;
+    let users = app_state.db.collection::<User>("users");
+
+    seed_admin(&app_state).await.unwrap();
+    assert_eq!(users.count_documents(doc! { "role": ADMIN_ROLE }).await.unwrap(), 1);
+
+    // Deleting the seed admin must not bring it back on the next start
+    users.update_many(doc! {}, doc! { "$set": { "deleted": true } }).await.unwrap();
+    seed_admin(&app_state).await.unwrap();
+    assert_eq!(users.count_documents(doc! {}).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn duplicate_accounts_are_a_conflict() {
+    let app_state = test_state().await;
+    create_user(&app_state, "member@example.com", USER_ROLE).await;
+
+    let account = NewAccount {
+      full_name: "Member".to_string(),
+      email: "Member@Example.com".to_string(),
+      password: PASSWORD.to_string(),
+      role: USER_ROLE.to_string(),
+      verified: true,
+    };
+    let error = create_account(&app_state, account, None).await.unwrap_err();
+    assert_eq!(error.status, StatusCode::CONFLICT);
+  }
+}
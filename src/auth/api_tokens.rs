@@ -0,0 +1,12 @@
+use crate::utils::generate_token;
+
+// Marks a bearer token as a personal access token rather than a JWT
+pub const API_TOKEN_PREFIX: &str = "tdl_pat_";
+
+pub fn generate_api_token() -> String {
+  format!("{API_TOKEN_PREFIX}{}", generate_token())
+}
+
+pub fn is_api_token(token: &str) -> bool {
+  token.starts_with(API_TOKEN_PREFIX)
+}
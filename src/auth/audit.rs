@@ -0,0 +1,33 @@
+use crate::{
+  db::AppState,
+  models::SecurityEvent,
+  utils::{ResultExt, AppError},
+};
+
+/// Log a security event and keep it in `security_events`
+/// Failing to store it never fails the request that caused it
+pub async fn record_security_event(app_state: &AppState, event: SecurityEvent) {
+  if let Err(e) = store_security_event(app_state, &event).await {
+    tracing::error!(error = %e.message, "failed to store security event");
+  }
+}
+
+/// Like `record_security_event`, for callers that must not go ahead unless the event was kept
+pub async fn store_security_event(app_state: &AppState, event: &SecurityEvent) -> Result<(), AppError> {
+  tracing::warn!(
+    target: "security",
+    kind = event.kind.as_str(),
+    user_id = ?event.user_id,
+    email = ?event.email,
+    ip = ?event.ip,
+    actor_id = ?event.actor_id,
+    detail = ?event.detail,
+    "security event",
+  );
+
+  app_state.db.collection::<SecurityEvent>("security_events")
+    .insert_one(event)
+    .await
+    .internal_error("Failed to store security event")?;
+  Ok(())
+}
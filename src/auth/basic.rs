@@ -0,0 +1,123 @@
+use axum::{
+  async_trait,
+  extract::FromRequestParts,
+  http::{header, request::Parts, StatusCode},
+  response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mongodb::bson::oid::ObjectId;
+use crate::{
+  auth::{
+    is_api_token,
+    middleware::{authenticate_api_token, role_permissions},
+    verify_login_password,
+    ClientInfo,
+  },
+  db::AppState,
+  models::Permission,
+  utils::AppError,
+};
+
+// User authenticated with HTTP Basic credentials
+// CalDAV clients cannot obtain a JWT, so they send an email address and either the account
+// password or a personal access token on every request
+#[derive(Clone)]
+pub struct BasicAuthUser {
+  pub user_id: ObjectId,
+  pub email: String,
+  // The role's permissions, narrowed to the token's scopes when a token was used
+  pub permissions: Vec<Permission>,
+}
+
+impl BasicAuthUser {
+  pub fn require_permission(&self, permission: Permission) -> Result<(), AppError> {
+    if !self.permissions.contains(&permission) {
+      return Err(AppError::forbidden(format!("Missing permission `{permission}`")));
+    }
+    Ok(())
+  }
+}
+
+// Why Basic credentials were not accepted
+pub enum BasicAuthRejection {
+  // Missing or wrong credentials: ask the client to retry
+  Challenge,
+  // Right credentials that cannot be used here, or too many wrong ones
+  Refused(AppError),
+}
+
+impl IntoResponse for BasicAuthRejection {
+  fn into_response(self) -> Response {
+    match self {
+      Self::Challenge => (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"to_do_list\", charset=\"UTF-8\"")],
+      ).into_response(),
+      Self::Refused(e) => e.into_response(),
+    }
+  }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for BasicAuthUser {
+  type Rejection = BasicAuthRejection;
+
+  async fn from_request_parts(
+    parts: &mut Parts,
+    state: &AppState,
+  ) -> Result<Self, Self::Rejection> {
+    let (email, password) = parts.headers
+      .get(header::AUTHORIZATION)
+      .and_then(|h| h.to_str().ok())
+      .and_then(|h| h.strip_prefix("Basic "))
+      .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+      .and_then(|decoded| String::from_utf8(decoded).ok())
+      .and_then(|decoded| {
+        decoded.split_once(':').map(|(email, password)| (email.to_string(), password.to_string()))
+      })
+      .ok_or(BasicAuthRejection::Challenge)?;
+
+    // A personal access token works in place of the password, and is the only way in with 2FA on
+    if is_api_token(&password) {
+      let user = authenticate_api_token(state, &password)
+        .await
+        .map_err(|_| BasicAuthRejection::Challenge)?;
+      if !user.email.eq_ignore_ascii_case(&email) {
+        return Err(BasicAuthRejection::Challenge);
+      }
+      return Ok(BasicAuthUser {
+        user_id: ObjectId::parse_str(&user.user_id).map_err(|_| BasicAuthRejection::Challenge)?,
+        email: user.email,
+        permissions: user.permissions,
+      });
+    }
+
+    let Ok(client) = ClientInfo::from_request_parts(parts, state).await;
+    let (user, _) = verify_login_password(state, &email, &password, &client)
+      .await
+      .map_err(|e| match e.status {
+        StatusCode::UNAUTHORIZED => BasicAuthRejection::Challenge,
+        _ => BasicAuthRejection::Refused(e),
+      })?;
+
+    // The same gates as `complete_login`; a password alone cannot pass a second factor
+    if state.config.require_verified_email && !user.verified {
+      return Err(BasicAuthRejection::Refused(AppError::forbidden("Email address has not been verified")));
+    }
+    if user.mfa.as_ref().is_some_and(|mfa| mfa.enabled) {
+      return Err(BasicAuthRejection::Refused(AppError::forbidden(
+        "Two-factor authentication is enabled; use a personal access token as the password",
+      )));
+    }
+
+    let permissions = role_permissions(state, &user.role)
+      .await
+      .map_err(|_| BasicAuthRejection::Refused(AppError::internal_error("Unable to load role")))?;
+
+    Ok(BasicAuthUser {
+      user_id: user.id.ok_or(BasicAuthRejection::Challenge)?,
+      email: user.email,
+      permissions,
+    })
+  }
+}
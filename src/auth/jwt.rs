@@ -1,44 +1,178 @@
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
-use chrono::{Duration, Utc};
-use std::env;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use crate::auth::{JwtKeys, TokenKind};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
   pub user_id: String,
   pub email: String,
-  pub role: i16,
+  pub role: String,
   pub exp: usize,
+  // Unique token ID, used to revoke a single token on logout
+  pub jti: String,
+  // The user's token generation at issue time; bumping it invalidates every older token
+  pub token_generation: i64,
+  // Session the token belongs to; ending the session invalidates it
+  // Tokens issued before sessions existed have none and stay valid until they expire
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sid: Option<String>,
+  // Set on impersonation tokens: the admin acting as `user_id` (RFC 8693 `act`)
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub act: Option<Actor>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Actor {
+  pub sub: String,
+  pub email: String,
 }
 
 impl Claims {
-  pub fn new(user_id: String, email: String, role: i16) -> Self {
-    let exp = (Utc::now() + Duration::hours(1)).timestamp() as usize;
-    Self { user_id, email, role, exp }
-  }
-}
-
-pub fn create_token(claims: Claims) -> Result<String, jsonwebtoken::errors::Error> {
-  let secret = env::var("JWT_SECRET")
-      .expect("JWT_SECRET must be set");
-  
-  encode(
-    &Header::default(), 
-    &claims, 
-    &EncodingKey::from_secret(secret.as_bytes())
-  )
-}
-
-pub fn verify_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-  let secret = env::var("JWT_SECRET")
-      .expect("JWT_SECRET must be set");
-  
-  let validation = Validation::default();
-  let token_data = decode::<Claims>(
-    token, 
-    &DecodingKey::from_secret(secret.as_bytes()), 
-    &validation
-  )?;
-
-  Ok(token_data.claims)
-}
\ No newline at end of file
+  pub fn new(user_id: String, email: String, role: String, token_generation: i64, sid: String, ttl: Duration) -> Self {
+    let exp = (Utc::now() + ttl).timestamp() as usize;
+    let jti = Uuid::new_v4().to_string();
+    Self { user_id, email, role, exp, jti, token_generation, sid: Some(sid), act: None }
+  }
+
+  pub fn with_actor(self, actor: Actor) -> Self {
+    Self { act: Some(actor), ..self }
+  }
+
+  pub fn expires_at(&self) -> DateTime<Utc> {
+    DateTime::from_timestamp(self.exp as i64, 0).unwrap_or_else(Utc::now)
+  }
+}
+
+pub fn create_token(keys: &JwtKeys, claims: Claims) -> Result<String, jsonwebtoken::errors::Error> {
+  keys.sign(TokenKind::Access, &claims)
+}
+
+/// Claims of an access token; MFA challenge and email verification tokens are refused
+pub fn verify_token(keys: &JwtKeys, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+  keys.verify(TokenKind::Access, token)
+}
+
+// Claims of the signed link mailed to confirm an email address
+// Bound to the address, so the link stops working once the email changes again
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmailVerificationClaims {
+  pub sub: String,
+  pub email: String,
+  pub purpose: String,
+  pub exp: usize,
+}
+
+const EMAIL_VERIFICATION: &str = "email_verification";
+
+impl EmailVerificationClaims {
+  pub fn new(user_id: String, email: String, ttl: Duration) -> Self {
+    let exp = (Utc::now() + ttl).timestamp() as usize;
+    Self { sub: user_id, email, purpose: EMAIL_VERIFICATION.to_string(), exp }
+  }
+}
+
+pub fn create_verification_token(keys: &JwtKeys, claims: EmailVerificationClaims) -> Result<String, jsonwebtoken::errors::Error> {
+  keys.sign(TokenKind::EmailVerification, &claims)
+}
+
+pub fn verify_verification_token(keys: &JwtKeys, token: &str) -> Option<EmailVerificationClaims> {
+  let claims: EmailVerificationClaims = keys.verify(TokenKind::EmailVerification, token).ok()?;
+  (claims.purpose == EMAIL_VERIFICATION).then_some(claims)
+}
+
+// Claims of the token `login` hands out when a second factor is still needed
+// It only proves the password was right and cannot be used as an access token
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaChallengeClaims {
+  pub sub: String,
+  pub purpose: String,
+  pub exp: usize,
+  // Revoked once the challenge has been answered, so it works only once
+  pub jti: String,
+}
+
+const MFA_CHALLENGE: &str = "mfa_challenge";
+
+impl MfaChallengeClaims {
+  pub fn new(user_id: String, ttl: Duration) -> Self {
+    let exp = (Utc::now() + ttl).timestamp() as usize;
+    let jti = Uuid::new_v4().to_string();
+    Self { sub: user_id, purpose: MFA_CHALLENGE.to_string(), exp, jti }
+  }
+
+  pub fn expires_at(&self) -> DateTime<Utc> {
+    DateTime::from_timestamp(self.exp as i64, 0).unwrap_or_else(Utc::now)
+  }
+}
+
+pub fn create_mfa_challenge_token(keys: &JwtKeys, claims: MfaChallengeClaims) -> Result<String, jsonwebtoken::errors::Error> {
+  keys.sign(TokenKind::MfaChallenge, &claims)
+}
+
+pub fn verify_mfa_challenge_token(keys: &JwtKeys, token: &str) -> Option<MfaChallengeClaims> {
+  let claims: MfaChallengeClaims = keys.verify(TokenKind::MfaChallenge, token).ok()?;
+  (claims.purpose == MFA_CHALLENGE).then_some(claims)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reads_tokens_issued_before_sessions() {
+    let claims: Claims = serde_json::from_value(serde_json::json!({
+      "user_id": "65f000000000000000000001",
+      "email": "a@example.com",
+      "role": "user",
+      "exp": 1_900_000_000,
+      "jti": "3f1c",
+      "token_generation": 0,
+    })).unwrap();
+    assert_eq!(claims.sid, None);
+
+    let claims = Claims::new("id".into(), "a@example.com".into(), "user".into(), 0, "sid".into(), Duration::minutes(5));
+    let value = serde_json::to_value(&claims).unwrap();
+    assert_eq!(value["sid"], "sid");
+  }
+
+  #[test]
+  fn only_access_tokens_pass_as_access_tokens() {
+    let keys = JwtKeys::from_config(&crate::config::JwtConfig {
+      algorithm: crate::config::JwtAlgorithm::Hs256,
+      secret: Some("secret".to_string()),
+      private_key_file: None,
+      key_id: None,
+      verification_key_files: Vec::new(),
+      issuer: "http://127.0.0.1:3000".to_string(),
+      audience: "todo-api".to_string(),
+      validate_issuer: true,
+      validate_audience: true,
+      leeway_seconds: 0,
+    }).unwrap();
+    let ttl = Duration::minutes(5);
+
+    let access = create_token(&keys, Claims::new("id".into(), "a@example.com".into(), "user".into(), 0, "sid".into(), ttl)).unwrap();
+    assert!(verify_token(&keys, &access).is_ok());
+    assert!(verify_mfa_challenge_token(&keys, &access).is_none());
+
+    let challenge = create_mfa_challenge_token(&keys, MfaChallengeClaims::new("id".into(), ttl)).unwrap();
+    assert!(verify_token(&keys, &challenge).is_err());
+    assert!(verify_verification_token(&keys, &challenge).is_none());
+    assert!(verify_mfa_challenge_token(&keys, &challenge).is_some());
+
+    let verification = EmailVerificationClaims::new("id".into(), "a@example.com".into(), ttl);
+    let verification = create_verification_token(&keys, verification).unwrap();
+    assert!(verify_token(&keys, &verification).is_err());
+    assert!(verify_verification_token(&keys, &verification).is_some());
+
+    // Even a verifier that skips the `typ` check finds the wrong audience
+    let header = jsonwebtoken::decode_header(&challenge).unwrap();
+    assert_eq!(header.typ.as_deref(), Some("mfa-challenge+jwt"));
+    let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+    validation.set_audience(&["todo-api"]);
+    let key = jsonwebtoken::DecodingKey::from_secret(b"secret");
+    assert!(jsonwebtoken::decode::<serde_json::Value>(&challenge, &key, &validation).is_err());
+    assert!(jsonwebtoken::decode::<serde_json::Value>(&access, &key, &validation).is_ok());
+  }
+}
@@ -0,0 +1,386 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{SigningKey as Ed25519SigningKey, VerifyingKey as Ed25519VerifyingKey};
+use jsonwebtoken::{
+  decode, decode_header, encode,
+  jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+  },
+  Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rsa::{
+  pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+  pkcs8::{DecodePrivateKey, DecodePublicKey},
+  traits::PublicKeyParts,
+  RsaPrivateKey, RsaPublicKey,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+use chrono::Utc;
+use crate::config::{JwtAlgorithm, JwtConfig};
+
+struct VerificationKey {
+  kid: Option<String>,
+  algorithm: Algorithm,
+  key: DecodingKey,
+  // Public half, published at `/.well-known/jwks.json`; none for a shared secret
+  jwk: Option<Jwk>,
+}
+
+/// Keys every token this service issues is signed and checked with
+/// New tokens are signed with one key; tokens signed by any of the verification keys are accepted,
+/// so a retired key can stay valid until the last token it signed has expired
+pub struct JwtKeys {
+  signing_kid: Option<String>,
+  algorithm: Algorithm,
+  signing_key: EncodingKey,
+  verification_keys: Vec<VerificationKey>,
+  issuer: String,
+  audience: String,
+  validate_issuer: bool,
+  validate_audience: bool,
+  leeway_seconds: u64,
+}
+
+/// What a token is for; each kind has its own `typ` header and is checked for it on `verify`,
+/// so a token minted for one purpose is never accepted for another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+  Access,
+  MfaChallenge,
+  EmailVerification,
+}
+
+impl TokenKind {
+  fn typ(self) -> &'static str {
+    match self {
+      // RFC 9068
+      TokenKind::Access => "at+jwt",
+      TokenKind::MfaChallenge => "mfa-challenge+jwt",
+      TokenKind::EmailVerification => "email-verification+jwt",
+    }
+  }
+
+  // Only access tokens carry the configured audience; the others are addressed to this
+  // service alone, so a resource server checking `aud` against our JWKS turns them away
+  fn audience(self, audience: &str) -> String {
+    match self {
+      TokenKind::Access => audience.to_string(),
+      TokenKind::MfaChallenge => format!("{audience}/mfa-challenge"),
+      TokenKind::EmailVerification => format!("{audience}/email-verification"),
+    }
+  }
+}
+
+// Registered claims added to everything `sign` produces
+#[derive(Serialize)]
+struct Signed<'a, T> {
+  iss: &'a str,
+  aud: &'a str,
+  iat: i64,
+  nbf: i64,
+  #[serde(flatten)]
+  claims: &'a T,
+}
+
+impl JwtKeys {
+  pub fn from_config(config: &JwtConfig) -> Result<Self, String> {
+    let (signing_kid, algorithm, signing_key, mut verification_keys) = match config.algorithm {
+      JwtAlgorithm::Hs256 => {
+        let secret = config.secret.as_ref()
+          .ok_or("JWT_SECRET must be set for HS256")?;
+        let verification = VerificationKey {
+          kid: None,
+          algorithm: Algorithm::HS256,
+          key: DecodingKey::from_secret(secret.as_bytes()),
+          jwk: None,
+        };
+        (None, Algorithm::HS256, EncodingKey::from_secret(secret.as_bytes()), vec![verification])
+      }
+      JwtAlgorithm::Rs256 | JwtAlgorithm::EdDsa => {
+        let path = config.private_key_file.as_ref()
+          .ok_or("JWT_PRIVATE_KEY_FILE must be set for RS256 and EdDSA")?;
+        let pem = read_pem(path)?;
+        let (signing_key, jwk) = load_private_key(&config.algorithm, &pem)
+          .map_err(|e| format!("{}: {e}", path.display()))?;
+        let verification = verification_key(config.key_id.clone(), jwk)?;
+        (verification.kid.clone(), verification.algorithm, signing_key, vec![verification])
+      }
+    };
+
+    for (kid, path) in &config.verification_key_files {
+      let jwk = load_public_key(&read_pem(path)?)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+      verification_keys.push(verification_key(kid.clone(), jwk)?);
+    }
+
+    Ok(Self {
+      signing_kid,
+      algorithm,
+      signing_key,
+      verification_keys,
+      issuer: config.issuer.clone(),
+      audience: config.audience.clone(),
+      validate_issuer: config.validate_issuer,
+      validate_audience: config.validate_audience,
+      leeway_seconds: config.leeway_seconds,
+    })
+  }
+
+  pub fn sign<T: Serialize>(&self, kind: TokenKind, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+    let mut header = Header::new(self.algorithm);
+    header.kid = self.signing_kid.clone();
+    header.typ = Some(kind.typ().to_string());
+
+    let now = Utc::now().timestamp();
+    let audience = kind.audience(&self.audience);
+    let claims = Signed { iss: &self.issuer, aud: &audience, iat: now, nbf: now, claims };
+    encode(&header, &claims, &self.signing_key)
+  }
+
+  pub fn verify<T: DeserializeOwned + Clone>(&self, kind: TokenKind, token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+    let header = decode_header(token)?;
+    if header.typ.as_deref() != Some(kind.typ()) {
+      return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    // The key decides the algorithm, never the token, so an RSA public key cannot be used as an HMAC secret
+    let key = self.verification_keys
+      .iter()
+      .find(|key| key.kid == header.kid && key.algorithm == header.alg)
+      .ok_or(jsonwebtoken::errors::ErrorKind::InvalidSignature)?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.leeway = self.leeway_seconds;
+    validation.validate_nbf = true;
+    if self.validate_issuer {
+      validation.set_issuer(&[&self.issuer]);
+    }
+    // Internal tokens are always ours to check, whatever `validate_audience` says
+    if self.validate_audience || kind != TokenKind::Access {
+      validation.set_audience(&[kind.audience(&self.audience)]);
+    } else {
+      validation.validate_aud = false;
+    }
+
+    Ok(decode::<T>(token, &key.key, &validation)?.claims)
+  }
+
+  /// Public keys other services need to verify our tokens
+  pub fn jwks(&self) -> JwkSet {
+    JwkSet {
+      keys: self.verification_keys.iter().filter_map(|key| key.jwk.clone()).collect(),
+    }
+  }
+}
+
+fn read_pem(path: &Path) -> Result<String, String> {
+  fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn verification_key(kid: Option<String>, mut jwk: Jwk) -> Result<VerificationKey, String> {
+  let kid = kid.unwrap_or_else(|| thumbprint(&jwk));
+  jwk.common.key_id = Some(kid.clone());
+  let algorithm = match jwk.common.key_algorithm {
+    Some(KeyAlgorithm::EdDSA) => Algorithm::EdDSA,
+    _ => Algorithm::RS256,
+  };
+  let key = DecodingKey::from_jwk(&jwk).map_err(|e| e.to_string())?;
+  Ok(VerificationKey { kid: Some(kid), algorithm, key, jwk: Some(jwk) })
+}
+
+fn load_private_key(algorithm: &JwtAlgorithm, pem: &str) -> Result<(EncodingKey, Jwk), String> {
+  match algorithm {
+    JwtAlgorithm::Rs256 => {
+      let private = RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .map_err(|_| "not an RSA private key".to_string())?;
+      let signing_key = EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| e.to_string())?;
+      Ok((signing_key, rsa_jwk(&private.to_public_key())))
+    }
+    JwtAlgorithm::EdDsa => {
+      let private = Ed25519SigningKey::from_pkcs8_pem(pem)
+        .map_err(|_| "not an Ed25519 private key".to_string())?;
+      let signing_key = EncodingKey::from_ed_pem(pem.as_bytes()).map_err(|e| e.to_string())?;
+      Ok((signing_key, ed25519_jwk(&private.verifying_key())))
+    }
+    JwtAlgorithm::Hs256 => Err("HS256 does not use a key file".to_string()),
+  }
+}
+
+fn load_public_key(pem: &str) -> Result<Jwk, String> {
+  if let Ok(public) = RsaPublicKey::from_public_key_pem(pem).or_else(|_| RsaPublicKey::from_pkcs1_pem(pem)) {
+    return Ok(rsa_jwk(&public));
+  }
+  Ed25519VerifyingKey::from_public_key_pem(pem)
+    .map(|public| ed25519_jwk(&public))
+    .map_err(|_| "not an RSA or Ed25519 public key".to_string())
+}
+
+fn common(algorithm: KeyAlgorithm) -> CommonParameters {
+  CommonParameters {
+    public_key_use: Some(PublicKeyUse::Signature),
+    key_algorithm: Some(algorithm),
+    ..Default::default()
+  }
+}
+
+fn rsa_jwk(public: &RsaPublicKey) -> Jwk {
+  Jwk {
+    common: common(KeyAlgorithm::RS256),
+    algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+      key_type: RSAKeyType::RSA,
+      n: URL_SAFE_NO_PAD.encode(public.n().to_bytes_be()),
+      e: URL_SAFE_NO_PAD.encode(public.e().to_bytes_be()),
+    }),
+  }
+}
+
+fn ed25519_jwk(public: &Ed25519VerifyingKey) -> Jwk {
+  Jwk {
+    common: common(KeyAlgorithm::EdDSA),
+    algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+      key_type: OctetKeyPairType::OctetKeyPair,
+      curve: EllipticCurve::Ed25519,
+      x: URL_SAFE_NO_PAD.encode(public.as_bytes()),
+    }),
+  }
+}
+
+// RFC 7638 thumbprint: SHA-256 of the required members in lexicographic order
+fn thumbprint(jwk: &Jwk) -> String {
+  let canonical = match &jwk.algorithm {
+    AlgorithmParameters::RSA(rsa) => format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, rsa.e, rsa.n),
+    AlgorithmParameters::OctetKeyPair(okp) => format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, okp.x),
+    _ => String::new(),
+  };
+  URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey, EncodePublicKey};
+  use serde::Deserialize;
+  use std::path::PathBuf;
+
+  #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+  struct TestClaims {
+    sub: String,
+    exp: i64,
+  }
+
+  fn claims() -> TestClaims {
+    TestClaims { sub: "alice".to_string(), exp: Utc::now().timestamp() + 60 }
+  }
+
+  fn config(algorithm: JwtAlgorithm) -> JwtConfig {
+    JwtConfig {
+      algorithm,
+      secret: Some("secret".to_string()),
+      private_key_file: None,
+      key_id: None,
+      verification_key_files: Vec::new(),
+      issuer: "http://127.0.0.1:3000".to_string(),
+      audience: "todo-api".to_string(),
+      validate_issuer: true,
+      validate_audience: true,
+      leeway_seconds: 0,
+    }
+  }
+
+  // Writes an Ed25519 key pair and returns (private, public) PEM paths
+  fn ed25519_key_files(seed: u8) -> (PathBuf, PathBuf) {
+    let key = Ed25519SigningKey::from_bytes(&[seed; 32]);
+    let dir = std::env::temp_dir();
+    let private = dir.join(format!("jwt-test-{}-{seed}.pem", std::process::id()));
+    let public = dir.join(format!("jwt-test-{}-{seed}.pub.pem", std::process::id()));
+    fs::write(&private, key.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    fs::write(&public, key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap()).unwrap();
+    (private, public)
+  }
+
+  #[test]
+  fn round_trips_with_a_shared_secret() {
+    let keys = JwtKeys::from_config(&config(JwtAlgorithm::Hs256)).unwrap();
+    let token = keys.sign(TokenKind::Access, &claims()).unwrap();
+    assert_eq!(keys.verify::<TestClaims>(TokenKind::Access, &token).unwrap().sub, "alice");
+    assert!(keys.jwks().keys.is_empty());
+  }
+
+  #[test]
+  fn rejects_tokens_for_another_audience() {
+    let keys = JwtKeys::from_config(&config(JwtAlgorithm::Hs256)).unwrap();
+    let mut other = config(JwtAlgorithm::Hs256);
+    other.audience = "another-api".to_string();
+    let token = JwtKeys::from_config(&other).unwrap().sign(TokenKind::Access, &claims()).unwrap();
+    assert!(keys.verify::<TestClaims>(TokenKind::Access, &token).is_err());
+
+    other.validate_audience = false;
+    assert!(JwtKeys::from_config(&other).unwrap().verify::<TestClaims>(TokenKind::Access, &token).is_ok());
+  }
+
+  #[test]
+  fn accepts_retired_keys_during_rotation() {
+    let (old_private, old_public) = ed25519_key_files(1);
+    let (new_private, _) = ed25519_key_files(2);
+
+    let mut old = config(JwtAlgorithm::EdDsa);
+    old.private_key_file = Some(old_private);
+    let old_keys = JwtKeys::from_config(&old).unwrap();
+    let token = old_keys.sign(TokenKind::Access, &claims()).unwrap();
+
+    let mut new = config(JwtAlgorithm::EdDsa);
+    new.private_key_file = Some(new_private);
+    assert!(JwtKeys::from_config(&new).unwrap().verify::<TestClaims>(TokenKind::Access, &token).is_err());
+
+    new.verification_key_files = vec![(None, old_public)];
+    let new_keys = JwtKeys::from_config(&new).unwrap();
+    assert_eq!(new_keys.verify::<TestClaims>(TokenKind::Access, &token).unwrap().sub, "alice");
+
+    // Both keys are published, under the same `kid` the old key signed with
+    let jwks = new_keys.jwks();
+    assert_eq!(jwks.keys.len(), 2);
+    assert!(jwks.find(old_keys.signing_kid.as_deref().unwrap()).is_some());
+  }
+
+  #[test]
+  fn signs_and_rotates_rsa_keys() {
+    use rsa::pkcs8::EncodePublicKey as _;
+
+    // Writes one of the fixture RSA key pairs and returns (private, public) PEM paths;
+    // generating 2048-bit keys in a debug build takes too long
+    let key_files = |pem: &str, n: u8| {
+      let key = RsaPrivateKey::from_pkcs8_pem(pem).unwrap();
+      let dir = std::env::temp_dir();
+      let private = dir.join(format!("jwt-test-{}-rsa-{n}.pem", std::process::id()));
+      let public = dir.join(format!("jwt-test-{}-rsa-{n}.pub.pem", std::process::id()));
+      fs::write(&private, pem).unwrap();
+      fs::write(&public, key.to_public_key().to_public_key_pem(LineEnding::LF).unwrap()).unwrap();
+      (private, public)
+    };
+    let (old_private, old_public) = key_files(include_str!("test_keys/rsa-1.pem"), 1);
+    let (new_private, _) = key_files(include_str!("test_keys/rsa-2.pem"), 2);
+
+    let mut old = config(JwtAlgorithm::Rs256);
+    old.private_key_file = Some(old_private);
+    let old_keys = JwtKeys::from_config(&old).unwrap();
+    let token = old_keys.sign(TokenKind::Access, &claims()).unwrap();
+    assert_eq!(decode_header(&token).unwrap().alg, Algorithm::RS256);
+    assert_eq!(old_keys.verify::<TestClaims>(TokenKind::Access, &token).unwrap().sub, "alice");
+
+    let mut new = config(JwtAlgorithm::Rs256);
+    new.private_key_file = Some(new_private);
+    assert!(JwtKeys::from_config(&new).unwrap().verify::<TestClaims>(TokenKind::Access, &token).is_err());
+
+    new.verification_key_files = vec![(None, old_public)];
+    let new_keys = JwtKeys::from_config(&new).unwrap();
+    assert_eq!(new_keys.verify::<TestClaims>(TokenKind::Access, &token).unwrap().sub, "alice");
+    let fresh = new_keys.sign(TokenKind::Access, &claims()).unwrap();
+    assert!(old_keys.verify::<TestClaims>(TokenKind::Access, &fresh).is_err());
+
+    let jwks = new_keys.jwks();
+    assert_eq!(jwks.keys.len(), 2);
+    assert!(jwks.find(old_keys.signing_kid.as_deref().unwrap()).is_some());
+  }
+}
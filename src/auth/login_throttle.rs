@@ -0,0 +1,334 @@
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{DateTime, Duration, Utc};
+use mongodb::{bson::doc, options::ReturnDocument};
+use std::{
+  future::Future,
+  net::{IpAddr, SocketAddr},
+};
+use crate::{
+  auth::{record_security_event, verify_dummy_password, verify_password, ClientInfo, PasswordCheck},
+  config::AppConfig,
+  db::{email_collation, AppState},
+  models::{LoginAttempt, SecurityEvent, SecurityEventKind, User},
+  notifications::{send_security_alert, SecurityAlert},
+  utils::{ResultExt, AppError},
+};
+
+// Longest wait between two attempts on one account before it is locked outright
+const MAX_BACKOFF_SECONDS: i64 = 60;
+
+/// What failed logins are counted against
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThrottleKey {
+  Account(String),
+  Ip(IpAddr),
+}
+
+impl ThrottleKey {
+  pub fn account(email: &str) -> Self {
+    Self::Account(email.trim().to_lowercase())
+  }
+
+  fn key(&self) -> String {
+    match self {
+      Self::Account(email) => format!("account:{email}"),
+      Self::Ip(ip) => format!("ip:{ip}"),
+    }
+  }
+
+  fn limit(&self, config: &AppConfig) -> i64 {
+    match self {
+      Self::Account(_) => config.login_max_failures,
+      Self::Ip(_) => config.login_max_failures_per_ip,
+    }
+  }
+
+  /// Wait imposed after `failures` consecutive failures
+  /// Accounts back off exponentially until the limit; an IP may be shared, so it is only locked at the limit
+  fn delay(&self, failures: i64, limit: i64, lockout: Duration) -> Duration {
+    if failures >= limit {
+      return lockout;
+    }
+    match self {
+      Self::Account(_) => {
+        let seconds = 1i64.checked_shl(failures.saturating_sub(1) as u32).unwrap_or(i64::MAX);
+        Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS)).min(lockout)
+      }
+      Self::Ip(_) => Duration::zero(),
+    }
+  }
+}
+
+/// Client address, from `X-Forwarded-For` when the proxy in front is trusted to set it
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, trust_proxy_headers: bool) -> Option<IpAddr> {
+  if trust_proxy_headers {
+    let forwarded = headers.get("x-forwarded-for")
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.split(',').next())
+      .and_then(|value| value.trim().parse().ok());
+    if forwarded.is_some() {
+      return forwarded;
+    }
+  }
+  peer.map(|peer| peer.ip())
+}
+
+/// Keys a guess at the password or second factor of `email` from `ip` is counted against
+pub fn login_keys(email: &str, ip: Option<IpAddr>) -> Vec<ThrottleKey> {
+  let mut keys = vec![ThrottleKey::account(email)];
+  keys.extend(ip.map(ThrottleKey::Ip));
+  keys
+}
+
+// Lock ends to reserve for an attempt, by the count it takes: entry `n - 1` applies if it is failure `n`
+fn reservations(key: &ThrottleKey, limit: i64, lockout: Duration, now: DateTime<Utc>) -> Vec<mongodb::bson::DateTime> {
+  (1..=limit.max(1))
+    .map(|failures| mongodb::bson::DateTime::from_chrono(now + key.delay(failures, limit, lockout)))
+    .collect()
+}
+
+// A key counted by `claim`, with the lock it reserved in case the attempt fails
+struct ClaimedKey {
+  key: ThrottleKey,
+  failures: i64,
+  reserved_until: Option<DateTime<Utc>>,
+}
+
+/// Count an attempt against `key` before any credential is checked, refusing while the key is locked
+/// The count and the backoff the attempt would earn by failing are written in one update,
+/// so concurrent guesses see each other and cannot slip past the limit together
+async fn claim(app_state: &AppState, key: ThrottleKey) -> Result<Result<ClaimedKey, DateTime<Utc>>, AppError> {
+  let config = &app_state.config;
+  let now = Utc::now();
+  let limit = key.limit(config);
+  let at_now = mongodb::bson::DateTime::from_chrono(now);
+
+  // A lockout that has run out starts the count again
+  let count = doc! { "$ifNull": ["$failures", 0] };
+  let pipeline = vec![
+    doc! { "$set": { "refused": { "$gt": ["$locked_until", at_now] } } },
+    doc! { "$set": {
+      "failures": { "$cond": ["$refused", "$failures", {
+        "$add": [{ "$cond": [{ "$gte": [count.clone(), limit] }, 0, count] }, 1],
+      }] },
+      "expires_at": mongodb::bson::DateTime::from_chrono(now + config.login_lockout),
+    } },
+    doc! { "$set": { "locked_until": { "$cond": ["$refused", "$locked_until", {
+      "$arrayElemAt": [reservations(&key, limit, config.login_lockout, now), { "$subtract": ["$failures", 1] }],
+    }] } } },
+  ];
+
+  let attempt = app_state.db.collection::<LoginAttempt>("login_attempts")
+    .find_one_and_update(doc! { "key": key.key() }, pipeline)
+    .upsert(true)
+    .return_document(ReturnDocument::After)
+    .await
+    .internal_error("Failed to record login attempt")?
+    .ok_or_else(|| AppError::internal_error("Failed to record login attempt"))?;
+
+  if attempt.refused {
+    return Ok(Err(attempt.locked_until.unwrap_or(now)));
+  }
+  Ok(Ok(ClaimedKey {
+    key,
+    failures: attempt.failures,
+    reserved_until: attempt.locked_until.filter(|until| *until > now),
+  }))
+}
+
+// The attempt succeeded: forget the account's failures and hand back what was reserved on the IP
+async fn release(app_state: &AppState, claimed: &ClaimedKey) -> Result<(), AppError> {
+  if let ThrottleKey::Account(_) = claimed.key {
+    clear_login_failures(app_state, &claimed.key).await?;
+    return Ok(());
+  }
+
+  let collection = app_state.db.collection::<LoginAttempt>("login_attempts");
+  collection.update_one(doc! { "key": claimed.key.key() }, doc! { "$inc": { "failures": -1 } })
+    .await
+    .internal_error("Failed to record login attempt")?;
+  if let Some(until) = claimed.reserved_until {
+    collection
+      .update_one(
+        doc! { "key": claimed.key.key(), "locked_until": mongodb::bson::DateTime::from_chrono(until) },
+        doc! { "$unset": { "locked_until": "" } },
+      )
+      .await
+      .internal_error("Failed to record login attempt")?;
+  }
+  Ok(())
+}
+
+/// Run `check` as a throttled guess at a password or code
+/// Refused with 429 while any key is locked; an `Err` from `check` stays counted as a failure
+pub async fn throttle_attempt<T>(
+  app_state: &AppState,
+  keys: Vec<ThrottleKey>,
+  check: impl Future<Output = Result<T, AppError>>,
+) -> Result<T, AppError> {
+  let now = Utc::now();
+  let mut claimed = Vec::new();
+  let mut locked_until = None;
+  for key in keys {
+    match claim(app_state, key).await? {
+      Ok(key) => claimed.push(key),
+      Err(until) => locked_until = locked_until.max(Some(until)),
+    }
+  }
+  if let Some(until) = locked_until {
+    return Err(AppError::new(
+      StatusCode::TOO_MANY_REQUESTS,
+      format!("Too many failed attempts, try again in {} seconds", (until - now).num_seconds().max(1)),
+    ));
+  }
+
+  match check.await {
+    Ok(value) => {
+      for key in &claimed {
+        release(app_state, key).await?;
+      }
+      Ok(value)
+    }
+    Err(e) => {
+      record_lockouts(app_state, &claimed).await;
+      Err(e)
+    }
+  }
+}
+
+// Audit the keys this failure locked
+async fn record_lockouts(app_state: &AppState, claimed: &[ClaimedKey]) {
+  let email = claimed.iter().find_map(|claimed| match &claimed.key {
+    ThrottleKey::Account(email) => Some(email.clone()),
+    ThrottleKey::Ip(_) => None,
+  });
+  let ip = claimed.iter().find_map(|claimed| match claimed.key {
+    ThrottleKey::Ip(ip) => Some(ip.to_string()),
+    ThrottleKey::Account(_) => None,
+  });
+
+  for claimed in claimed {
+    if claimed.failures != claimed.key.limit(&app_state.config) {
+      continue;
+    }
+    let mut event = SecurityEvent::new(match claimed.key {
+      ThrottleKey::Account(_) => SecurityEventKind::AccountLocked,
+      ThrottleKey::Ip(_) => SecurityEventKind::IpLocked,
+    });
+    event.email = email.clone();
+    event.ip = ip.clone();
+    record_security_event(app_state, event).await;
+    if let ThrottleKey::Account(email) = &claimed.key {
+      alert_locked_account(app_state, email).await;
+    }
+  }
+}
+
+// Tell the owner, if the locked address belongs to an account
+async fn alert_locked_account(app_state: &AppState, email: &str) {
+  let user = app_state.db.collection::<User>("users")
+    .find_one(doc! { "email": email, "deleted": false })
+    .collation(email_collation())
+    .await;
+  match user {
+    Ok(Some(user)) => send_security_alert(app_state, &user, SecurityAlert::AccountLocked).await,
+    Ok(None) => {}
+    Err(e) => tracing::error!(error = %e, "failed to look up locked account"),
+  }
+}
+
+/// First factor of a login: the account with `email`, if `password` is its password
+/// Unknown addresses still cost one hash, so timing does not reveal which accounts exist
+pub async fn verify_login_password(
+  app_state: &AppState,
+  email: &str,
+  password: &str,
+  client: &ClientInfo,
+) -> Result<(User, PasswordCheck), AppError> {
+  throttle_attempt(app_state, login_keys(email, client.ip), async {
+    let user = app_state.db.collection::<User>("users")
+      .find_one(doc! { "email": email.trim(), "deleted": false })
+      .collation(email_collation())
+      .await
+      .internal_error("Failed to query database")?;
+
+    let config = &app_state.config.password_hash;
+    let check = match &user {
+      Some(user) => verify_password(config, password.to_string(), user.password.clone()).await?,
+      None => {
+        verify_dummy_password(config, password.to_string()).await;
+        PasswordCheck::Invalid
+      }
+    };
+    match user.filter(|_| check.is_valid()) {
+      Some(user) => Ok((user, check)),
+      None => Err(AppError::unauthorized("Invalid email or password")),
+    }
+  }).await
+}
+
+/// Check a password re-entered to confirm a sensitive change, throttled like a login
+pub async fn verify_current_password(
+  app_state: &AppState,
+  account: &User,
+  password: String,
+  client: &ClientInfo,
+) -> Result<(), AppError> {
+  throttle_attempt(app_state, login_keys(&account.email, client.ip), async {
+    let check = verify_password(&app_state.config.password_hash, password, account.password.clone()).await?;
+    if !check.is_valid() {
+      return Err(AppError::unauthorized("Current password is incorrect"));
+    }
+    Ok(())
+  }).await
+}
+
+/// Forget the failures counted against a key
+pub async fn clear_login_failures(app_state: &AppState, key: &ThrottleKey) -> Result<bool, AppError> {
+  let result = app_state.db.collection::<LoginAttempt>("login_attempts")
+    .delete_one(doc! { "key": key.key() })
+    .await
+    .internal_error("Failed to clear login attempts")?;
+  Ok(result.deleted_count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn accounts_back_off_exponentially_then_lock() {
+    let key = ThrottleKey::account("Alice@Example.com ");
+    let lockout = Duration::minutes(15);
+    let delays: Vec<i64> = (1..=6).map(|failures| key.delay(failures, 5, lockout).num_seconds()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 8, 900, 900]);
+    assert_eq!(key.key(), "account:alice@example.com");
+  }
+
+  #[test]
+  fn attempts_reserve_the_backoff_their_failure_would_earn() {
+    let now: DateTime<Utc> = "2025-01-01T12:00:00Z".parse().unwrap();
+    let lockout = Duration::minutes(15);
+    let seconds = |key: &ThrottleKey| -> Vec<i64> {
+      reservations(key, 5, lockout, now).into_iter().map(|until| (until.to_chrono() - now).num_seconds()).collect()
+    };
+    assert_eq!(seconds(&ThrottleKey::account("alice@example.com")), vec![1, 2, 4, 8, 900]);
+    assert_eq!(seconds(&ThrottleKey::Ip("10.0.0.1".parse().unwrap())), vec![0, 0, 0, 0, 900]);
+  }
+
+  #[test]
+  fn ips_are_only_locked_at_the_limit() {
+    let key = ThrottleKey::Ip("10.0.0.1".parse().unwrap());
+    assert_eq!(key.delay(49, 50, Duration::minutes(15)), Duration::zero());
+    assert_eq!(key.delay(50, 50, Duration::minutes(15)), Duration::minutes(15));
+  }
+
+  #[test]
+  fn forwarded_for_is_only_trusted_when_configured() {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.2".parse().unwrap());
+    let peer = Some(SocketAddr::from(([10, 0, 0, 2], 40000)));
+    assert_eq!(client_ip(&headers, peer, false), Some(IpAddr::from([10, 0, 0, 2])));
+    assert_eq!(client_ip(&headers, peer, true), Some(IpAddr::from([203, 0, 113, 7])));
+  }
+}
@@ -0,0 +1,105 @@
+use rand::{distributions::Alphanumeric, Rng};
+use totp_rs::{Algorithm, Secret, TOTP};
+use crate::utils::hash_token;
+
+const ISSUER: &str = "To-Do List";
+const DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+// Codes from the previous and next step are accepted to absorb clock drift
+const SKEW_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// New random TOTP secret, base32 encoded
+pub fn generate_secret() -> String {
+  match Secret::generate_secret().to_encoded() {
+    Secret::Encoded(secret) => secret,
+    Secret::Raw(_) => unreachable!("to_encoded always returns an encoded secret"),
+  }
+}
+
+fn totp(secret: &str, account_name: &str) -> Option<TOTP> {
+  let bytes = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+  TOTP::new(
+    Algorithm::SHA1,
+    DIGITS,
+    SKEW_STEPS as u8,
+    STEP_SECONDS,
+    bytes,
+    Some(ISSUER.to_string()),
+    account_name.replace(':', ""),
+  ).ok()
+}
+
+/// `otpauth://` URI to render as a QR code for authenticator apps
+pub fn otpauth_uri(secret: &str, email: &str) -> Option<String> {
+  totp(secret, email).map(|totp| totp.get_url())
+}
+
+/// Check `code` against `secret` at `now` (Unix seconds)
+/// Returns the matching time step, which must be newer than `last_step` so a code cannot be replayed
+pub fn verify_code(secret: &str, code: &str, now: u64, last_step: Option<i64>) -> Option<i64> {
+  let code = code.trim();
+  if code.len() != DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  let totp = totp(secret, "")?;
+  let current = (now / STEP_SECONDS) as i64;
+
+  (current - SKEW_STEPS..=current + SKEW_STEPS)
+    .filter(|step| *step >= 0 && last_step.is_none_or(|last| *step > last))
+    .find(|step| totp.generate(*step as u64 * STEP_SECONDS) == code)
+}
+
+/// Fresh recovery codes, returned in clear once and stored hashed
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+  let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+    .map(|_| {
+      let raw: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(|c| (c as char).to_ascii_lowercase())
+        .collect();
+      format!("{}-{}", &raw[..5], &raw[5..])
+    })
+    .collect();
+  let hashes = codes.iter().map(|code| hash_recovery_code(code)).collect();
+  (codes, hashes)
+}
+
+/// Recovery codes are compared without dashes, spaces or case
+pub fn hash_recovery_code(code: &str) -> String {
+  let normalized: String = code
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric())
+    .map(|c| c.to_ascii_lowercase())
+    .collect();
+  hash_token(&normalized)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // RFC 6238 appendix B secret ("12345678901234567890"), truncated to 6 digits
+  const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+  #[test]
+  fn accepts_rfc_6238_codes() {
+    assert_eq!(verify_code(RFC_SECRET, "287082", 59, None), Some(1));
+    assert_eq!(verify_code(RFC_SECRET, "081804", 1111111109, None), Some(37037036));
+    assert_eq!(verify_code(RFC_SECRET, "000000", 59, None), None);
+  }
+
+  #[test]
+  fn rejects_replayed_steps() {
+    assert_eq!(verify_code(RFC_SECRET, "287082", 59, Some(1)), None);
+    assert_eq!(verify_code(RFC_SECRET, "287082", 59, Some(0)), Some(1));
+  }
+
+  #[test]
+  fn recovery_codes_ignore_formatting() {
+    let (codes, hashes) = generate_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+    assert_eq!(hash_recovery_code(&codes[0].to_uppercase().replace('-', " ")), hashes[0]);
+  }
+}
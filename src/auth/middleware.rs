@@ -1,14 +1,22 @@
   use axum::{
     async_trait,
-    extract::{FromRequestParts, Request},
-    http::{request::Parts, StatusCode, HeaderMap},
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, HeaderMap, StatusCode},
     response::{Response, IntoResponse},
     Json,
     middleware::Next,
   };
 
+  use chrono::{DateTime, Duration, Utc};
+  use futures_util::future::BoxFuture;
+  use mongodb::bson::{doc, oid::ObjectId, Document};
   use serde::{Deserialize, Serialize};
-  use crate::auth::jwt::{Claims, verify_token};
+  use crate::{
+    db::AppState,
+    auth::{jwt::verify_token, is_api_token, store_security_event},
+    models::{ApiToken, Permission, Role, SecurityEvent, SecurityEventKind, Session},
+    utils::{hash_token, AppError},
+  };
 
   // Represents an authentication error that can be returned to the client
   #[derive(Debug, Deserialize, Serialize)]
@@ -16,9 +24,13 @@
     pub message: String,
   }
 
-  pub trait RoleGuard {
-    fn require_role(&self, require_role: i16) -> Result<(), AuthError>;
-    // fn is_role(&self, require_role: i16) -> bool;
+  pub trait PermissionGuard {
+    fn require_permission(&self, permission: Permission) -> Result<(), AppError>;
+    fn has_permission(&self, permission: Permission) -> bool;
+    /// Reject API tokens
+    fn require_login_token(&self) -> Result<(), AppError>;
+    /// Reject API tokens and impersonation on endpoints that manage the account's own credentials
+    fn require_session(&self) -> Result<(), AppError>;
   }
 
   /// Implement `IntoResponse` for `AuthError`
@@ -32,19 +44,50 @@
     }
   }
 
-  impl RoleGuard for AuthenticatedUser {
-    fn require_role(&self, require_role: i16) -> Result<(), AuthError> {
-      if self.role != require_role {
-        return Err(AuthError {
-          message: "Unauthorized".to_string(),
-        });
+  impl PermissionGuard for AuthenticatedUser {
+    fn require_permission(&self, permission: Permission) -> Result<(), AppError> {
+      if !self.has_permission(permission) {
+        return Err(AppError::forbidden(format!("Missing permission `{permission}`")));
       }
       Ok(())
     }
 
-    // fn is_role(&self, require_role: i16) -> bool {
-    //   self.role == require_role
-    // }
+    fn has_permission(&self, permission: Permission) -> bool {
+      self.permissions.contains(&permission)
+    }
+
+    fn require_login_token(&self) -> Result<(), AppError> {
+      if self.api_token_id.is_some() {
+        return Err(AppError::forbidden("This endpoint needs a login session, not an API token"));
+      }
+      Ok(())
+    }
+
+    fn require_session(&self) -> Result<(), AppError> {
+      self.require_login_token()?;
+      if self.impersonator_id.is_some() {
+        return Err(AppError::forbidden("This endpoint is not available while impersonating a user"));
+      }
+      Ok(())
+    }
+  }
+
+  /// Middleware for routes that need `permission`
+  /// Goes on the route (`route_layer`) so it runs after `auth_middleware`
+  pub fn require_permission(
+    permission: Permission,
+  ) -> impl Fn(Request, Next) -> BoxFuture<'static, Response> + Clone + Send + Sync + 'static {
+    move |request: Request, next: Next| {
+      Box::pin(async move {
+        let Some(user) = request.extensions().get::<AuthenticatedUser>() else {
+          return AuthError::new("Missing Authorization header").into_response();
+        };
+        if let Err(e) = user.require_permission(permission) {
+          return e.into_response();
+        }
+        next.run(request).await
+      })
+    }
   }
 
   // Struct that represents an authenticated user
@@ -52,84 +95,277 @@
   #[derive(Clone)]
   pub struct AuthenticatedUser {
     pub user_id: String,
+    #[allow(dead_code)]
     pub email: String,
-    pub role: i16,
+    pub role: String,
+    // Resolved from the role on every request, so role edits apply immediately
+    pub permissions: Vec<Permission>,
+    // `jti` and expiry of the presented token, needed to revoke it
+    pub token_id: String,
+    pub token_expires_at: DateTime<Utc>,
+    // Set when the request used a personal access token instead of a JWT
+    pub api_token_id: Option<ObjectId>,
+    // Login session of the access token; none for API tokens
+    pub session_id: Option<ObjectId>,
+    // Admin behind an impersonation token (its `act` claim)
+    pub impersonator_id: Option<ObjectId>,
+  }
+
+  impl AuthError {
+    fn new(message: &str) -> Self {
+      Self { message: message.to_string() }
+    }
+  }
+
+  // What a request needs to know about the token's owner, read in one query
+  #[derive(Deserialize)]
+  struct AuthState {
+    email: String,
+    #[serde(default)]
+    token_generation: i64,
+    role: String,
+    #[serde(default = "crate::models::user::default_verified")]
+    verified: bool,
+    // An unknown role grants nothing rather than failing the request
+    #[serde(default)]
+    permissions: Vec<Permission>,
+    // Only looked up when the token names a session; missing once it has ended
+    #[serde(default)]
+    session: Option<Session>,
+  }
+
+  // Load the user together with its role's permissions and, if given, its live session
+  async fn load_auth_state(
+    app_state: &AppState,
+    user_id: ObjectId,
+    session_id: Option<ObjectId>,
+  ) -> Result<AuthState, AuthError> {
+    let mut pipeline = vec![
+      doc! { "$match": { "_id": user_id, "deleted": false } },
+      doc! { "$lookup": { "from": "roles", "localField": "role", "foreignField": "name", "as": "roles" } },
+    ];
+    if let Some(session_id) = session_id {
+      pipeline.push(doc! { "$lookup": {
+        "from": "sessions",
+        "pipeline": [{ "$match": { "_id": session_id, "user_id": user_id, "revoked_at": null } }],
+        "as": "sessions",
+      } });
+    }
+    pipeline.push(doc! { "$project": {
+      "email": 1,
+      "token_generation": 1,
+      "role": 1,
+      "verified": 1,
+      "permissions": { "$ifNull": [{ "$arrayElemAt": ["$roles.permissions", 0] }, []] },
+      "session": { "$arrayElemAt": [{ "$ifNull": ["$sessions", []] }, 0] },
+    } });
+
+    let mut cursor = app_state.db.collection::<Document>("users")
+        .aggregate(pipeline)
+        .await
+        .map_err(|_| AuthError::new("Unable to verify token"))?;
+    if !cursor.advance().await.map_err(|_| AuthError::new("Unable to verify token"))? {
+      return Err(AuthError::new("Token has been revoked"));
+    }
+    let state = cursor.deserialize_current()
+        .map_err(|_| AuthError::new("Unable to verify token"))?;
+    mongodb::bson::from_document(state)
+        .map_err(|_| AuthError::new("Unable to verify token"))
+  }
+
+  /// Verify the bearer token in `headers`
+  /// Besides the signature and expiry, the token must not have been revoked and must
+  /// carry the user's current token generation
+  pub async fn authenticate(app_state: &AppState, headers: &HeaderMap) -> Result<AuthenticatedUser, AuthError> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .ok_or_else(|| AuthError::new("Missing Authorization header"))?;
+
+    let token = auth_header.strip_prefix("Bearer ")
+        .ok_or_else(|| AuthError::new("Invalid Authorization header format"))?;
+
+    if is_api_token(token) {
+      return authenticate_api_token(app_state, token).await;
+    }
+
+    let claims = verify_token(&app_state.jwt, token)
+        .map_err(|_| AuthError::new("Invalid or expired token"))?;
+
+    let revoked = app_state.revocations.is_revoked(&claims.jti)
+        .await
+        .map_err(|_| AuthError::new("Unable to verify token"))?;
+    if revoked {
+      return Err(AuthError::new("Token has been revoked"));
+    }
+
+    let user_id = ObjectId::parse_str(&claims.user_id)
+        .map_err(|_| AuthError::new("Invalid or expired token"))?;
+    let session_id = claims.sid.as_deref()
+        .map(ObjectId::parse_str)
+        .transpose()
+        .map_err(|_| AuthError::new("Invalid or expired token"))?;
+    let current = load_auth_state(app_state, user_id, session_id).await?;
+    if claims.token_generation != current.token_generation {
+      return Err(AuthError::new("Token has been revoked"));
+    }
+    if app_state.config.require_verified_email && !current.verified {
+      return Err(AuthError::new("Email address has not been verified"));
+    }
+
+    if let Some(session_id) = session_id {
+      let session = current.session
+          .ok_or_else(|| AuthError::new("Session has ended"))?;
+      touch_session(app_state, session_id, &session).await?;
+    }
+    let impersonator_id = claims.act.as_ref()
+        .map(|actor| ObjectId::parse_str(&actor.sub))
+        .transpose()
+        .map_err(|_| AuthError::new("Invalid or expired token"))?;
+
+    Ok(AuthenticatedUser {
+      token_expires_at: claims.expires_at(),
+      user_id: claims.user_id,
+      email: claims.email,
+      role: current.role,
+      permissions: current.permissions,
+      token_id: claims.jti,
+      api_token_id: None,
+      session_id,
+      impersonator_id,
+    })
+  }
+
+  // Record that the session is still in use, at most one write a minute per session
+  async fn touch_session(app_state: &AppState, session_id: ObjectId, session: &Session) -> Result<(), AuthError> {
+    let now = Utc::now();
+    if now - session.last_seen_at > Duration::minutes(1) {
+      let last_seen_at = mongodb::bson::to_bson(&now)
+          .map_err(|_| AuthError::new("Unable to verify token"))?;
+      app_state.db.collection::<Session>("sessions")
+          .update_one(doc! { "_id": session_id }, doc! { "$set": { "last_seen_at": last_seen_at } })
+          .await
+          .map_err(|_| AuthError::new("Unable to verify token"))?;
+    }
+    Ok(())
+  }
+
+  // An unknown role grants nothing rather than failing the request
+  pub(crate) async fn role_permissions(app_state: &AppState, role: &str) -> Result<Vec<Permission>, AuthError> {
+    let permissions = app_state.db.collection::<Role>("roles")
+        .find_one(doc! { "name": role })
+        .await
+        .map_err(|_| AuthError::new("Unable to verify token"))?
+        .map(|role| role.permissions)
+        .unwrap_or_default();
+    Ok(permissions)
+  }
+
+  pub(crate) async fn authenticate_api_token(app_state: &AppState, token: &str) -> Result<AuthenticatedUser, AuthError> {
+    let now = Utc::now();
+    let api_token = app_state.db.collection::<ApiToken>("api_tokens")
+        .find_one(doc! { "token_hash": hash_token(token) })
+        .await
+        .map_err(|_| AuthError::new("Unable to verify token"))?
+        .ok_or_else(|| AuthError::new("Invalid or expired token"))?;
+    if api_token.expires_at.is_some_and(|expires_at| expires_at <= now) {
+      return Err(AuthError::new("Invalid or expired token"));
+    }
+    let token_id = api_token.id
+        .ok_or_else(|| AuthError::new("Invalid or expired token"))?;
+
+    let owner = load_auth_state(app_state, api_token.user_id, None).await
+        .map_err(|_| AuthError::new("Invalid or expired token"))?;
+    if app_state.config.require_verified_email && !owner.verified {
+      return Err(AuthError::new("Email address has not been verified"));
+    }
+
+    let permissions = owner.permissions
+        .into_iter()
+        .filter(|permission| api_token.scopes.contains(permission))
+        .collect();
+
+    // At most one write a minute per token
+    if api_token.last_used_at.is_none_or(|last_used_at| now - last_used_at > Duration::minutes(1)) {
+      let last_used_at = mongodb::bson::to_bson(&now)
+          .map_err(|_| AuthError::new("Unable to verify token"))?;
+      app_state.db.collection::<ApiToken>("api_tokens")
+          .update_one(doc! { "_id": token_id }, doc! { "$set": { "last_used_at": last_used_at } })
+          .await
+          .map_err(|_| AuthError::new("Unable to verify token"))?;
+    }
+
+    Ok(AuthenticatedUser {
+      user_id: api_token.user_id.to_hex(),
+      email: owner.email,
+      role: owner.role,
+      permissions,
+      token_id: token_id.to_hex(),
+      token_expires_at: api_token.expires_at.unwrap_or(DateTime::<Utc>::MAX_UTC),
+      api_token_id: Some(token_id),
+      session_id: None,
+      impersonator_id: None,
+    })
   }
 
   // Implement `FromRequestParts` for `AuthenticatedUser`
   // This makes `AuthenticatedUser` an **extractor**, meaning Axum can automatically
   // get it from the incoming request (like `req.user` in Express)
   #[async_trait]
-  impl<S> FromRequestParts<S> for AuthenticatedUser         // Generic implementation for any state type S
-  where
-    S: Send + Sync,   // State must be thread-safe
+  impl FromRequestParts<AppState> for AuthenticatedUser
   {
     type Rejection = AuthError;       // Error type returned if extraction fails
 
     async fn from_request_parts(
       parts: &mut Parts, 
-      state: &S
+      state: &AppState
     ) -> Result<Self, Self::Rejection> {
-      let headers = &parts.headers;   // Contains request metadata (headers, URI, etc.)
-      let auth_header = headers       
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AuthError { 
-          message: "Missing Authorization header".to_string() 
-        })?;
-      
-      if !auth_header.starts_with("Bearer ") {
-        return Err(AuthError { 
-          message: "Invalid Authorization header format".to_string(),
-        });
+      // Behind `auth_middleware` the token has already been checked
+      if let Some(user) = parts.extensions.get::<AuthenticatedUser>() {
+        return Ok(user.clone());
       }
-    
-      let token = auth_header.split_at(7).1.to_string();
-      let claims = verify_token(&token).map_err(|_| AuthError { 
-        message: "Invalid token".to_string(),
-      })?;
-    
-      Ok(AuthenticatedUser { 
-        user_id: claims.user_id,
-        email: claims.email,
-        role: claims.role,
-      })
+
+      authenticate(state, &parts.headers).await
     }
   }
 
   pub async fn auth_middleware(
+    State(app_state): State<AppState>,
     mut request: Request,
     next: Next,
   ) -> Result<Response, AuthError> {
-    // Lấy Authorization header
-    let auth_header = request.headers()
-        .get("Authorization")
-        .and_then(|h| h.to_str().ok())
-        .ok_or_else(|| AuthError {
-            message: "Missing Authorization header".to_string(),
-        })?;
+    let user = authenticate(&app_state, request.headers()).await?;
+    let impersonation = user.impersonator_id.zip(ObjectId::parse_str(&user.user_id).ok());
 
-    // Kiểm tra format "Bearer <token>"
-    if !auth_header.starts_with("Bearer ") {
-        return Err(AuthError {
-            message: "Invalid Authorization header format".to_string(),
-        });
-    }
+    // Inject user info vào request extensions
+    request.extensions_mut().insert(user);
 
-    let token = auth_header.trim_start_matches("Bearer ");
+    // Every write made while impersonating is audited under both identities, before it runs,
+    // so a write that crashes or hangs is still on record
+    let Some((impersonator_id, user_id)) = impersonation.filter(|_| !request.method().is_safe()) else {
+      return Ok(next.run(request).await);
+    };
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let event_id = ObjectId::new();
+    let mut event = SecurityEvent::new(SecurityEventKind::ImpersonatedRequest);
+    event.id = Some(event_id);
+    event.user_id = Some(user_id);
+    event.actor_id = Some(impersonator_id);
+    event.detail = Some(action.clone());
+    if let Err(e) = store_security_event(&app_state, &event).await {
+      return Ok(e.into_response());
+    }
 
-    // Verify token
-    let claims = verify_token(token).map_err(|_| AuthError {
-        message: "Invalid or expired token".to_string(),
-    })?;
+    let response = next.run(request).await;
 
-    // Inject user info vào request extensions
-    request.extensions_mut().insert(AuthenticatedUser {
-        user_id: claims.user_id,
-        email: claims.email,
-        role: claims.role,
-    });
+    let detail = format!("{action} -> {}", response.status().as_u16());
+    let updated = app_state.db.collection::<SecurityEvent>("security_events")
+        .update_one(doc! { "_id": event_id }, doc! { "$set": { "detail": detail } })
+        .await;
+    if let Err(e) = updated {
+      tracing::error!(error = %e, "failed to record the status of an impersonated request");
+    }
 
-    Ok(next.run(request).await)
+    Ok(response)
   }
 
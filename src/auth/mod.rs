@@ -1,5 +1,29 @@
 pub mod jwt;
+pub mod keys;
+pub mod password;
+pub mod password_policy;
 pub mod middleware;
+pub mod basic;
+pub mod tokens;
+pub mod sessions;
+pub mod revocation;
+pub mod mfa;
+pub mod api_tokens;
+pub mod oidc;
+pub mod login_throttle;
+pub mod audit;
+pub mod rate_limit;
 
 pub use jwt::*;
-pub use middleware::*;
\ No newline at end of file
+pub use keys::*;
+pub use password::*;
+pub use password_policy::*;
+pub use middleware::*;
+pub use basic::*;
+pub use tokens::*;
+pub use sessions::*;
+pub use revocation::*;
+pub use api_tokens::*;
+pub use login_throttle::*;
+pub use audit::*;
+pub use rate_limit::*;
@@ -0,0 +1,275 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
+use crate::{
+  config::OidcConfig,
+  utils::AppError,
+};
+
+// The parts of the discovery document the login flow needs
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProviderMetadata {
+  pub issuer: String,
+  pub authorization_endpoint: String,
+  pub token_endpoint: String,
+  pub jwks_uri: String,
+}
+
+struct Provider {
+  metadata: ProviderMetadata,
+  jwks: JwkSet,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct IdTokenClaims {
+  pub iss: String,
+  pub sub: String,
+  pub email: Option<String>,
+  pub email_verified: Option<bool>,
+  pub name: Option<String>,
+  pub nonce: Option<String>,
+  // Everything else, so `role_claim` can point at any claim
+  #[serde(flatten)]
+  pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+  id_token: String,
+}
+
+/// Authorization code flow with PKCE against one OpenID Connect provider
+/// Discovery and the signing keys are fetched lazily and cached; keys are refetched when a token
+/// is signed with an unknown `kid`, which is how providers roll their keys
+pub struct OidcClient {
+  config: OidcConfig,
+  http: reqwest::Client,
+  provider: RwLock<Option<Arc<Provider>>>,
+}
+
+fn provider_error(message: impl Into<String>) -> AppError {
+  AppError::new(StatusCode::BAD_GATEWAY, message)
+}
+
+impl OidcClient {
+  pub fn new(config: OidcConfig) -> Self {
+    Self {
+      config,
+      http: reqwest::Client::new(),
+      provider: RwLock::new(None),
+    }
+  }
+
+  pub fn config(&self) -> &OidcConfig {
+    &self.config
+  }
+
+  async fn provider(&self, refresh: bool) -> Result<Arc<Provider>, AppError> {
+    if !refresh && let Some(provider) = self.provider.read().await.as_ref() {
+      return Ok(provider.clone());
+    }
+
+    let discovery_url = format!("{}/.well-known/openid-configuration", self.config.issuer_url);
+    let metadata: ProviderMetadata = self.fetch_json(&discovery_url).await?;
+    if metadata.issuer.trim_end_matches('/') != self.config.issuer_url {
+      return Err(provider_error("Identity provider reported an unexpected issuer"));
+    }
+    let jwks: JwkSet = self.fetch_json(&metadata.jwks_uri).await?;
+
+    let provider = Arc::new(Provider { metadata, jwks });
+    *self.provider.write().await = Some(provider.clone());
+    Ok(provider)
+  }
+
+  async fn fetch_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+    self.http.get(url)
+      .send()
+      .await
+      .and_then(|response| response.error_for_status())
+      .map_err(|e| {
+        tracing::error!(error = %e, url, "identity provider request failed");
+        provider_error("Identity provider is unavailable")
+      })?
+      .json()
+      .await
+      .map_err(|_| provider_error("Identity provider sent an invalid response"))
+  }
+
+  /// Where to send the browser to sign in
+  pub async fn authorization_url(&self, state: &str, nonce: &str, code_verifier: &str) -> Result<String, AppError> {
+    let provider = self.provider(false).await?;
+    let mut url = reqwest::Url::parse(&provider.metadata.authorization_endpoint)
+      .map_err(|_| provider_error("Identity provider sent an invalid authorization endpoint"))?;
+    url.query_pairs_mut()
+      .append_pair("response_type", "code")
+      .append_pair("client_id", &self.config.client_id)
+      .append_pair("redirect_uri", &self.config.redirect_url)
+      .append_pair("scope", &self.config.scopes)
+      .append_pair("state", state)
+      .append_pair("nonce", nonce)
+      .append_pair("code_challenge", &pkce_challenge(code_verifier))
+      .append_pair("code_challenge_method", "S256");
+    Ok(url.into())
+  }
+
+  /// Trade the authorization code for an ID token and validate it
+  pub async fn exchange_code(&self, code: &str, code_verifier: &str, nonce: &str) -> Result<IdTokenClaims, AppError> {
+    let provider = self.provider(false).await?;
+
+    let mut form = vec![
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", self.config.redirect_url.as_str()),
+      ("client_id", self.config.client_id.as_str()),
+      ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = &self.config.client_secret {
+      form.push(("client_secret", secret.as_str()));
+    }
+
+    let response = self.http.post(&provider.metadata.token_endpoint)
+      .form(&form)
+      .send()
+      .await
+      .map_err(|e| {
+        tracing::error!(error = %e, "token request to identity provider failed");
+        provider_error("Identity provider is unavailable")
+      })?;
+    if !response.status().is_success() {
+      tracing::warn!(status = %response.status(), "identity provider rejected the authorization code");
+      return Err(AppError::unauthorized("Authorization code was rejected"));
+    }
+    let tokens: TokenResponse = response.json()
+      .await
+      .map_err(|_| provider_error("Identity provider sent an invalid token response"))?;
+
+    self.validate_id_token(&tokens.id_token, nonce).await
+  }
+
+  async fn validate_id_token(&self, id_token: &str, nonce: &str) -> Result<IdTokenClaims, AppError> {
+    let invalid = || AppError::unauthorized("Invalid ID token");
+
+    let header = decode_header(id_token).map_err(|_| invalid())?;
+    // Only asymmetric signatures: a shared-secret algorithm here would let anyone forge tokens
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+      return Err(invalid());
+    }
+
+    let mut provider = self.provider(false).await?;
+    let key = match find_key(&provider.jwks, header.kid.as_deref()) {
+      Some(key) => key,
+      None => {
+        provider = self.provider(true).await?;
+        find_key(&provider.jwks, header.kid.as_deref()).ok_or_else(invalid)?
+      }
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&provider.metadata.issuer]);
+    validation.set_audience(&[&self.config.client_id]);
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+      .map_err(|e| {
+        tracing::warn!(error = %e, "ID token rejected");
+        invalid()
+      })?
+      .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+      return Err(invalid());
+    }
+    Ok(claims)
+  }
+
+  /// Role named by `role_mapping` for the values of `role_claim`, if any
+  pub fn map_role(&self, claims: &IdTokenClaims) -> Option<String> {
+    let claim = self.config.role_claim.as_ref()?;
+    let values: Vec<&str> = match claims.extra.get(claim)? {
+      serde_json::Value::String(value) => vec![value.as_str()],
+      serde_json::Value::Array(values) => values.iter().filter_map(|value| value.as_str()).collect(),
+      _ => return None,
+    };
+
+    self.config.role_mapping
+      .iter()
+      .find(|(value, _)| values.contains(&value.as_str()))
+      .map(|(_, role)| role.clone())
+  }
+}
+
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<DecodingKey> {
+  let jwk = match kid {
+    Some(kid) => jwks.find(kid)?,
+    // Providers with a single key sometimes omit `kid`
+    None if jwks.keys.len() == 1 => &jwks.keys[0],
+    None => return None,
+  };
+  DecodingKey::from_jwk(jwk).ok()
+}
+
+/// S256 code challenge for a PKCE verifier (RFC 7636)
+pub fn pkce_challenge(code_verifier: &str) -> String {
+  URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn client(role_mapping: &[(&str, &str)]) -> OidcClient {
+    OidcClient::new(OidcConfig {
+      issuer_url: "http://127.0.0.1:8080/default".to_string(),
+      client_id: "todo".to_string(),
+      client_secret: None,
+      redirect_url: "http://127.0.0.1:3000/api/auth/oidc/callback".to_string(),
+      scopes: "openid email".to_string(),
+      auto_create_users: true,
+      require_verified_email: true,
+      role_claim: Some("groups".to_string()),
+      role_mapping: role_mapping.iter().map(|(value, role)| (value.to_string(), role.to_string())).collect(),
+      default_role: "user".to_string(),
+    })
+  }
+
+  fn claims(extra: serde_json::Value) -> IdTokenClaims {
+    let mut value = serde_json::json!({ "iss": "http://127.0.0.1:8080/default", "sub": "alice" });
+    value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(value).unwrap()
+  }
+
+  #[test]
+  fn computes_rfc_7636_challenge() {
+    assert_eq!(
+      pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+      "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+    );
+  }
+
+  #[tokio::test]
+  async fn exchanges_a_code_with_a_provider() {
+    let idp = crate::test_support::MockIdp::start().await;
+    let client = OidcClient::new(idp.config());
+    idp.issue_id_token(serde_json::json!({ "sub": "alice", "email": "alice@example.com", "nonce": "n-1" })).await;
+
+    let url = client.authorization_url("s-1", "n-1", "verifier").await.unwrap();
+    assert!(url.starts_with(&format!("{}/authorize?", idp.server.base_url())));
+    let claims = client.exchange_code("code", "verifier", "n-1").await.unwrap();
+    assert_eq!((claims.sub.as_str(), claims.email.as_deref()), ("alice", Some("alice@example.com")));
+
+    // A token minted for another login must not be accepted
+    let error = client.exchange_code("code", "verifier", "n-2").await.unwrap_err();
+    assert_eq!(error.status, StatusCode::UNAUTHORIZED);
+  }
+
+  #[test]
+  fn maps_the_first_matching_claim_value() {
+    let client = client(&[("admins", "admin"), ("staff", "user")]);
+    assert_eq!(client.map_role(&claims(serde_json::json!({ "groups": ["staff", "admins"] }))), Some("admin".to_string()));
+    assert_eq!(client.map_role(&claims(serde_json::json!({ "groups": "staff" }))), Some("user".to_string()));
+    assert_eq!(client.map_role(&claims(serde_json::json!({ "groups": ["guests"] }))), None);
+    assert_eq!(client.map_role(&claims(serde_json::json!({}))), None);
+  }
+}
@@ -0,0 +1,126 @@
+use argon2::{
+  password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+  Algorithm, Argon2, Params, Version,
+};
+use tokio::sync::OnceCell;
+use crate::{
+  config::PasswordHashConfig,
+  utils::{ResultExt, AppError},
+};
+
+/// Outcome of checking a password against a stored hash
+#[derive(Debug, PartialEq)]
+pub enum PasswordCheck {
+  Invalid,
+  // The stored hash is bcrypt or uses weaker Argon2 parameters than configured
+  Valid { needs_rehash: bool },
+}
+
+impl PasswordCheck {
+  pub fn is_valid(&self) -> bool {
+    matches!(self, Self::Valid { .. })
+  }
+}
+
+fn argon2(config: &PasswordHashConfig) -> Result<Argon2<'static>, AppError> {
+  let params = Params::new(config.memory_kib, config.iterations, config.parallelism, None)
+      .internal_error("Invalid Argon2 parameters")?;
+  Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Hash a password with Argon2id into a PHC string
+/// Runs on the blocking pool: a hash takes tens of milliseconds of CPU by design
+pub async fn hash_password(config: &PasswordHashConfig, password: String) -> Result<String, AppError> {
+  let config = *config;
+  tokio::task::spawn_blocking(move || {
+    let salt = SaltString::generate(&mut OsRng);
+    argon2(&config)?
+      .hash_password(password.as_bytes(), &salt)
+      .map(|hash| hash.to_string())
+      .internal_error("Failed to hash password")
+  })
+  .await
+  .internal_error("Failed to hash password")?
+}
+
+/// Check a password against a PHC (Argon2) or bcrypt hash
+pub async fn verify_password(
+  config: &PasswordHashConfig,
+  password: String,
+  stored: String,
+) -> Result<PasswordCheck, AppError> {
+  let config = *config;
+  tokio::task::spawn_blocking(move || check(&config, &password, &stored))
+    .await
+    .internal_error("Failed to verify password")?
+}
+
+fn check(config: &PasswordHashConfig, password: &str, stored: &str) -> Result<PasswordCheck, AppError> {
+  if stored.starts_with("$2") {
+    let valid = bcrypt::verify(password, stored)
+        .internal_error("Failed to verify password")?;
+    return Ok(if valid { PasswordCheck::Valid { needs_rehash: true } } else { PasswordCheck::Invalid });
+  }
+
+  let Ok(hash) = PasswordHash::new(stored) else {
+    tracing::error!("stored password is not a recognised hash");
+    return Ok(PasswordCheck::Invalid);
+  };
+  // Verification takes the algorithm and parameters from the hash itself
+  if Argon2::default().verify_password(password.as_bytes(), &hash).is_err() {
+    return Ok(PasswordCheck::Invalid);
+  }
+
+  let needs_rehash = hash.algorithm != Algorithm::Argon2id.ident()
+    || Params::try_from(&hash).is_ok_and(|params| {
+      params.m_cost() < config.memory_kib
+        || params.t_cost() < config.iterations
+        || params.p_cost() < config.parallelism
+    });
+  Ok(PasswordCheck::Valid { needs_rehash })
+}
+
+/// Spend as long as a real check when there is no account to check against,
+/// so response times do not reveal which emails exist
+pub async fn verify_dummy_password(config: &PasswordHashConfig, password: String) {
+  static DUMMY_HASH: OnceCell<String> = OnceCell::const_new();
+  let Ok(hash) = DUMMY_HASH
+    .get_or_try_init(|| hash_password(config, "dummy password".to_string()))
+    .await
+  else {
+    return;
+  };
+  let _ = verify_password(config, password, hash.clone()).await;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Cheap parameters keep the tests fast
+  const WEAK: PasswordHashConfig = PasswordHashConfig { memory_kib: 1024, iterations: 1, parallelism: 1 };
+  const STRONG: PasswordHashConfig = PasswordHashConfig { memory_kib: 2048, iterations: 2, parallelism: 1 };
+
+  #[tokio::test]
+  async fn argon2_hashes_round_trip() {
+    let hash = hash_password(&WEAK, "hunter2".to_string()).await.unwrap();
+    assert!(hash.starts_with("$argon2id$"));
+    assert_eq!(check(&WEAK, "hunter2", &hash).unwrap(), PasswordCheck::Valid { needs_rehash: false });
+    assert_eq!(check(&WEAK, "hunter3", &hash).unwrap(), PasswordCheck::Invalid);
+  }
+
+  #[tokio::test]
+  async fn weaker_hashes_need_a_rehash() {
+    let hash = hash_password(&WEAK, "hunter2".to_string()).await.unwrap();
+    assert_eq!(check(&STRONG, "hunter2", &hash).unwrap(), PasswordCheck::Valid { needs_rehash: true });
+
+    let bcrypt_hash = bcrypt::hash("hunter2", 4).unwrap();
+    assert_eq!(check(&WEAK, "hunter2", &bcrypt_hash).unwrap(), PasswordCheck::Valid { needs_rehash: true });
+    assert_eq!(check(&WEAK, "hunter3", &bcrypt_hash).unwrap(), PasswordCheck::Invalid);
+  }
+
+  #[test]
+  fn unhashed_passwords_never_match() {
+    assert_eq!(check(&WEAK, "hunter2", "hunter2").unwrap(), PasswordCheck::Invalid);
+  }
+}
@@ -0,0 +1,314 @@
+use sha1::{Digest, Sha1};
+use std::io::ErrorKind;
+use crate::{
+  config::PasswordPolicyConfig,
+  utils::{AppError, FieldError},
+};
+
+// Guesses per character that matches no pattern, as in zxcvbn's brute-force fallback
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+
+// Patterns shorter than this are scored as individual characters
+const MIN_PATTERN_LENGTH: usize = 3;
+
+// Common passwords, most common first; a match costs its rank in guesses
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+// Unshifted rows of a US QWERTY keyboard, each offset half a key right of the one above
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"];
+
+// Keys a walk can start on, and the neighbours each key has on average
+const KEYBOARD_STARTS: f64 = 45.0;
+const KEYBOARD_DEGREE: f64 = 4.0;
+
+/// Check a new password against the policy
+/// `user_inputs` are the account's name, email and the like, which make a password easy to guess
+/// Every failed rule is reported as a `FieldError` on `field`
+pub async fn check_password_policy(
+  policy: &PasswordPolicyConfig,
+  field: &str,
+  password: &str,
+  user_inputs: &[&str],
+) -> Result<(), AppError> {
+  let mut errors = Vec::new();
+
+  let length = password.chars().count();
+  if length < policy.min_length {
+    errors.push(FieldError::new(field, "too_short", format!("Use at least {} characters", policy.min_length)));
+  }
+  if length > policy.max_length {
+    errors.push(FieldError::new(field, "too_long", format!("Use at most {} characters", policy.max_length)));
+  }
+
+  let tokens = personal_tokens(user_inputs);
+  let estimate = estimate_strength(password, &tokens);
+  if estimate.score < policy.min_score {
+    let message = if estimate.personal {
+      "Too easy to guess: avoid your name or email address"
+    } else {
+      "Too easy to guess: avoid common passwords, keyboard patterns, repeats and sequences, or add more words"
+    };
+    errors.push(FieldError::new(field, "too_weak", message));
+  }
+
+  if let Some(dir) = &policy.breach_corpus_dir
+    && is_breached(dir, password).await
+  {
+    errors.push(FieldError::new(field, "breached", "This password has appeared in a data breach; choose another"));
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(AppError::validation(errors))
+  }
+}
+
+#[derive(Debug, PartialEq)]
+struct Strength {
+  // 0 to 4, on zxcvbn's scale
+  score: u8,
+  // Whether part of the password came from `user_inputs`
+  personal: bool,
+}
+
+// Words an attacker targeting this account would try first
+fn personal_tokens(user_inputs: &[&str]) -> Vec<Vec<char>> {
+  user_inputs
+    .iter()
+    .flat_map(|input| input.split(|c: char| !c.is_alphanumeric()))
+    .filter(|token| token.chars().count() >= MIN_PATTERN_LENGTH)
+    .map(|token| token.to_lowercase().chars().collect())
+    .collect()
+}
+
+/// A rough zxcvbn-style estimate: the password is split into personal words, common passwords,
+/// keyboard walks, runs of one character, ascending or descending sequences and leftover
+/// characters, and the guesses needed for each part are multiplied together
+fn estimate_strength(password: &str, tokens: &[Vec<char>]) -> Strength {
+  let chars: Vec<char> = password.to_lowercase().chars().collect();
+
+  // `abcabcabc` is as hard as `abc`, times the number of repeats
+  let (base, repeats) = repeated_base(&chars);
+  let (mut guesses_log10, personal) = segment_guesses(&chars[..base], tokens);
+  guesses_log10 += (repeats as f64).log10();
+
+  let score = match guesses_log10 {
+    g if g < 3.0 => 0,
+    g if g < 6.0 => 1,
+    g if g < 8.0 => 2,
+    g if g < 10.0 => 3,
+    _ => 4,
+  };
+  Strength { score, personal }
+}
+
+fn repeated_base(chars: &[char]) -> (usize, usize) {
+  for base in 1..=chars.len() / 2 {
+    if chars.len().is_multiple_of(base) && chars.chunks(base).all(|chunk| chunk == &chars[..base]) {
+      return (base, chars.len() / base);
+    }
+  }
+  (chars.len(), 1)
+}
+
+// log10 of the guesses for `chars`, and whether a personal token was found
+fn segment_guesses(chars: &[char], tokens: &[Vec<char>]) -> (f64, bool) {
+  let mut total = 0.0;
+  let mut personal = false;
+  let mut i = 0;
+
+  while i < chars.len() {
+    let rest = &chars[i..];
+    let token = tokens.iter()
+      .filter(|token| rest.starts_with(token))
+      .map(Vec::len)
+      .max();
+
+    let (length, guesses) = if let Some(length) = token {
+      personal = true;
+      (length, tokens.len() as f64 * 2.0)
+    } else {
+      let run = rest.iter().take_while(|&&c| c == rest[0]).count();
+      let sequence = sequence_length(rest);
+      let (walk, turns) = keyboard_walk(rest);
+      [
+        common_password(rest),
+        (walk >= MIN_PATTERN_LENGTH)
+          .then(|| (walk, KEYBOARD_STARTS * KEYBOARD_DEGREE.powi(turns as i32 + 1) * walk as f64)),
+        (run >= MIN_PATTERN_LENGTH).then_some((run, BRUTEFORCE_CARDINALITY * run as f64)),
+        (sequence >= MIN_PATTERN_LENGTH).then_some((sequence, BRUTEFORCE_CARDINALITY * sequence as f64 * 2.0)),
+      ]
+        .into_iter()
+        .flatten()
+        // The longest pattern wins, and the cheaper one on a tie
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)))
+        .unwrap_or((1, BRUTEFORCE_CARDINALITY))
+    };
+
+    total += guesses.log10();
+    i += length;
+  }
+
+  (total, personal)
+}
+
+// Longest common password at the start of `chars`, with its rank as the guesses
+// Common l33t substitutions such as `p@ssw0rd` double the guesses
+fn common_password(chars: &[char]) -> Option<(usize, f64)> {
+  let unleeted: Vec<char> = chars.iter().map(|&c| unleet(c)).collect();
+  COMMON_PASSWORDS.lines()
+    .enumerate()
+    .filter_map(|(rank, word)| {
+      let word: Vec<char> = word.chars().collect();
+      let guesses = (rank + 1) as f64;
+      if word.len() < MIN_PATTERN_LENGTH {
+        None
+      } else if chars.starts_with(&word) {
+        Some((word.len(), guesses))
+      } else if unleeted.starts_with(&word) {
+        Some((word.len(), guesses * 2.0))
+      } else {
+        None
+      }
+    })
+    .max_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)))
+}
+
+fn unleet(c: char) -> char {
+  match c {
+    '0' => 'o',
+    '1' | '!' => 'i',
+    '3' => 'e',
+    '4' | '@' => 'a',
+    '5' | '$' => 's',
+    '7' => 't',
+    _ => c,
+  }
+}
+
+// Length of the walk over adjacent keys at the start of `chars`, such as `qwerty` or `1qaz`,
+// and how often it changes direction
+fn keyboard_walk(chars: &[char]) -> (usize, usize) {
+  let mut length = 1;
+  let mut turns = 0;
+  let mut direction = None;
+  for pair in chars.windows(2) {
+    let (Some(from), Some(to)) = (key_position(pair[0]), key_position(pair[1])) else { break };
+    let step = (to.0 - from.0, to.1 - from.1);
+    // Neighbours on the same row, and the two keys touching it on each adjacent row
+    if !matches!(step, (0, -1) | (0, 1) | (-1, 0) | (-1, 1) | (1, -1) | (1, 0)) {
+      break;
+    }
+    if direction.is_some_and(|direction| direction != step) {
+      turns += 1;
+    }
+    direction = Some(step);
+    length += 1;
+  }
+  (length, turns)
+}
+
+fn key_position(c: char) -> Option<(i32, i32)> {
+  KEYBOARD_ROWS.iter()
+    .enumerate()
+    .find_map(|(row, keys)| keys.chars().position(|key| key == c).map(|column| (row as i32, column as i32)))
+}
+
+// Length of the `abcd` / `4321` style sequence at the start of `chars`
+fn sequence_length(chars: &[char]) -> usize {
+  if chars.len() < 2 {
+    return chars.len();
+  }
+  let step = chars[1] as i64 - chars[0] as i64;
+  if step.abs() != 1 {
+    return 1;
+  }
+  1 + chars.windows(2)
+    .take_while(|pair| pair[1] as i64 - pair[0] as i64 == step)
+    .count()
+}
+
+// Looks the password's SHA-1 up in the range file for its 5-character prefix
+// Only the prefix decides which file is read, as with the k-anonymity range API
+async fn is_breached(dir: &std::path::Path, password: &str) -> bool {
+  let digest = hex::encode_upper(Sha1::digest(password.as_bytes()));
+  let (prefix, suffix) = digest.split_at(5);
+
+  let contents = match tokio::fs::read_to_string(dir.join(format!("{prefix}.txt"))).await {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == ErrorKind::NotFound => return false,
+    Err(e) => {
+      tracing::error!(error = %e, prefix, "failed to read breached-password corpus");
+      return false;
+    }
+  };
+
+  contents.lines().any(|line| {
+    line.split_once(':').is_some_and(|(candidate, count)| {
+      // Padding entries have a count of 0
+      candidate.trim().eq_ignore_ascii_case(suffix) && count.trim().parse::<u64>().is_ok_and(|count| count > 0)
+    })
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn policy(breach_corpus_dir: Option<std::path::PathBuf>) -> PasswordPolicyConfig {
+    PasswordPolicyConfig { min_length: 10, max_length: 64, min_score: 2, breach_corpus_dir }
+  }
+
+  fn codes(result: Result<(), AppError>) -> Vec<String> {
+    result.err().map(|e| e.errors.into_iter().map(|e| e.code).collect()).unwrap_or_default()
+  }
+
+  #[test]
+  fn patterns_score_low() {
+    let tokens = personal_tokens(&["alice.smith@example.com", "Alice Smith"]);
+    assert_eq!(estimate_strength("aaaaaaaaaaaa", &[]).score, 0);
+    assert_eq!(estimate_strength("9876543210", &[]).score, 0);
+    assert_eq!(estimate_strength("abcabcabcabc", &[]).score, 0);
+    assert_eq!(estimate_strength("alicesmith1", &tokens), Strength { score: 1, personal: true });
+    assert_eq!(estimate_strength("correct horse battery staple", &tokens).score, 4);
+  }
+
+  #[test]
+  fn common_passwords_and_keyboard_walks_score_low() {
+    for password in ["qwertyuiop", "iloveyou12", "password1234", "P@ssw0rd2024", "1qaz2wsx3edc", "zxcvbnm,./"] {
+      let strength = estimate_strength(password, &[]);
+      assert!(strength.score < 2, "{password} scored {}", strength.score);
+    }
+    assert_eq!(keyboard_walk(&"1qaz2".chars().collect::<Vec<_>>()), (4, 0));
+    assert_eq!(keyboard_walk(&"asdfr".chars().collect::<Vec<_>>()), (5, 1));
+  }
+
+  #[tokio::test]
+  async fn rejects_common_passwords() {
+    for password in ["qwertyuiop", "iloveyou12", "password1234"] {
+      assert_eq!(codes(check_password_policy(&policy(None), "password", password, &[]).await), vec!["too_weak"]);
+    }
+  }
+
+  #[tokio::test]
+  async fn reports_every_failed_rule() {
+    assert_eq!(codes(check_password_policy(&policy(None), "password", "aaaa", &[]).await), vec!["too_short", "too_weak"]);
+    assert!(check_password_policy(&policy(None), "password", "plum-orbit-canvas-42", &[]).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn finds_passwords_in_the_breach_corpus() {
+    let dir = std::env::temp_dir().join(format!("breach-corpus-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // SHA-1 of `plum-orbit-canvas-42`, next to a padding entry
+    let digest = hex::encode_upper(Sha1::digest(b"plum-orbit-canvas-42"));
+    let (prefix, suffix) = digest.split_at(5);
+    std::fs::write(dir.join(format!("{prefix}.txt")), format!("0000000000000000000000000000000000A:0\n{suffix}:3\n")).unwrap();
+
+    let result = check_password_policy(&policy(Some(dir.clone())), "new_password", "plum-orbit-canvas-42", &[]).await;
+    let errors = result.unwrap_err().errors;
+    assert_eq!(errors, vec![FieldError::new("new_password", "breached", "This password has appeared in a data breach; choose another")]);
+    assert!(check_password_policy(&policy(Some(dir)), "password", "plum-orbit-canvas-43", &[]).await.is_ok());
+  }
+}
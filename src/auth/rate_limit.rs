@@ -0,0 +1,45 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use mongodb::{bson::doc, options::ReturnDocument};
+use crate::{
+  db::AppState,
+  models::RateLimit,
+  utils::{ResultExt, AppError},
+};
+
+/// Count a request against `key`, refusing it with 429 once `limit` were made in `window`
+/// The window starts at the first request, so a client cannot keep a key busy forever
+pub async fn enforce_rate_limit(
+  app_state: &AppState,
+  key: &str,
+  limit: i64,
+  window: Duration,
+) -> Result<(), AppError> {
+  let now = Utc::now();
+  let at_now = mongodb::bson::DateTime::from_chrono(now);
+
+  let pipeline = vec![
+    doc! { "$set": { "expired": { "$not": [{ "$gt": ["$expires_at", at_now] }] } } },
+    doc! { "$set": {
+      "count": { "$cond": ["$expired", 1_i64, { "$add": ["$count", 1_i64] }] },
+      "expires_at": { "$cond": ["$expired", mongodb::bson::DateTime::from_chrono(now + window), "$expires_at"] },
+    } },
+    doc! { "$unset": "expired" },
+  ];
+
+  let counted = app_state.db.collection::<RateLimit>("rate_limits")
+    .find_one_and_update(doc! { "key": key }, pipeline)
+    .upsert(true)
+    .return_document(ReturnDocument::After)
+    .await
+    .internal_error("Failed to record request")?
+    .ok_or_else(|| AppError::internal_error("Failed to record request"))?;
+
+  if counted.count > limit {
+    return Err(AppError::new(
+      StatusCode::TOO_MANY_REQUESTS,
+      format!("Too many requests, try again in {} seconds", (counted.expires_at - now).num_seconds().max(1)),
+    ));
+  }
+  Ok(())
+}
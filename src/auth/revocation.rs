@@ -0,0 +1,97 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::{bson::doc, Collection, Database};
+use std::{collections::HashMap, sync::Mutex};
+use crate::{
+  models::RevokedToken,
+  utils::{ResultExt, AppError},
+};
+
+/// Remembers revoked access tokens (by `jti`) until they expire
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+  async fn revoke(&self, jti: &str, user_id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError>;
+  async fn is_revoked(&self, jti: &str) -> Result<bool, AppError>;
+}
+
+pub struct MongoRevocationStore {
+  collection: Collection<RevokedToken>,
+}
+
+impl MongoRevocationStore {
+  pub fn new(db: &Database) -> Self {
+    Self { collection: db.collection::<RevokedToken>("revoked_tokens") }
+  }
+}
+
+#[async_trait]
+impl RevocationStore for MongoRevocationStore {
+  async fn revoke(&self, jti: &str, user_id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+    let record = RevokedToken {
+      jti: jti.to_string(),
+      user_id: user_id.to_string(),
+      expires_at,
+    };
+    self.collection.replace_one(doc! { "_id": jti }, &record)
+      .upsert(true)
+      .await
+      .internal_error("Failed to revoke token")?;
+    Ok(())
+  }
+
+  async fn is_revoked(&self, jti: &str) -> Result<bool, AppError> {
+    let count = self.collection.count_documents(doc! { "_id": jti })
+      .await
+      .internal_error("Failed to query database")?;
+    Ok(count > 0)
+  }
+}
+
+#[derive(Default)]
+pub struct MemoryRevocationStore {
+  revoked: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+#[async_trait]
+impl RevocationStore for MemoryRevocationStore {
+  async fn revoke(&self, jti: &str, _user_id: &str, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+    let now = Utc::now();
+    let mut revoked = self.revoked.lock()
+      .map_err(|_| AppError::internal_error("Revocation store is poisoned"))?;
+    // Entries are useless once the token has expired, so prune them as we go
+    revoked.retain(|_, expires_at| *expires_at > now);
+    revoked.insert(jti.to_string(), expires_at);
+    Ok(())
+  }
+
+  async fn is_revoked(&self, jti: &str) -> Result<bool, AppError> {
+    let revoked = self.revoked.lock()
+      .map_err(|_| AppError::internal_error("Revocation store is poisoned"))?;
+    Ok(revoked.get(jti).is_some_and(|expires_at| *expires_at > Utc::now()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration;
+
+  #[tokio::test]
+  async fn remembers_revoked_tokens() {
+    let store = MemoryRevocationStore::default();
+    store.revoke("a", "user", Utc::now() + Duration::hours(1)).await.unwrap();
+
+    assert!(store.is_revoked("a").await.unwrap());
+    assert!(!store.is_revoked("b").await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn forgets_expired_tokens() {
+    let store = MemoryRevocationStore::default();
+    store.revoke("old", "user", Utc::now() - Duration::seconds(1)).await.unwrap();
+    assert!(!store.is_revoked("old").await.unwrap());
+
+    store.revoke("new", "user", Utc::now() + Duration::hours(1)).await.unwrap();
+    assert_eq!(store.revoked.lock().unwrap().len(), 1);
+  }
+}
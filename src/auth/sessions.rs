@@ -0,0 +1,153 @@
+use axum::{
+  async_trait,
+  extract::{ConnectInfo, FromRequestParts},
+  http::{header::USER_AGENT, request::Parts},
+};
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId};
+use std::{convert::Infallible, net::{IpAddr, SocketAddr}};
+use crate::{
+  db::AppState,
+  auth::{client_ip, create_token, issue_tokens, Actor, Claims},
+  models::{RefreshToken, Session, User},
+  dtos::LoginResponse,
+  utils::{ResultExt, AppError},
+};
+
+// Longest user agent kept on a session
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// Where a request comes from, as recorded on sessions
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+  pub ip: Option<IpAddr>,
+  pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientInfo {
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+    let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(peer)| *peer);
+    let user_agent = parts.headers.get(USER_AGENT)
+      .and_then(|value| value.to_str().ok())
+      .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+    Ok(Self {
+      ip: client_ip(&parts.headers, peer, state.config.trust_proxy_headers),
+      user_agent,
+    })
+  }
+}
+
+/// Open a session for a fresh login and issue its first tokens
+pub async fn start_session(
+  app_state: &AppState,
+  user: &User,
+  client: &ClientInfo,
+) -> Result<LoginResponse, AppError> {
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+
+  let now = Utc::now();
+  let session = Session {
+    id: None,
+    user_id,
+    user_agent: client.user_agent.clone(),
+    ip: client.ip.map(|ip| ip.to_string()),
+    created_at: now,
+    last_seen_at: now,
+    expires_at: now + app_state.config.refresh_token_ttl,
+    revoked_at: None,
+    impersonator_id: None,
+  };
+  let result = app_state.db.collection::<Session>("sessions")
+    .insert_one(&session)
+    .await
+    .internal_error("Failed to store session")?;
+  let session_id = result.inserted_id.as_object_id()
+      .ok_or_else(|| AppError::internal_error("Failed to get inserted session ID"))?;
+
+  issue_tokens(app_state, user, session_id).await
+}
+
+/// Open a short session in which `impersonator` acts as `user`
+/// Only an access token is issued: when it expires the admin has to start again
+pub async fn start_impersonation(
+  app_state: &AppState,
+  user: &User,
+  impersonator: Actor,
+  client: &ClientInfo,
+) -> Result<String, AppError> {
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  let impersonator_id = ObjectId::parse_str(&impersonator.sub)
+      .bad_request("Invalid user ID")?;
+
+  let now = Utc::now();
+  let ttl = app_state.config.impersonation_ttl;
+  let session = Session {
+    id: None,
+    user_id,
+    user_agent: client.user_agent.clone(),
+    ip: client.ip.map(|ip| ip.to_string()),
+    created_at: now,
+    last_seen_at: now,
+    expires_at: now + ttl,
+    revoked_at: None,
+    impersonator_id: Some(impersonator_id),
+  };
+  let result = app_state.db.collection::<Session>("sessions")
+    .insert_one(&session)
+    .await
+    .internal_error("Failed to store session")?;
+  let session_id = result.inserted_id.as_object_id()
+      .ok_or_else(|| AppError::internal_error("Failed to get inserted session ID"))?;
+
+  let claims = Claims::new(user_id.to_hex(), user.email.clone(), user.role.clone(), user.token_generation, session_id.to_hex(), ttl)
+    .with_actor(impersonator);
+  create_token(&app_state.jwt, claims)
+    .internal_error("Failed to create token")
+}
+
+/// End a session: its access tokens are refused from now on and its refresh tokens are revoked
+pub async fn end_session(app_state: &AppState, session_id: ObjectId) -> Result<(), AppError> {
+  app_state.db.collection::<Session>("sessions")
+    .update_one(
+      doc! { "_id": session_id, "revoked_at": null },
+      doc! { "$set": { "revoked_at": mongodb::bson::to_bson(&Utc::now())
+        .internal_error("Failed to encode timestamp")? } },
+    )
+    .await
+    .internal_error("Failed to end session")?;
+
+  app_state.db.collection::<RefreshToken>("refresh_tokens")
+    .update_many(doc! { "family_id": session_id.to_hex() }, doc! { "$set": { "revoked": true } })
+    .await
+    .internal_error("Failed to revoke refresh tokens")?;
+
+  Ok(())
+}
+
+/// End every session of `user_id` except `keep`, e.g. after a password change made from `keep`
+pub async fn end_other_sessions(app_state: &AppState, user_id: ObjectId, keep: ObjectId) -> Result<(), AppError> {
+  app_state.db.collection::<Session>("sessions")
+    .update_many(
+      doc! { "user_id": user_id, "_id": { "$ne": keep }, "revoked_at": null },
+      doc! { "$set": { "revoked_at": mongodb::bson::to_bson(&Utc::now())
+        .internal_error("Failed to encode timestamp")? } },
+    )
+    .await
+    .internal_error("Failed to end sessions")?;
+
+  app_state.db.collection::<RefreshToken>("refresh_tokens")
+    .update_many(
+      doc! { "user_id": user_id, "family_id": { "$ne": keep.to_hex() } },
+      doc! { "$set": { "revoked": true } },
+    )
+    .await
+    .internal_error("Failed to revoke refresh tokens")?;
+
+  Ok(())
+}
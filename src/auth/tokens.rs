@@ -0,0 +1,49 @@
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+use crate::{
+  db::AppState,
+  auth::{create_token, Claims},
+  models::{RefreshToken, User},
+  dtos::LoginResponse,
+  utils::{generate_token, hash_token, ResultExt, AppError},
+};
+
+/// Issue an access token and a refresh token for `user` within a session
+/// A fresh login goes through `start_session`; refreshing a token stays in its session
+pub async fn issue_tokens(
+  app_state: &AppState,
+  user: &User,
+  session_id: ObjectId,
+) -> Result<LoginResponse, AppError> {
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+
+  let ttl = app_state.config.access_token_ttl;
+  let claims = Claims::new(user_id.to_hex(), user.email.clone(), user.role.clone(), user.token_generation, session_id.to_hex(), ttl);
+  let token = create_token(&app_state.jwt, claims)
+      .internal_error("Failed to create token")?;
+
+  let refresh_token = generate_token();
+  let now = Utc::now();
+  let record = RefreshToken {
+    id: None,
+    user_id,
+    family_id: session_id.to_hex(),
+    token_hash: hash_token(&refresh_token),
+    expires_at: now + app_state.config.refresh_token_ttl,
+    used_at: None,
+    revoked: false,
+    created_at: now,
+  };
+
+  app_state.db.collection::<RefreshToken>("refresh_tokens")
+    .insert_one(&record)
+    .await
+    .internal_error("Failed to store refresh token")?;
+
+  Ok(LoginResponse {
+    token,
+    refresh_token,
+    expires_in: ttl.num_seconds(),
+  })
+}
@@ -0,0 +1,167 @@
+use clap::{Args, Parser, Subcommand};
+use futures_util::TryStreamExt;
+use mongodb::bson::doc;
+use std::io::BufRead;
+use crate::{
+  accounts::{create_account, set_password, set_role, NewAccount},
+  db::AppState,
+  models::{User, ADMIN_ROLE, USER_ROLE},
+  utils::{ResultExt, AppError},
+};
+
+/// To-Do List API server; without a command it serves HTTP
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+  /// Run the HTTP server (the default)
+  Serve,
+  /// Manage user accounts directly in the database
+  #[command(subcommand)]
+  User(UserCommand),
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+  /// Create a verified account
+  Create {
+    #[arg(long)]
+    email: String,
+    #[arg(long)]
+    name: String,
+    /// Role to give the account
+    #[arg(long, default_value = USER_ROLE, conflicts_with = "admin")]
+    role: String,
+    /// Shorthand for `--role admin`
+    #[arg(long)]
+    admin: bool,
+    #[command(flatten)]
+    password: PasswordSource,
+  },
+  /// Set a new password and end every session of the account
+  ResetPassword {
+    #[arg(long)]
+    email: String,
+    #[command(flatten)]
+    password: PasswordSource,
+  },
+  /// Move an account to another role
+  SetRole {
+    #[arg(long)]
+    email: String,
+    #[arg(long)]
+    role: String,
+  },
+  /// Print every account that is not deleted
+  List,
+}
+
+#[derive(Args)]
+pub struct PasswordSource {
+  /// Read the password from the first line of stdin instead of prompting
+  #[arg(long)]
+  password_stdin: bool,
+}
+
+impl PasswordSource {
+  fn read(&self) -> Result<String, AppError> {
+    if self.password_stdin {
+      let mut line = String::new();
+      std::io::stdin().lock().read_line(&mut line)
+          .bad_request("Failed to read password from stdin")?;
+      return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let password = rpassword::prompt_password("Password: ")
+        .bad_request("Failed to read password")?;
+    let confirmation = rpassword::prompt_password("Repeat password: ")
+        .bad_request("Failed to read password")?;
+    if password != confirmation {
+      return Err(AppError::bad_request("Passwords do not match"));
+    }
+    Ok(password)
+  }
+}
+
+// Field errors from the password policy, one per line
+fn describe(error: &AppError) -> String {
+  let mut message = error.message.clone();
+  for field in &error.errors {
+    message.push_str(&format!("\n  {}: {}", field.field, field.message));
+  }
+  message
+}
+
+pub async fn run_user_command(app_state: &AppState, command: UserCommand) -> Result<(), String> {
+  run(app_state, command).await.map_err(|e| describe(&e))
+}
+
+async fn run(app_state: &AppState, command: UserCommand) -> Result<(), AppError> {
+  match command {
+    UserCommand::Create { email, name, role, admin, password } => {
+      let account = NewAccount {
+        full_name: name,
+        email,
+        password: password.read()?,
+        role: if admin { ADMIN_ROLE.to_string() } else { role },
+        verified: true,
+      };
+      let user = create_account(app_state, account, None).await?;
+      let id = user.id.map(|id| id.to_hex()).unwrap_or_default();
+      tracing::info!(user = %user.email, role = %user.role, "user created from the command line");
+      println!("Created {} ({}) with role `{}`", user.email, id, user.role);
+    }
+    UserCommand::ResetPassword { email, password } => {
+      let user = set_password(app_state, &email, password.read()?).await?;
+      tracing::info!(user = %user.email, "password reset from the command line");
+      println!("Password of {} has been reset; all sessions were logged out", user.email);
+    }
+    UserCommand::SetRole { email, role } => {
+      let user = set_role(app_state, &email, &role).await?;
+      tracing::info!(user = %user.email, role = %user.role, "role changed from the command line");
+      println!("{} now has role `{}`", user.email, user.role);
+    }
+    UserCommand::List => {
+      let users: Vec<User> = app_state.db.collection::<User>("users")
+        .find(doc! { "deleted": false })
+        .sort(doc! { "email": 1 })
+        .await
+        .internal_error("Failed to query database")?
+        .try_collect()
+        .await
+        .internal_error("Failed to query database")?;
+
+      println!("{:<24}  {:<32}  {:<12}  {:<8}  NAME", "ID", "EMAIL", "ROLE", "VERIFIED");
+      for user in users {
+        let id = user.id.map(|id| id.to_hex()).unwrap_or_default();
+        println!("{id:<24}  {:<32}  {:<12}  {:<8}  {}", user.email, user.role, user.verified, user.full_name);
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn admin_flag_conflicts_with_role() {
+    let cli = Cli::try_parse_from(["to_do_list", "user", "create", "--email", "a@b.c", "--name", "A", "--admin"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::User(UserCommand::Create { admin: true, .. }))));
+
+    let both = ["to_do_list", "user", "create", "--email", "a@b.c", "--name", "A", "--admin", "--role", "user"];
+    assert!(Cli::try_parse_from(both).is_err());
+  }
+
+  #[test]
+  fn no_command_means_serve() {
+    let cli = Cli::try_parse_from(["to_do_list"]).unwrap();
+    assert!(cli.command.is_none());
+  }
+}
@@ -0,0 +1,395 @@
+use std::{env, path::PathBuf, str::FromStr};
+use chrono::Duration;
+
+// Settings read once from the environment at startup
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+  // Base URL used to build links handed out to clients (calendar feeds, emails, ...)
+  pub public_base_url: String,
+
+  // How access tokens and other signed tokens are signed and checked
+  pub jwt: JwtConfig,
+
+  // Cost of new password hashes; weaker stored hashes are upgraded at the next login
+  pub password_hash: PasswordHashConfig,
+
+  // Rules new passwords must meet
+  pub password_policy: PasswordPolicyConfig,
+
+  // Lifetime of the JWT returned by `login`
+  pub access_token_ttl: Duration,
+
+  // Lifetime of a refresh token; each rotation issues a fresh one
+  pub refresh_token_ttl: Duration,
+
+  // Where revoked access tokens are remembered until they expire
+  pub revocation_store: RevocationBackend,
+
+  // How outgoing email is delivered
+  pub mailer: MailerBackend,
+
+  // `From` header of outgoing email
+  pub mail_from: String,
+
+  // How long a password reset link stays valid
+  pub password_reset_ttl: Duration,
+
+  // Reset emails one address, or one client IP, may ask for per window
+  pub password_reset_max_per_email: i64,
+  pub password_reset_max_per_ip: i64,
+  pub password_reset_rate_window: Duration,
+
+  // Refuse `login` until the account's email address is verified
+  pub require_verified_email: bool,
+
+  // How long an email verification link stays valid
+  pub email_verification_ttl: Duration,
+
+  // Minimum time between two verification emails to the same account
+  pub verification_resend_interval: Duration,
+
+  // How long the MFA challenge returned by `login` can be exchanged
+  pub mfa_challenge_ttl: Duration,
+
+  // Codes that may be tried against one challenge before the user has to sign in again
+  pub mfa_challenge_max_attempts: i64,
+
+  // Who may create an account through `POST /api/auth/register`
+  pub signup_policy: SignupPolicy,
+
+  // Role given to self-registered users; never chosen by the caller
+  pub signup_default_role: String,
+
+  // How long an invitation link stays valid
+  pub invitation_ttl: Duration,
+
+  // Lifetime of the access token an admin gets to impersonate a user
+  pub impersonation_ttl: Duration,
+
+  // How long a deleted account keeps its personal data before it is anonymised
+  pub account_deletion_grace: Duration,
+
+  // Failed logins for one account before it is locked
+  pub login_max_failures: i64,
+
+  // Failed logins from one IP address before it is locked out
+  pub login_max_failures_per_ip: i64,
+
+  // How long a lockout lasts, and how long failures are remembered
+  pub login_lockout: Duration,
+
+  // Take the client IP from `X-Forwarded-For`; only safe behind a proxy that sets it
+  pub trust_proxy_headers: bool,
+
+  // Single sign-on through an OpenID Connect provider; off unless `OIDC_ISSUER_URL` is set
+  pub oidc: Option<OidcConfig>,
+
+  // Admin account created at startup on a database without users; off unless `SEED_ADMIN_EMAIL` is set
+  pub seed_admin: Option<SeedAdminConfig>,
+}
+
+// Argon2id parameters; the defaults are the OWASP minimum (19 MiB, 2 passes, 1 lane)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PasswordHashConfig {
+  pub memory_kib: u32,
+  pub iterations: u32,
+  pub parallelism: u32,
+}
+
+impl PasswordHashConfig {
+  fn from_env() -> Self {
+    Self {
+      memory_kib: env_or("ARGON2_MEMORY_KIB", 19 * 1024),
+      iterations: env_or("ARGON2_ITERATIONS", 2),
+      parallelism: env_or("ARGON2_PARALLELISM", 1),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct PasswordPolicyConfig {
+  pub min_length: usize,
+  // Bounds the work a single login can cause
+  pub max_length: usize,
+  // Minimum strength score from 0 (trivial) to 4 (very strong)
+  pub min_score: u8,
+  // Directory of breached-password range files: `<first 5 hex of SHA-1>.txt` holding
+  // `<remaining 35 hex>:<count>` lines, the layout of the Have I Been Pwned range API
+  pub breach_corpus_dir: Option<PathBuf>,
+}
+
+impl PasswordPolicyConfig {
+  fn from_env() -> Self {
+    Self {
+      min_length: env_or("PASSWORD_MIN_LENGTH", 10),
+      max_length: env_or("PASSWORD_MAX_LENGTH", 256),
+      min_score: env_or("PASSWORD_MIN_SCORE", 2),
+      breach_corpus_dir: env::var("PASSWORD_BREACH_CORPUS_DIR").ok().map(PathBuf::from),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JwtAlgorithm {
+  // Shared secret from `JWT_SECRET`; only this service can verify tokens
+  Hs256,
+  Rs256,
+  EdDsa,
+}
+
+impl FromStr for JwtAlgorithm {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "HS256" => Ok(Self::Hs256),
+      "RS256" => Ok(Self::Rs256),
+      "EdDSA" => Ok(Self::EdDsa),
+      other => Err(format!("unknown JWT algorithm `{other}`, expected HS256, RS256 or EdDSA")),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct JwtConfig {
+  pub algorithm: JwtAlgorithm,
+  // Required for HS256
+  pub secret: Option<String>,
+  // PEM private key (PKCS#8, or PKCS#1 for RSA) used to sign new tokens with RS256 or EdDSA
+  pub private_key_file: Option<PathBuf>,
+  // `kid` of the signing key; defaults to its RFC 7638 thumbprint
+  pub key_id: Option<String>,
+  // PEM public keys of retired signing keys still accepted during a rotation, as `path` or `kid=path`
+  pub verification_key_files: Vec<(Option<String>, PathBuf)>,
+  pub issuer: String,
+  pub audience: String,
+  // Reject tokens whose `iss` / `aud` do not match, e.g. tokens issued before these claims existed
+  pub validate_issuer: bool,
+  pub validate_audience: bool,
+  // Allowed clock skew when checking `exp` and `nbf`
+  pub leeway_seconds: u64,
+}
+
+impl JwtConfig {
+  fn from_env(public_base_url: &str) -> Self {
+    let verification_key_files = env::var("JWT_VERIFICATION_KEY_FILES")
+      .unwrap_or_default()
+      .split(',')
+      .map(str::trim)
+      .filter(|entry| !entry.is_empty())
+      .map(|entry| match entry.split_once('=') {
+        Some((kid, path)) => (Some(kid.to_string()), PathBuf::from(path)),
+        None => (None, PathBuf::from(entry)),
+      })
+      .collect();
+
+    Self {
+      algorithm: env::var("JWT_ALGORITHM")
+        .map(|value| value.parse().expect("Invalid JWT_ALGORITHM"))
+        .unwrap_or(JwtAlgorithm::Hs256),
+      secret: env::var("JWT_SECRET").ok().filter(|secret| !secret.is_empty()),
+      private_key_file: env::var("JWT_PRIVATE_KEY_FILE").ok().map(PathBuf::from),
+      key_id: env::var("JWT_KEY_ID").ok().filter(|kid| !kid.is_empty()),
+      verification_key_files,
+      issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| public_base_url.to_string()),
+      audience: env::var("JWT_AUDIENCE").unwrap_or_else(|_| "todo-api".to_string()),
+      validate_issuer: env_or("JWT_VALIDATE_ISSUER", true),
+      validate_audience: env_or("JWT_VALIDATE_AUDIENCE", true),
+      leeway_seconds: env_or("JWT_LEEWAY_SECONDS", 60),
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+  // Discovery document is read from `{issuer_url}/.well-known/openid-configuration`
+  pub issuer_url: String,
+  pub client_id: String,
+  // Public clients rely on PKCE alone
+  pub client_secret: Option<String>,
+  pub redirect_url: String,
+  pub scopes: String,
+  // Create a local user on first login when no account matches the email
+  pub auto_create_users: bool,
+  // Only link accounts by email when the provider vouches for the address
+  pub require_verified_email: bool,
+  // ID token claim holding the user's groups or roles, e.g. `groups`
+  pub role_claim: Option<String>,
+  // Claim value to role name, first match wins (`OIDC_ROLE_MAPPING=admins=admin,staff=user`)
+  pub role_mapping: Vec<(String, String)>,
+  // Role for new users when nothing in `role_mapping` matches
+  pub default_role: String,
+}
+
+impl OidcConfig {
+  fn from_env(public_base_url: &str) -> Option<Self> {
+    let issuer_url = env::var("OIDC_ISSUER_URL").ok()?
+      .trim_end_matches('/')
+      .to_string();
+    let client_id = env::var("OIDC_CLIENT_ID")
+      .expect("OIDC_CLIENT_ID must be set when OIDC_ISSUER_URL is");
+
+    let role_mapping = env::var("OIDC_ROLE_MAPPING")
+      .unwrap_or_default()
+      .split(',')
+      .filter_map(|pair| pair.split_once('='))
+      .map(|(value, role)| (value.trim().to_string(), role.trim().to_string()))
+      .filter(|(value, role)| !value.is_empty() && !role.is_empty())
+      .collect();
+
+    Some(Self {
+      issuer_url,
+      client_id,
+      client_secret: env::var("OIDC_CLIENT_SECRET").ok().filter(|secret| !secret.is_empty()),
+      redirect_url: env::var("OIDC_REDIRECT_URL")
+        .unwrap_or_else(|_| format!("{public_base_url}/api/auth/oidc/callback")),
+      scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+      auto_create_users: env_or("OIDC_AUTO_CREATE_USERS", true),
+      require_verified_email: env_or("OIDC_REQUIRE_VERIFIED_EMAIL", true),
+      role_claim: env::var("OIDC_ROLE_CLAIM").ok().filter(|claim| !claim.is_empty()),
+      role_mapping,
+      default_role: env::var("OIDC_DEFAULT_ROLE").unwrap_or_else(|_| "user".to_string()),
+    })
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct SeedAdminConfig {
+  pub email: String,
+  pub full_name: String,
+  pub password: String,
+}
+
+impl SeedAdminConfig {
+  fn from_env() -> Option<Self> {
+    let email = env::var("SEED_ADMIN_EMAIL").ok().filter(|email| !email.is_empty())?;
+    let password = env::var("SEED_ADMIN_PASSWORD")
+      .expect("SEED_ADMIN_PASSWORD must be set when SEED_ADMIN_EMAIL is");
+
+    Some(Self {
+      email,
+      full_name: env::var("SEED_ADMIN_NAME").unwrap_or_else(|_| "Administrator".to_string()),
+      password,
+    })
+  }
+}
+
+#[derive(Clone, Debug)]
+pub enum MailerBackend {
+  // Only write the message to the log
+  Log,
+  // Write each message as an `.eml` file into the directory
+  File(PathBuf),
+  // Send through an SMTP server, e.g. `smtp://127.0.0.1:1025` for a local mail catcher
+  Smtp(String),
+}
+
+impl MailerBackend {
+  fn from_env() -> Self {
+    match env::var("MAILER").as_deref() {
+      Ok("file") => Self::File(env_or("MAIL_DIR", PathBuf::from("mail"))),
+      Ok("smtp") => Self::Smtp(env::var("SMTP_URL").unwrap_or_else(|_| "smtp://127.0.0.1:1025".to_string())),
+      _ => Self::Log,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignupPolicy {
+  // Anyone may register
+  Open,
+  // Only addresses at these domains (lowercase) may register
+  AllowedDomains(Vec<String>),
+  // Accounts only come from an admin or an invitation
+  InviteOnly,
+  Disabled,
+}
+
+impl SignupPolicy {
+  fn from_env() -> Self {
+    match env::var("SIGNUP_POLICY").as_deref() {
+      Ok("open") => Self::Open,
+      Ok("domains") => Self::AllowedDomains(
+        env::var("SIGNUP_ALLOWED_DOMAINS")
+          .unwrap_or_default()
+          .split(',')
+          .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+          .filter(|domain| !domain.is_empty())
+          .collect(),
+      ),
+      Ok("invite_only") => Self::InviteOnly,
+      _ => Self::Disabled,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RevocationBackend {
+  #[default]
+  Mongo,
+  // Process-local; only suitable for tests and single-instance development
+  Memory,
+}
+
+impl FromStr for RevocationBackend {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "mongo" => Ok(Self::Mongo),
+      "memory" => Ok(Self::Memory),
+      _ => Err(format!("Unknown revocation store `{value}`")),
+    }
+  }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+  env::var(key)
+    .ok()
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(default)
+}
+
+impl AppConfig {
+  pub fn from_env() -> Self {
+    let public_base_url = env::var("PUBLIC_BASE_URL")
+        .unwrap_or_else(|_| "http://127.0.0.1:3000".to_string())
+        .trim_end_matches('/')
+        .to_string();
+
+    let jwt = JwtConfig::from_env(&public_base_url);
+    let oidc = OidcConfig::from_env(&public_base_url);
+
+    Self {
+      public_base_url,
+      jwt,
+      password_hash: PasswordHashConfig::from_env(),
+      password_policy: PasswordPolicyConfig::from_env(),
+      access_token_ttl: Duration::seconds(env_or("ACCESS_TOKEN_TTL_SECONDS", 3600)),
+      refresh_token_ttl: Duration::days(env_or("REFRESH_TOKEN_TTL_DAYS", 30)),
+      revocation_store: env_or("REVOCATION_STORE", RevocationBackend::Mongo),
+      mailer: MailerBackend::from_env(),
+      mail_from: env::var("MAIL_FROM").unwrap_or_else(|_| "To-Do List <no-reply@localhost>".to_string()),
+      password_reset_ttl: Duration::minutes(env_or("PASSWORD_RESET_TTL_MINUTES", 60)),
+      password_reset_max_per_email: env_or("PASSWORD_RESET_MAX_PER_EMAIL", 3),
+      password_reset_max_per_ip: env_or("PASSWORD_RESET_MAX_PER_IP", 20),
+      password_reset_rate_window: Duration::minutes(env_or("PASSWORD_RESET_RATE_WINDOW_MINUTES", 60)),
+      require_verified_email: env_or("REQUIRE_VERIFIED_EMAIL", false),
+      email_verification_ttl: Duration::hours(env_or("EMAIL_VERIFICATION_TTL_HOURS", 48)),
+      verification_resend_interval: Duration::seconds(env_or("VERIFICATION_RESEND_INTERVAL_SECONDS", 60)),
+      mfa_challenge_ttl: Duration::seconds(env_or("MFA_CHALLENGE_TTL_SECONDS", 300)),
+      mfa_challenge_max_attempts: env_or("MFA_CHALLENGE_MAX_ATTEMPTS", 5),
+      signup_policy: SignupPolicy::from_env(),
+      signup_default_role: env::var("SIGNUP_DEFAULT_ROLE").unwrap_or_else(|_| "user".to_string()),
+      invitation_ttl: Duration::days(env_or("INVITATION_TTL_DAYS", 7)),
+      impersonation_ttl: Duration::minutes(env_or("IMPERSONATION_TTL_MINUTES", 15)),
+      account_deletion_grace: Duration::days(env_or("ACCOUNT_DELETION_GRACE_DAYS", 30)),
+      login_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+      login_max_failures_per_ip: env_or("LOGIN_MAX_FAILURES_PER_IP", 50),
+      login_lockout: Duration::minutes(env_or("LOGIN_LOCKOUT_MINUTES", 15)),
+      trust_proxy_headers: env_or("TRUST_PROXY_HEADERS", false),
+      oidc,
+      seed_admin: SeedAdminConfig::from_env(),
+    }
+  }
+}
@@ -0,0 +1,141 @@
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::{
+  dtos::{ImportTaskRow, TaskResponse},
+  models::{TaskPriority, TaskStatus},
+};
+use super::{boxed, DecodedRow, ExportStream};
+
+// Column layout of an exported CSV file
+// `title`, `description`, `status`, `priority`, `due_date` and `tags` are read back on import
+#[derive(Serialize)]
+struct CsvTaskRecord<'a> {
+  id: &'a str,
+  title: &'a str,
+  description: Option<&'a str>,
+  status: &'a TaskStatus,
+  priority: &'a TaskPriority,
+  due_date: Option<DateTime<Utc>>,
+  tags: String,
+  created_at: Option<DateTime<Utc>>,
+  updated_at: Option<DateTime<Utc>>,
+}
+
+// Tags travel as a single comma-separated cell
+#[derive(Deserialize)]
+struct CsvImportRow {
+  title: Option<String>,
+  description: Option<String>,
+  status: Option<TaskStatus>,
+  priority: Option<TaskPriority>,
+  due_date: Option<DateTime<Utc>>,
+  tags: Option<String>,
+}
+
+impl From<CsvImportRow> for ImportTaskRow {
+  fn from(row: CsvImportRow) -> Self {
+    Self {
+      title: row.title,
+      description: row.description,
+      status: row.status,
+      priority: row.priority,
+      due_date: row.due_date,
+      tags: row.tags.map(|tags| {
+        tags.split(',')
+          .map(str::trim)
+          .filter(|tag| !tag.is_empty())
+          .map(str::to_string)
+          .collect()
+      }),
+    }
+  }
+}
+
+const HEADER: &str = "id,title,description,status,priority,due_date,tags,created_at,updated_at\n";
+
+pub fn encode<S>(tasks: S) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  let rows = tasks.map(|task| encode_row(&task?));
+  boxed(super::chunk(HEADER).chain(rows))
+}
+
+fn encode_row(task: &TaskResponse) -> Result<String, BoxError> {
+  let mut writer = ::csv::WriterBuilder::new()
+      .has_headers(false)
+      .from_writer(Vec::new());
+
+  writer.serialize(CsvTaskRecord {
+    id: &task.id,
+    title: &task.title,
+    description: task.description.as_deref(),
+    status: &task.status,
+    priority: &task.priority,
+    due_date: task.due_date,
+    tags: task.tags.join(","),
+    created_at: task.created_at,
+    updated_at: task.updated_at,
+  })?;
+
+  let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+  Ok(String::from_utf8(bytes)?)
+}
+
+pub fn decode(input: &str) -> Vec<DecodedRow> {
+  let mut reader = ::csv::ReaderBuilder::new()
+      .trim(::csv::Trim::All)
+      .flexible(true)
+      .from_reader(input.as_bytes());
+
+  reader
+    .deserialize::<CsvImportRow>()
+    .map(|row| row.map(ImportTaskRow::from).map_err(|e| e.to_string()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::converters::{fixtures::{export, tags, task}, TransferFormat};
+
+  #[tokio::test]
+  async fn exports_a_header_and_one_row_per_task() {
+    let mut first = task("Write report", TaskStatus::InProgress, TaskPriority::High);
+    first.tags = tags(&["work", "q1"]);
+    let second = task("Buy milk", TaskStatus::Pending, TaskPriority::Low);
+
+    let output = export(TransferFormat::Csv, vec![first, second]).await;
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 3, "{output}");
+    assert_eq!(format!("{}\n", lines[0]), HEADER);
+    assert!(lines[1].contains(",Write report,,inprogress,high,2025-01-05T00:00:00Z,\"work,q1\","), "{output}");
+  }
+
+  #[tokio::test]
+  async fn round_trips_through_import() {
+    let mut original = task("Plan \"Q2\", draft", TaskStatus::Completed, TaskPriority::Urgent);
+    original.description = Some("Line one\nLine two".to_string());
+    original.tags = tags(&["work", "@office"]);
+
+    let output = export(TransferFormat::Csv, vec![original]).await;
+    let row = decode(&output).pop().unwrap().unwrap();
+    assert_eq!(row.title.as_deref(), Some("Plan \"Q2\", draft"));
+    assert_eq!(row.description.as_deref(), Some("Line one\nLine two"));
+    assert_eq!(row.status, Some(TaskStatus::Completed));
+    assert_eq!(row.priority, Some(TaskPriority::Urgent));
+    assert_eq!(row.due_date, Some("2025-01-05T00:00:00Z".parse().unwrap()));
+    assert_eq!(row.tags, Some(tags(&["work", "@office"])));
+  }
+
+  #[test]
+  fn reports_bad_rows_without_dropping_the_rest() {
+    let rows = decode("title,status\nFirst,pending\nSecond,someday\nThird,\n");
+    assert_eq!(rows.len(), 3);
+    assert!(rows[0].is_ok());
+    assert!(rows[1].is_err());
+    assert_eq!(rows[2].as_ref().unwrap().title.as_deref(), Some("Third"));
+  }
+}
@@ -0,0 +1,427 @@
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use crate::{
+  dtos::{ImportTaskRow, TaskResponse},
+  models::{TaskPriority, TaskStatus},
+};
+use super::{boxed, chunk, DecodedRow, ExportStream};
+
+const HEADER: &str = "BEGIN:VCALENDAR\r\n\
+VERSION:2.0\r\n\
+PRODID:-//to_do_list//Tasks//EN\r\n\
+CALSCALE:GREGORIAN\r\n\
+X-WR-CALNAME:Tasks\r\n";
+
+const FOOTER: &str = "END:VCALENDAR\r\n";
+
+// Which calendar component each task is rendered as
+// Some calendar apps ignore VTODO entirely, so feeds can ask for VEVENT instead
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarComponent {
+  #[default]
+  Vtodo,
+  Vevent,
+}
+
+/// A complete calendar object holding a single task, as served to CalDAV clients
+pub fn encode_calendar(task: &TaskResponse, uid: &str) -> String {
+  format!("{HEADER}{}{FOOTER}", encode_task_with_uid(task, CalendarComponent::Vtodo, uid))
+}
+
+pub fn encode<S>(tasks: S, component: CalendarComponent) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  let items = tasks.map(move |task| Ok(encode_task(&task?, component)));
+  boxed(chunk(HEADER).chain(items).chain(chunk(FOOTER)))
+}
+
+/// Render a single task, or an empty string for a VEVENT without a due date
+pub fn encode_task(task: &TaskResponse, component: CalendarComponent) -> String {
+  encode_task_with_uid(task, component, &default_uid(&task.id))
+}
+
+pub fn default_uid(task_id: &str) -> String {
+  format!("{task_id}@to_do_list")
+}
+
+/// Same as `encode_task`, keeping a UID chosen by a CalDAV client
+pub fn encode_task_with_uid(task: &TaskResponse, component: CalendarComponent, uid: &str) -> String {
+  let mut lines = Vec::new();
+  let stamp = task.updated_at.or(task.created_at).unwrap_or_else(Utc::now);
+
+  match component {
+    CalendarComponent::Vtodo => {
+      lines.push("BEGIN:VTODO".to_string());
+      push_common(&mut lines, task, uid, stamp);
+      if let Some(due_date) = task.due_date {
+        lines.push(format!("DUE:{}", format_datetime(due_date)));
+      }
+      lines.push(format!("STATUS:{}", todo_status(&task.status)));
+      if task.status == TaskStatus::Completed {
+        lines.push(format!("COMPLETED:{}", format_datetime(stamp)));
+      }
+      lines.push("END:VTODO".to_string());
+    }
+    CalendarComponent::Vevent => {
+      let Some(due_date) = task.due_date else {
+        return String::new();
+      };
+      lines.push("BEGIN:VEVENT".to_string());
+      push_common(&mut lines, task, uid, stamp);
+      lines.push(format!("DTSTART:{}", format_datetime(due_date)));
+      lines.push(format!("STATUS:{}", event_status(&task.status)));
+      lines.push("END:VEVENT".to_string());
+    }
+  }
+
+  lines.iter().map(|line| fold(line)).collect()
+}
+
+fn push_common(lines: &mut Vec<String>, task: &TaskResponse, uid: &str, stamp: DateTime<Utc>) {
+  lines.push(format!("UID:{}", escape_text(uid)));
+  lines.push(format!("DTSTAMP:{}", format_datetime(stamp)));
+  if let Some(created_at) = task.created_at {
+    lines.push(format!("CREATED:{}", format_datetime(created_at)));
+  }
+  if let Some(updated_at) = task.updated_at {
+    lines.push(format!("LAST-MODIFIED:{}", format_datetime(updated_at)));
+  }
+  lines.push(format!("SUMMARY:{}", escape_text(&task.title)));
+  if let Some(description) = &task.description {
+    lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+  }
+  lines.push(format!("PRIORITY:{}", priority_to_ical(&task.priority)));
+  if !task.tags.is_empty() {
+    let categories: Vec<String> = task.tags.iter().map(|tag| escape_text(tag)).collect();
+    lines.push(format!("CATEGORIES:{}", categories.join(",")));
+  }
+}
+
+pub fn todo_status(status: &TaskStatus) -> &'static str {
+  match status {
+    TaskStatus::Pending => "NEEDS-ACTION",
+    TaskStatus::InProgress => "IN-PROCESS",
+    TaskStatus::Completed => "COMPLETED",
+    TaskStatus::Cancelled => "CANCELLED",
+  }
+}
+
+fn event_status(status: &TaskStatus) -> &'static str {
+  match status {
+    TaskStatus::Cancelled => "CANCELLED",
+    _ => "CONFIRMED",
+  }
+}
+
+pub fn status_from_ical(value: &str) -> Option<TaskStatus> {
+  match value.to_ascii_uppercase().as_str() {
+    "NEEDS-ACTION" => Some(TaskStatus::Pending),
+    "IN-PROCESS" => Some(TaskStatus::InProgress),
+    "COMPLETED" => Some(TaskStatus::Completed),
+    "CANCELLED" => Some(TaskStatus::Cancelled),
+    _ => None,
+  }
+}
+
+// RFC 5545 priorities run from 1 (highest) to 9 (lowest), 0 meaning undefined
+pub fn priority_to_ical(priority: &TaskPriority) -> u8 {
+  match priority {
+    TaskPriority::Urgent => 1,
+    TaskPriority::High => 3,
+    TaskPriority::Medium => 5,
+    TaskPriority::Low => 9,
+  }
+}
+
+pub fn priority_from_ical(value: u8) -> Option<TaskPriority> {
+  match value {
+    0 => None,
+    1..=2 => Some(TaskPriority::Urgent),
+    3..=4 => Some(TaskPriority::High),
+    5 => Some(TaskPriority::Medium),
+    _ => Some(TaskPriority::Low),
+  }
+}
+
+pub fn format_datetime(value: DateTime<Utc>) -> String {
+  value.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Parse a DATE or DATE-TIME value
+/// Times with a `TZID` are local to that IANA zone; floating times and dates are read as UTC
+pub fn parse_datetime(value: &str, tzid: Option<&str>) -> Result<DateTime<Utc>, String> {
+  let value = value.trim();
+  if let Ok(datetime) = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S") {
+    let zone = match tzid {
+      Some(tzid) if !value.ends_with('Z') => tzid.trim_matches('"').parse::<Tz>()
+        .map_err(|_| format!("Unknown TZID `{tzid}`"))?,
+      _ => return Ok(datetime.and_utc()),
+    };
+    // A time skipped by a daylight saving change is read as the instant the clocks jumped to
+    return zone.from_local_datetime(&datetime)
+      .earliest()
+      .or_else(|| zone.from_local_datetime(&(datetime + chrono::Duration::hours(1))).earliest())
+      .map(|local| local.with_timezone(&Utc))
+      .ok_or_else(|| format!("Invalid local time `{value}`"));
+  }
+  NaiveDate::parse_from_str(value, "%Y%m%d")
+    .ok()
+    .and_then(|date| date.and_hms_opt(0, 0, 0))
+    .map(|datetime| datetime.and_utc())
+    .ok_or_else(|| format!("Invalid date `{value}`"))
+}
+
+pub fn escape_text(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace(';', "\\;")
+    .replace(',', "\\,")
+    .replace("\r\n", "\\n")
+    .replace('\n', "\\n")
+}
+
+pub fn unescape_text(value: &str) -> String {
+  let mut result = String::with_capacity(value.len());
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      result.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') | Some('N') => result.push('\n'),
+      Some(other) => result.push(other),
+      None => result.push('\\'),
+    }
+  }
+  result
+}
+
+/// Split a list value such as CATEGORIES on commas that are not escaped
+fn split_list(value: &str) -> Vec<String> {
+  let mut items = Vec::new();
+  let mut current = String::new();
+  let mut escaped = false;
+  for c in value.chars() {
+    match c {
+      ',' if !escaped => items.push(std::mem::take(&mut current)),
+      _ => current.push(c),
+    }
+    escaped = c == '\\' && !escaped;
+  }
+  items.push(current);
+  items
+    .iter()
+    .map(|item| unescape_text(item.trim()))
+    .filter(|item| !item.is_empty())
+    .collect()
+}
+
+/// Fold a content line at 75 octets and terminate it with CRLF
+fn fold(line: &str) -> String {
+  let mut folded = String::with_capacity(line.len() + 8);
+  let mut width = 0;
+  for c in line.chars() {
+    if width + c.len_utf8() > 75 {
+      folded.push_str("\r\n ");
+      width = 1;
+    }
+    folded.push(c);
+    width += c.len_utf8();
+  }
+  folded.push_str("\r\n");
+  folded
+}
+
+/// Join folded lines back together
+pub fn unfold(input: &str) -> Vec<String> {
+  let mut lines: Vec<String> = Vec::new();
+  for line in input.split('\n') {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+      (Some(continuation), Some(last)) => last.push_str(continuation),
+      _ if line.is_empty() => {}
+      _ => lines.push(line.to_string()),
+    }
+  }
+  lines
+}
+
+// A content line split into its name, parameters and value
+pub struct ContentLine<'a> {
+  pub name: String,
+  params: &'a str,
+  pub value: &'a str,
+}
+
+impl ContentLine<'_> {
+  /// Value of a parameter such as `TZID`, unquoted
+  pub fn param(&self, key: &str) -> Option<&str> {
+    self.params
+      .split(';')
+      .filter_map(|param| param.split_once('='))
+      .find(|(name, _)| name.eq_ignore_ascii_case(key))
+      .map(|(_, value)| value.trim_matches('"'))
+  }
+}
+
+pub fn parse_line(line: &str) -> Option<ContentLine<'_>> {
+  // The value starts at the first ':' that is not inside a quoted parameter
+  let mut in_quotes = false;
+  let split = line.char_indices().find(|&(_, c)| {
+    if c == '"' {
+      in_quotes = !in_quotes;
+    }
+    c == ':' && !in_quotes
+  })?.0;
+
+  let (head, value) = (&line[..split], &line[split + 1..]);
+  let (name, params) = head.split_once(';').unwrap_or((head, ""));
+
+  Some(ContentLine { name: name.to_ascii_uppercase(), params, value })
+}
+
+// A VTODO read from a calendar file, together with its UID
+pub struct ParsedTodo {
+  pub uid: Option<String>,
+  pub row: ImportTaskRow,
+}
+
+/// Read every VTODO in a calendar file as an import row
+pub fn decode(input: &str) -> Vec<DecodedRow> {
+  parse_todos(input)
+    .into_iter()
+    .map(|todo| todo.map(|todo| todo.row))
+    .collect()
+}
+
+pub fn parse_todos(input: &str) -> Vec<Result<ParsedTodo, String>> {
+  let mut todos = Vec::new();
+  let mut current: Option<Vec<String>> = None;
+
+  for line in unfold(input) {
+    let upper = line.to_ascii_uppercase();
+    if upper == "BEGIN:VTODO" {
+      current = Some(Vec::new());
+    } else if upper == "END:VTODO" {
+      if let Some(properties) = current.take() {
+        todos.push(decode_todo(&properties));
+      }
+    } else if let Some(properties) = current.as_mut() {
+      properties.push(line);
+    }
+  }
+
+  todos
+}
+
+fn decode_todo(properties: &[String]) -> Result<ParsedTodo, String> {
+  let mut uid = None;
+  let mut row = ImportTaskRow {
+    title: None,
+    description: None,
+    status: None,
+    priority: None,
+    due_date: None,
+    tags: None,
+  };
+
+  for line in properties {
+    let Some(property) = parse_line(line) else {
+      continue;
+    };
+    match property.name.as_str() {
+      "UID" => uid = Some(unescape_text(property.value)),
+      "SUMMARY" => row.title = Some(unescape_text(property.value)),
+      "DESCRIPTION" => row.description = Some(unescape_text(property.value)),
+      "CATEGORIES" => row.tags.get_or_insert_with(Vec::new).extend(split_list(property.value)),
+      "STATUS" => {
+        row.status = Some(status_from_ical(property.value)
+          .ok_or_else(|| format!("Unsupported STATUS `{}`", property.value))?);
+      }
+      "PRIORITY" => {
+        let priority = property.value.trim().parse::<u8>()
+          .map_err(|_| format!("Invalid PRIORITY `{}`", property.value))?;
+        row.priority = priority_from_ical(priority);
+      }
+      "DUE" => {
+        row.due_date = Some(parse_datetime(property.value, property.param("TZID"))
+          .map_err(|e| format!("Invalid DUE: {e}"))?);
+      }
+      _ => {}
+    }
+  }
+
+  Ok(ParsedTodo { uid, row })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::converters::fixtures::{tags, task};
+
+  fn round_trip(original: &TaskResponse) -> ImportTaskRow {
+    let calendar = encode_calendar(original, "abc@example.com");
+    let mut todos = parse_todos(&calendar);
+    assert_eq!(todos.len(), 1, "{calendar}");
+    let todo = todos.pop().unwrap().unwrap();
+    assert_eq!(todo.uid.as_deref(), Some("abc@example.com"));
+    todo.row
+  }
+
+  #[test]
+  fn round_trips_every_status_and_priority() {
+    let statuses = [TaskStatus::Pending, TaskStatus::InProgress, TaskStatus::Completed, TaskStatus::Cancelled];
+    let priorities = [TaskPriority::Low, TaskPriority::Medium, TaskPriority::High, TaskPriority::Urgent];
+    for status in &statuses {
+      for priority in &priorities {
+        let row = round_trip(&task("Write report", status.clone(), priority.clone()));
+        assert_eq!(row.status.as_ref(), Some(status));
+        assert_eq!(row.priority.as_ref(), Some(priority));
+      }
+    }
+  }
+
+  #[test]
+  fn round_trips_text_that_needs_escaping_and_folding() {
+    let mut original = task(&"Plan; review, ship \\ repeat ".repeat(5), TaskStatus::Pending, TaskPriority::High);
+    original.description = Some("First line\nSecond, with comma".to_string());
+    original.tags = tags(&["work", "a,b"]);
+
+    let calendar = encode_calendar(&original, "abc@example.com");
+    assert!(calendar.lines().all(|line| line.len() <= 75), "{calendar}");
+
+    let row = round_trip(&original);
+    assert_eq!(row.title.as_deref(), Some(original.title.as_str()));
+    assert_eq!(row.description, original.description);
+    assert_eq!(row.due_date, original.due_date);
+    assert_eq!(row.tags, Some(original.tags));
+  }
+
+  #[test]
+  fn resolves_tzid_due_dates() {
+    let row = |due: &str| {
+      let calendar = format!("BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nSUMMARY:Call\r\n{due}\r\nEND:VTODO\r\nEND:VCALENDAR\r\n");
+      decode(&calendar).pop().unwrap()
+    };
+    let utc = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+
+    let berlin = row("DUE;TZID=Europe/Berlin:20250115T090000").unwrap();
+    assert_eq!(berlin.due_date, Some(utc("2025-01-15T08:00:00Z")));
+    let quoted = row("DUE;TZID=\"America/New_York\":20250715T090000").unwrap();
+    assert_eq!(quoted.due_date, Some(utc("2025-07-15T13:00:00Z")));
+    let explicit = row("DUE;TZID=Europe/Berlin:20250115T090000Z").unwrap();
+    assert_eq!(explicit.due_date, Some(utc("2025-01-15T09:00:00Z")));
+    let floating = row("DUE:20250115T090000").unwrap();
+    assert_eq!(floating.due_date, Some(utc("2025-01-15T09:00:00Z")));
+    let date = row("DUE;VALUE=DATE:20250115").unwrap();
+    assert_eq!(date.due_date, Some(utc("2025-01-15T00:00:00Z")));
+
+    assert!(row("DUE;TZID=Mars/Olympus:20250115T090000").is_err());
+  }
+}
@@ -0,0 +1,100 @@
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use crate::dtos::{ImportTaskRow, TaskResponse};
+use super::{boxed, chunk, DecodedRow, ExportStream};
+
+/// `[{...},{...}]`
+pub fn encode_array<S>(tasks: S) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  let items = tasks.enumerate().map(|(index, task)| {
+    let json = serde_json::to_string(&task?)?;
+    Ok(if index == 0 { json } else { format!(",{json}") })
+  });
+
+  boxed(chunk("[").chain(items).chain(chunk("]")))
+}
+
+/// One JSON object per line
+pub fn encode_lines<S>(tasks: S) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  boxed(tasks.map(|task| {
+    let json = serde_json::to_string(&task?)?;
+    Ok(format!("{json}\n"))
+  }))
+}
+
+pub fn decode_array(input: &str) -> Result<Vec<DecodedRow>, String> {
+  let values: Vec<serde_json::Value> = serde_json::from_str(input)
+      .map_err(|e| format!("Invalid JSON array: {e}"))?;
+
+  Ok(values.into_iter().map(decode_value).collect())
+}
+
+pub fn decode_lines(input: &str) -> Vec<DecodedRow> {
+  input
+    .lines()
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| {
+      serde_json::from_str::<serde_json::Value>(line)
+        .map_err(|e| format!("Invalid JSON: {e}"))
+        .and_then(decode_value)
+    })
+    .collect()
+}
+
+fn decode_value(value: serde_json::Value) -> DecodedRow {
+  serde_json::from_value::<ImportTaskRow>(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    converters::{fixtures::{export, task}, TransferFormat},
+    models::{TaskPriority, TaskStatus},
+  };
+
+  #[tokio::test]
+  async fn exports_a_valid_array() {
+    assert_eq!(export(TransferFormat::Json, Vec::new()).await, "[]");
+
+    let tasks = vec![
+      task("Write report", TaskStatus::Pending, TaskPriority::High),
+      task("Buy milk", TaskStatus::Completed, TaskPriority::Low),
+    ];
+    let output = export(TransferFormat::Json, tasks).await;
+    let rows = decode_array(&output).unwrap();
+    let titles: Vec<_> = rows.into_iter().map(|row| row.unwrap().title.unwrap()).collect();
+    assert_eq!(titles, vec!["Write report", "Buy milk"]);
+  }
+
+  #[tokio::test]
+  async fn exports_one_object_per_line() {
+    let tasks = vec![
+      task("Write report", TaskStatus::InProgress, TaskPriority::Urgent),
+      task("Buy milk", TaskStatus::Cancelled, TaskPriority::Medium),
+    ];
+    let output = export(TransferFormat::Ndjson, tasks).await;
+    assert_eq!(output.lines().count(), 2);
+
+    let row = decode_lines(&output).remove(0).unwrap();
+    assert_eq!(row.title.as_deref(), Some("Write report"));
+    assert_eq!(row.status, Some(TaskStatus::InProgress));
+    assert_eq!(row.priority, Some(TaskPriority::Urgent));
+  }
+
+  #[test]
+  fn reports_bad_lines_and_rejects_non_arrays() {
+    let rows = decode_lines("{\"title\":\"Ok\"}\nnot json\n{\"title\":\"Bad\",\"status\":\"later\"}\n");
+    assert_eq!(rows.len(), 3);
+    assert!(rows[0].is_ok());
+    assert!(rows[1].is_err());
+    assert!(rows[2].is_err());
+
+    assert!(decode_array("{\"title\":\"Ok\"}").is_err());
+  }
+}
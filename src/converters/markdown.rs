@@ -0,0 +1,306 @@
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use crate::{
+  dtos::{ImportTaskRow, TaskResponse},
+  models::{TaskPriority, TaskStatus},
+};
+use super::{boxed, escape_title, format_date, parse_date, words, DecodedRow, ExportStream, Title};
+
+// Markdown checklists, one item per task:
+//
+//   - [ ] Title #project @context !high due:2025-01-05
+//     Description lines are indented below the item
+//
+//     and may be split into paragraphs
+//
+// `[x]` marks a completed task, `[/]` one in progress and `[-]` a cancelled one.
+// Round-tripped fields: title, description, status, priority, due date (day only) and tags.
+// Title words that would read as a tag, priority or due date are written with a leading `\`.
+
+pub fn encode<S>(tasks: S) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  boxed(tasks.map(|task| Ok(encode_task(&task?))))
+}
+
+pub fn encode_task(task: &TaskResponse) -> String {
+  let mut parts = vec![
+    format!("- [{}]", status_marker(&task.status)),
+    escape_title(&task.title, |_, word| is_syntax(word)),
+  ];
+
+  for tag in &task.tags {
+    if tag.starts_with('@') {
+      parts.push(tag.clone());
+    } else {
+      parts.push(format!("#{tag}"));
+    }
+  }
+
+  if let Some(priority) = priority_token(&task.priority) {
+    parts.push(priority.to_string());
+  }
+
+  if let Some(due_date) = task.due_date {
+    parts.push(format!("due:{}", format_date(due_date)));
+  }
+
+  let mut item = parts.join(" ");
+  item.push('\n');
+
+  if let Some(description) = &task.description {
+    for line in description.lines() {
+      // Blank lines separate paragraphs; leave them without trailing spaces
+      if !line.trim().is_empty() {
+        item.push_str("  ");
+        item.push_str(line);
+      }
+      item.push('\n');
+    }
+  }
+
+  item
+}
+
+fn status_marker(status: &TaskStatus) -> char {
+  match status {
+    TaskStatus::Pending => ' ',
+    TaskStatus::InProgress => '/',
+    TaskStatus::Completed => 'x',
+    TaskStatus::Cancelled => '-',
+  }
+}
+
+fn priority_token(priority: &TaskPriority) -> Option<&'static str> {
+  match priority {
+    TaskPriority::Urgent => Some("!urgent"),
+    TaskPriority::High => Some("!high"),
+    TaskPriority::Medium => Some("!medium"),
+    TaskPriority::Low => None,
+  }
+}
+
+/// Split a list item such as `- [x] Title` into its marker and text
+fn parse_item(line: &str) -> Option<(char, &str)> {
+  let rest = line.trim_start()
+    .strip_prefix("- [")
+    .or_else(|| line.trim_start().strip_prefix("* ["))
+    .or_else(|| line.trim_start().strip_prefix("+ ["))?;
+  let mut chars = rest.chars();
+  let marker = chars.next()?;
+  let text = chars.as_str().strip_prefix(']')?;
+  Some((marker, text.strip_prefix(' ').unwrap_or(text)))
+}
+
+/// Read every checklist item; other Markdown (headings, paragraphs) is skipped
+pub fn decode(input: &str) -> Vec<DecodedRow> {
+  let mut rows: Vec<DecodedRow> = Vec::new();
+  let mut description: Option<Vec<&str>> = None;
+
+  // Blank lines at either end are dropped; the ones between paragraphs stay
+  let flush = |rows: &mut Vec<DecodedRow>, description: &mut Option<Vec<&str>>| {
+    if let (Some(Ok(row)), Some(lines)) = (rows.last_mut(), description.take()) {
+      let text = lines.join("\n");
+      let text = text.trim_end().trim_start_matches('\n');
+      if !text.is_empty() {
+        row.description = Some(text.to_string());
+      }
+    }
+  };
+
+  for line in input.lines() {
+    if let Some((marker, text)) = parse_item(line) {
+      flush(&mut rows, &mut description);
+      rows.push(decode_item(marker, text));
+      description = Some(Vec::new());
+    } else if line.trim().is_empty() && description.is_some() {
+      // Possibly a paragraph break; only a following indented line says so
+      if let Some(lines) = description.as_mut() {
+        lines.push("");
+      }
+    } else if line.starts_with(' ') || line.starts_with('\t') {
+      if let Some(lines) = description.as_mut() {
+        // One level of indentation belongs to the list item, anything deeper to the description
+        let line = line.strip_prefix("  ").or_else(|| line.strip_prefix('\t')).unwrap_or(line.trim_start());
+        lines.push(line.trim_end());
+      }
+    } else {
+      flush(&mut rows, &mut description);
+    }
+  }
+  flush(&mut rows, &mut description);
+
+  rows
+}
+
+fn decode_item(marker: char, text: &str) -> DecodedRow {
+  let status = match marker {
+    ' ' => TaskStatus::Pending,
+    'x' | 'X' => TaskStatus::Completed,
+    '/' => TaskStatus::InProgress,
+    '-' => TaskStatus::Cancelled,
+    _ => return Err(format!("Unknown checklist marker `[{marker}]`")),
+  };
+
+  let mut row = ImportTaskRow {
+    title: None,
+    description: None,
+    status: Some(status),
+    priority: None,
+    due_date: None,
+    tags: None,
+  };
+  let mut title = Title::new(text);
+  let mut tags = Vec::new();
+
+  for word in words(text) {
+    let token = word.text;
+    if let Some(unescaped) = token.strip_prefix('\\') {
+      title.push(&word, unescaped);
+    } else if let Some(tag) = token.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+      tags.push(tag.to_string());
+    } else if token.len() > 1 && token.starts_with('@') {
+      tags.push(token.to_string());
+    } else if let Some(value) = token.strip_prefix("due:") {
+      row.due_date = Some(parse_date(value).ok_or_else(|| format!("Invalid due date `{value}`"))?);
+    } else if let Some(priority) = parse_priority(token) {
+      row.priority = Some(priority);
+    } else {
+      title.push(&word, token);
+    }
+  }
+
+  row.title = Some(title.into_string());
+  if !tags.is_empty() {
+    row.tags = Some(tags);
+  }
+  Ok(row)
+}
+
+// Words `decode_item` would not keep in the title
+fn is_syntax(word: &str) -> bool {
+  (word.len() > 1 && (word.starts_with('#') || word.starts_with('@')))
+    || word.starts_with("due:")
+    || parse_priority(word).is_some()
+}
+
+fn parse_priority(token: &str) -> Option<TaskPriority> {
+  match token {
+    "!urgent" => Some(TaskPriority::Urgent),
+    "!high" => Some(TaskPriority::High),
+    "!medium" => Some(TaskPriority::Medium),
+    "!low" => Some(TaskPriority::Low),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::converters::fixtures::{tags, task};
+
+  fn item(title: &str, status: TaskStatus, priority: TaskPriority) -> TaskResponse {
+    TaskResponse {
+      description: Some("First line\nSecond line".to_string()),
+      tags: tags(&["work", "@office"]),
+      ..task(title, status, priority)
+    }
+  }
+
+  fn assert_round_trip(originals: &[TaskResponse]) {
+    let document: String = originals.iter().map(encode_task).collect();
+    let rows = decode(&document);
+    assert_eq!(rows.len(), originals.len(), "{document}");
+
+    for (original, row) in originals.iter().zip(rows) {
+      let row = row.unwrap();
+      assert_eq!(row.title.as_deref(), Some(original.title.as_str()), "{document}");
+      assert_eq!(row.description, original.description, "{document}");
+      assert_eq!(row.status.unwrap_or_default(), original.status, "{document}");
+      assert_eq!(row.priority.unwrap_or_default(), original.priority, "{document}");
+      assert_eq!(row.due_date, original.due_date, "{document}");
+      assert_eq!(row.tags.unwrap_or_default(), original.tags, "{document}");
+    }
+  }
+
+  #[test]
+  fn round_trips_every_status_and_priority() {
+    let statuses = [TaskStatus::Pending, TaskStatus::InProgress, TaskStatus::Completed, TaskStatus::Cancelled];
+    let priorities = [TaskPriority::Low, TaskPriority::Medium, TaskPriority::High, TaskPriority::Urgent];
+
+    let tasks: Vec<TaskResponse> = statuses.iter()
+      .flat_map(|status| priorities.iter().map(move |priority| item("Write report", status.clone(), priority.clone())))
+      .collect();
+    assert_round_trip(&tasks);
+  }
+
+  #[test]
+  fn round_trips_without_optional_fields() {
+    let mut original = item("Buy milk", TaskStatus::Pending, TaskPriority::Low);
+    original.description = None;
+    original.due_date = None;
+    original.tags = Vec::new();
+
+    assert_eq!(encode_task(&original), "- [ ] Buy milk\n");
+    assert_round_trip(&[original]);
+  }
+
+  #[test]
+  fn encodes_checklist_syntax() {
+    let original = item("Ship release", TaskStatus::Completed, TaskPriority::High);
+    assert_eq!(
+      encode_task(&original),
+      "- [x] Ship release #work @office !high due:2025-01-05\n  First line\n  Second line\n",
+    );
+  }
+
+  #[test]
+  fn skips_surrounding_markdown() {
+    let rows = decode("# Sprint\n\nSome notes.\n\n* [X] Done thing\n- [ ] Open thing\n\nTrailing paragraph\n");
+    let titles: Vec<_> = rows.into_iter().map(|row| row.unwrap().title.unwrap()).collect();
+    assert_eq!(titles, vec!["Done thing", "Open thing"]);
+  }
+
+  #[test]
+  fn reports_unknown_markers() {
+    assert!(decode("- [?] Maybe").pop().unwrap().is_err());
+  }
+
+  #[test]
+  fn round_trips_titles_that_look_like_syntax() {
+    let titles = [
+      "Fix #42 before release",
+      "Email @bob about !high load",
+      "Read due:2025-01-01 notes",
+      "Path C:\\temp\\new",
+      "\\#literal backslash",
+      "!urgent",
+    ];
+    let tasks: Vec<TaskResponse> = titles.iter()
+      .map(|title| item(title, TaskStatus::Pending, TaskPriority::Medium))
+      .collect();
+    assert_round_trip(&tasks);
+
+    let escaped = encode_task(&item("Fix #42 !high", TaskStatus::Pending, TaskPriority::Low));
+    assert!(escaped.starts_with("- [ ] Fix \\#42 \\!high #work"), "{escaped}");
+  }
+
+  #[test]
+  fn keeps_spacing_and_paragraphs() {
+    let mut original = item("Pay  rent   on time", TaskStatus::Pending, TaskPriority::Low);
+    original.description = Some("First paragraph\nstill first\n\nSecond after a blank line\n    indented code\n\n\nThird".to_string());
+    let mut other = item("Next\tone", TaskStatus::Completed, TaskPriority::High);
+    other.description = Some("Single line".to_string());
+    let encoded = encode_task(&original);
+    assert_round_trip(&[original, other]);
+    assert!(encoded.contains("  still first\n\n  Second after a blank line\n      indented code\n"), "{encoded}");
+  }
+
+  #[test]
+  fn trims_only_the_ends_of_descriptions() {
+    let rows = decode("- [ ] Title\n\n    Indented more\n\n  Second   paragraph  \n\nNot part of it\n");
+    let row = rows.into_iter().next().unwrap().unwrap();
+    assert_eq!(row.description.as_deref(), Some("  Indented more\n\nSecond   paragraph"));
+  }
+}
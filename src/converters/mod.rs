@@ -0,0 +1,221 @@
+pub mod csv;
+pub mod ical;
+pub mod json;
+pub mod markdown;
+pub mod todo_txt;
+pub mod webdav;
+
+use axum::BoxError;
+use futures_util::{stream::BoxStream, Stream, StreamExt};
+use serde::Deserialize;
+use chrono::{DateTime, NaiveDate, Utc};
+use crate::dtos::{ImportTaskRow, TaskResponse};
+
+pub type ExportStream = BoxStream<'static, Result<String, BoxError>>;
+
+// A decoded import row, or the reason it could not be read
+pub type DecodedRow = Result<ImportTaskRow, String>;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+  #[default]
+  Json,
+  Ndjson,
+  Csv,
+  Ics,
+  TodoTxt,
+  Markdown,
+}
+
+impl TransferFormat {
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      TransferFormat::Json => "application/json",
+      TransferFormat::Ndjson => "application/x-ndjson",
+      TransferFormat::Csv => "text/csv; charset=utf-8",
+      TransferFormat::Ics => "text/calendar; charset=utf-8",
+      TransferFormat::TodoTxt => "text/plain; charset=utf-8",
+      TransferFormat::Markdown => "text/markdown; charset=utf-8",
+    }
+  }
+
+  pub fn file_extension(&self) -> &'static str {
+    match self {
+      TransferFormat::Json => "json",
+      TransferFormat::Ndjson => "ndjson",
+      TransferFormat::Csv => "csv",
+      TransferFormat::Ics => "ics",
+      TransferFormat::TodoTxt => "txt",
+      TransferFormat::Markdown => "md",
+    }
+  }
+}
+
+/// Encode a stream of tasks into chunks of the requested format
+/// Nothing is buffered, so large exports are sent as they are read from the cursor
+pub fn encode_tasks<S>(format: TransferFormat, tasks: S) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  match format {
+    TransferFormat::Json => json::encode_array(tasks),
+    TransferFormat::Ndjson => json::encode_lines(tasks),
+    TransferFormat::Csv => csv::encode(tasks),
+    TransferFormat::Ics => ical::encode(tasks, ical::CalendarComponent::Vtodo),
+    TransferFormat::TodoTxt => todo_txt::encode(tasks),
+    TransferFormat::Markdown => markdown::encode(tasks),
+  }
+}
+
+/// Split an import file into rows
+/// Fails only when the file as a whole is unreadable; bad rows are reported individually
+pub fn decode_rows(format: TransferFormat, input: &str) -> Result<Vec<DecodedRow>, String> {
+  match format {
+    TransferFormat::Json => json::decode_array(input),
+    TransferFormat::Ndjson => Ok(json::decode_lines(input)),
+    TransferFormat::Csv => Ok(csv::decode(input)),
+    TransferFormat::Ics => Ok(ical::decode(input)),
+    TransferFormat::TodoTxt => Ok(todo_txt::decode(input)),
+    TransferFormat::Markdown => Ok(markdown::decode(input)),
+  }
+}
+
+// Plain-text formats only carry the day of a due date, at midnight UTC
+pub(crate) fn format_date(value: DateTime<Utc>) -> String {
+  value.format("%Y-%m-%d").to_string()
+}
+
+pub(crate) fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+  NaiveDate::parse_from_str(value, "%Y-%m-%d")
+    .ok()
+    .and_then(|date| date.and_hms_opt(0, 0, 0))
+    .map(|datetime| datetime.and_utc())
+}
+
+// Title words a plain-text format would read as syntax get a leading `\`, as do words that
+// already start with one; `is_syntax` receives each word and its position in the title
+// The spacing between words is kept, except line breaks, which would end the line
+pub(crate) fn escape_title(title: &str, is_syntax: impl Fn(usize, &str) -> bool) -> String {
+  let title = title.trim();
+  let mut escaped = String::with_capacity(title.len());
+  let mut end = 0;
+  for (position, word) in words(title).into_iter().enumerate() {
+    escaped.push_str(&title[end..word.start].replace(['\r', '\n'], " "));
+    if word.text.starts_with('\\') || is_syntax(position, word.text) {
+      escaped.push('\\');
+    }
+    escaped.push_str(word.text);
+    end = word.end;
+  }
+  escaped
+}
+
+// A whitespace-separated word of a line and the byte range it covers
+pub(crate) struct Word<'a> {
+  pub text: &'a str,
+  pub start: usize,
+  pub end: usize,
+}
+
+pub(crate) fn words(line: &str) -> Vec<Word<'_>> {
+  let mut words = Vec::new();
+  let mut start = None;
+  for (index, c) in line.char_indices().chain([(line.len(), ' ')]) {
+    match (start, c.is_whitespace()) {
+      (None, false) => start = Some(index),
+      (Some(first), true) => {
+        words.push(Word { text: &line[first..index], start: first, end: index });
+        start = None;
+      }
+      _ => {}
+    }
+  }
+  words
+}
+
+// The title words picked out of a line, with the spacing that was between them; words that had
+// syntax such as a tag in between are joined by a single space
+pub(crate) struct Title<'a> {
+  line: &'a str,
+  text: String,
+  end: Option<usize>,
+}
+
+impl<'a> Title<'a> {
+  pub fn new(line: &'a str) -> Self {
+    Self { line, text: String::new(), end: None }
+  }
+
+  // `text` is the word as it goes into the title, without any escape
+  pub fn push(&mut self, word: &Word, text: &str) {
+    if let Some(end) = self.end {
+      let gap = &self.line[end..word.start];
+      if gap.trim().is_empty() {
+        self.text.push_str(gap);
+      } else {
+        self.text.push(' ');
+      }
+    }
+    self.text.push_str(text);
+    self.end = Some(word.end);
+  }
+
+  pub fn into_string(self) -> String {
+    self.text
+  }
+}
+
+// Wrap a single chunk so it can be chained in front of or behind a row stream
+fn chunk(value: &'static str) -> impl Stream<Item = Result<String, BoxError>> {
+  futures_util::stream::once(async move { Ok(value.to_string()) })
+}
+
+pub(crate) fn boxed<S>(stream: S) -> ExportStream
+where
+  S: Stream<Item = Result<String, BoxError>> + Send + 'static,
+{
+  stream.boxed()
+}
+
+// Shared by the converter tests
+#[cfg(test)]
+pub(crate) mod fixtures {
+  use chrono::{TimeZone, Utc};
+  use futures_util::{stream, TryStreamExt};
+  use crate::{
+    dtos::TaskResponse,
+    models::{TaskPriority, TaskStatus},
+  };
+  use super::{encode_tasks, TransferFormat};
+
+  pub fn task(title: &str, status: TaskStatus, priority: TaskPriority) -> TaskResponse {
+    TaskResponse {
+      id: "65f000000000000000000001".to_string(),
+      user_id: "65f000000000000000000002".to_string(),
+      title: title.to_string(),
+      description: None,
+      status,
+      priority,
+      due_date: Some(Utc.with_ymd_and_hms(2025, 1, 5, 0, 0, 0).unwrap()),
+      tags: Vec::new(),
+      created_by: None,
+      updated_by: None,
+      created_at: Some(Utc.with_ymd_and_hms(2025, 1, 1, 9, 30, 0).unwrap()),
+      updated_at: Some(Utc.with_ymd_and_hms(2025, 1, 3, 18, 0, 0).unwrap()),
+    }
+  }
+
+  pub fn tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+  }
+
+  /// Export `tasks` in `format` and join the chunks the stream produces
+  pub async fn export(format: TransferFormat, tasks: Vec<TaskResponse>) -> String {
+    let chunks: Vec<String> = encode_tasks(format, stream::iter(tasks.into_iter().map(Ok)))
+      .try_collect()
+      .await
+      .unwrap();
+    chunks.concat()
+  }
+}
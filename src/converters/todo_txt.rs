@@ -0,0 +1,288 @@
+use axum::BoxError;
+use futures_util::{Stream, StreamExt};
+use crate::{
+  dtos::{ImportTaskRow, TaskResponse},
+  models::{TaskPriority, TaskStatus},
+};
+use super::{boxed, escape_title, format_date, parse_date, words, DecodedRow, ExportStream, Title};
+
+// todo.txt (https://github.com/todotxt/todo.txt), one task per line:
+//
+//   x 2025-01-03 2025-01-01 Title +project @context due:2025-01-05 pri:A
+//   (B) 2025-01-01 Title +project
+//
+// Round-tripped fields: title, status, priority, due date (day only) and tags.
+// `+project` becomes the tag `project`, `@context` is kept as the tag `@context`.
+// States todo.txt has no syntax for are written as a `status:` key.
+// Title words that would read as any of the above are written with a leading `\`.
+
+pub fn encode<S>(tasks: S) -> ExportStream
+where
+  S: Stream<Item = Result<TaskResponse, BoxError>> + Send + 'static,
+{
+  boxed(tasks.map(|task| Ok(format!("{}\n", encode_task(&task?)))))
+}
+
+pub fn encode_task(task: &TaskResponse) -> String {
+  let mut parts = Vec::new();
+  let completed = task.status == TaskStatus::Completed;
+
+  if completed {
+    parts.push("x".to_string());
+    // The completion date must come first whenever a creation date follows
+    if let Some(completed_at) = task.updated_at.or(task.created_at) {
+      parts.push(format_date(completed_at));
+    }
+  } else if let Some(letter) = priority_letter(&task.priority) {
+    parts.push(format!("({letter})"));
+  }
+
+  if let Some(created_at) = task.created_at {
+    parts.push(format_date(created_at));
+  }
+
+  parts.push(escape_title(&task.title, is_syntax));
+
+  for tag in &task.tags {
+    if tag.starts_with('@') {
+      parts.push(tag.clone());
+    } else {
+      parts.push(format!("+{tag}"));
+    }
+  }
+
+  if let Some(due_date) = task.due_date {
+    parts.push(format!("due:{}", format_date(due_date)));
+  }
+
+  // Completed tasks lose their `(A)` prefix, so the priority moves into a key
+  if completed && let Some(letter) = priority_letter(&task.priority) {
+    parts.push(format!("pri:{letter}"));
+  }
+
+  match task.status {
+    TaskStatus::InProgress => parts.push("status:inprogress".to_string()),
+    TaskStatus::Cancelled => parts.push("status:cancelled".to_string()),
+    _ => {}
+  }
+
+  parts.join(" ")
+}
+
+pub fn decode(input: &str) -> Vec<DecodedRow> {
+  input
+    .lines()
+    .filter(|line| !line.trim().is_empty())
+    .map(decode_line)
+    .collect()
+}
+
+pub fn decode_line(line: &str) -> DecodedRow {
+  let mut tokens = words(line).into_iter().peekable();
+  let mut row = ImportTaskRow {
+    title: None,
+    description: None,
+    status: None,
+    priority: None,
+    due_date: None,
+    tags: None,
+  };
+
+  if tokens.peek().is_some_and(|token| token.text == "x") {
+    tokens.next();
+    row.status = Some(TaskStatus::Completed);
+    // Completion and creation dates are informational only
+    while tokens.peek().is_some_and(|token| parse_date(token.text).is_some()) {
+      tokens.next();
+    }
+  } else {
+    if let Some(priority) = tokens.peek().and_then(|token| parse_priority_prefix(token.text)) {
+      row.priority = Some(priority);
+      tokens.next();
+    }
+    if tokens.peek().is_some_and(|token| parse_date(token.text).is_some()) {
+      tokens.next();
+    }
+  }
+
+  let mut title = Title::new(line);
+  let mut tags = Vec::new();
+
+  for word in tokens {
+    let token = word.text;
+    if let Some(unescaped) = token.strip_prefix('\\') {
+      title.push(&word, unescaped);
+    } else if let Some(project) = token.strip_prefix('+').filter(|project| !project.is_empty()) {
+      tags.push(project.to_string());
+    } else if token.len() > 1 && token.starts_with('@') {
+      tags.push(token.to_string());
+    } else if let Some(value) = token.strip_prefix("due:") {
+      row.due_date = Some(parse_date(value).ok_or_else(|| format!("Invalid due date `{value}`"))?);
+    } else if let Some(value) = token.strip_prefix("pri:") {
+      row.priority = Some(parse_priority_letter(value)
+        .ok_or_else(|| format!("Invalid priority `{value}`"))?);
+    } else if let Some(value) = token.strip_prefix("status:") {
+      let status = match value {
+        "inprogress" => TaskStatus::InProgress,
+        "cancelled" => TaskStatus::Cancelled,
+        "pending" => TaskStatus::Pending,
+        "completed" => TaskStatus::Completed,
+        _ => return Err(format!("Invalid status `{value}`")),
+      };
+      row.status = Some(status);
+    } else {
+      title.push(&word, token);
+    }
+  }
+
+  row.title = Some(title.into_string());
+  if !tags.is_empty() {
+    row.tags = Some(tags);
+  }
+  Ok(row)
+}
+
+// Words `decode_line` would not keep in the title; the first one could also pass for the
+// completion mark, a date or a priority
+fn is_syntax(position: usize, word: &str) -> bool {
+  (word.len() > 1 && (word.starts_with('+') || word.starts_with('@')))
+    || ["due:", "pri:", "status:"].iter().any(|key| word.starts_with(key))
+    || (position == 0 && (word == "x" || parse_date(word).is_some() || parse_priority_prefix(word).is_some()))
+}
+
+// Low priority is the default and is written without a letter
+fn priority_letter(priority: &TaskPriority) -> Option<char> {
+  match priority {
+    TaskPriority::Urgent => Some('A'),
+    TaskPriority::High => Some('B'),
+    TaskPriority::Medium => Some('C'),
+    TaskPriority::Low => None,
+  }
+}
+
+fn parse_priority_letter(value: &str) -> Option<TaskPriority> {
+  let mut chars = value.chars();
+  let letter = chars.next().filter(|c| c.is_ascii_uppercase())?;
+  if chars.next().is_some() {
+    return None;
+  }
+  Some(match letter {
+    'A' => TaskPriority::Urgent,
+    'B' => TaskPriority::High,
+    'C' => TaskPriority::Medium,
+    _ => TaskPriority::Low,
+  })
+}
+
+fn parse_priority_prefix(token: &str) -> Option<TaskPriority> {
+  token.strip_prefix('(')
+    .and_then(|rest| rest.strip_suffix(')'))
+    .and_then(parse_priority_letter)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::{TimeZone, Utc};
+  use crate::converters::fixtures::{tags, task};
+
+  fn assert_round_trip(original: &TaskResponse) {
+    let line = encode_task(original);
+    let row = decode_line(&line).unwrap();
+
+    assert_eq!(row.title.as_deref(), Some(original.title.as_str()), "{line}");
+    assert_eq!(row.status.unwrap_or_default(), original.status, "{line}");
+    assert_eq!(row.priority.unwrap_or_default(), original.priority, "{line}");
+    assert_eq!(row.due_date, original.due_date, "{line}");
+    assert_eq!(row.tags.unwrap_or_default(), original.tags, "{line}");
+  }
+
+  #[test]
+  fn round_trips_every_status_and_priority() {
+    let statuses = [TaskStatus::Pending, TaskStatus::InProgress, TaskStatus::Completed, TaskStatus::Cancelled];
+    let priorities = [TaskPriority::Low, TaskPriority::Medium, TaskPriority::High, TaskPriority::Urgent];
+
+    for status in &statuses {
+      for priority in &priorities {
+        assert_round_trip(&TaskResponse {
+          tags: tags(&["work", "@office"]),
+          ..task("Write report", status.clone(), priority.clone())
+        });
+      }
+    }
+  }
+
+  #[test]
+  fn round_trips_without_optional_fields() {
+    let mut original = task("Buy milk", TaskStatus::Completed, TaskPriority::Low);
+    original.due_date = None;
+    original.created_at = None;
+    original.updated_at = None;
+
+    assert_eq!(encode_task(&original), "x Buy milk");
+    assert_round_trip(&original);
+  }
+
+  #[test]
+  fn encodes_standard_syntax() {
+    let original = TaskResponse {
+      tags: tags(&["family", "@phone"]),
+      ..task("Call mom", TaskStatus::Pending, TaskPriority::Urgent)
+    };
+    assert_eq!(encode_task(&original), "(A) 2025-01-01 Call mom +family @phone due:2025-01-05");
+
+    let done = task("Call mom", TaskStatus::Completed, TaskPriority::High);
+    assert_eq!(encode_task(&done), "x 2025-01-03 2025-01-01 Call mom due:2025-01-05 pri:B");
+  }
+
+  #[test]
+  fn decodes_lines_written_by_other_tools() {
+    let row = decode_line("(D) 2024-12-30 Review +GarageSale plan @phone due:2025-02-01").unwrap();
+    assert_eq!(row.title.as_deref(), Some("Review plan"));
+    assert_eq!(row.priority, Some(TaskPriority::Low));
+    assert_eq!(row.tags, Some(vec!["GarageSale".to_string(), "@phone".to_string()]));
+    assert_eq!(row.due_date, Some(Utc.with_ymd_and_hms(2025, 2, 1, 0, 0, 0).unwrap()));
+  }
+
+  #[test]
+  fn reports_invalid_due_dates() {
+    assert!(decode_line("Pay rent due:tomorrow").is_err());
+  }
+
+  #[test]
+  fn round_trips_titles_that_look_like_syntax() {
+    let titles = [
+      "x marks the spot",
+      "(A) is not a priority",
+      "2025-02-01 is not a date",
+      "Email @bob about +1 for due:friday",
+      "Set pri:high and status:done",
+      "\\+literal backslash",
+    ];
+    let statuses = [TaskStatus::Pending, TaskStatus::Completed];
+    for title in titles {
+      for status in &statuses {
+        let mut original = task(title, status.clone(), TaskPriority::Medium);
+        assert_round_trip(&original);
+        original.created_at = None;
+        original.updated_at = None;
+        assert_round_trip(&original);
+      }
+    }
+
+    let escaped = task("x +1 @bob", TaskStatus::Pending, TaskPriority::Low);
+    assert_eq!(encode_task(&escaped), "2025-01-01 \\x \\+1 \\@bob due:2025-01-05");
+  }
+
+  #[test]
+  fn keeps_the_spacing_inside_titles() {
+    for title in ["Pay  rent", "Column\taligned   notes", "x  +1   @bob"] {
+      assert_round_trip(&task(title, TaskStatus::Pending, TaskPriority::High));
+      assert_round_trip(&task(title, TaskStatus::Completed, TaskPriority::Low));
+    }
+
+    // Only the ends are trimmed; words around syntax are joined by one space
+    let row = decode_line("(B)   Call   mom  +family   back ").unwrap();
+    assert_eq!(row.title.as_deref(), Some("Call   mom back"));
+  }
+}
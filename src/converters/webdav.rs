@@ -0,0 +1,305 @@
+use quick_xml::{events::Event, name::ResolveResult, NsReader, Reader};
+
+// Namespaces used in every multistatus body
+const NAMESPACES: &str = "xmlns:d=\"DAV:\" \
+xmlns:c=\"urn:ietf:params:xml:ns:caldav\" \
+xmlns:cs=\"http://calendarserver.org/ns/\"";
+
+// The prefixes bound in `NAMESPACES`, used to name pre-rendered properties
+const PREFIXES: [(&str, &str); 3] = [
+  ("d", "DAV:"),
+  ("c", "urn:ietf:params:xml:ns:caldav"),
+  ("cs", "http://calendarserver.org/ns/"),
+];
+
+/// A property name qualified by its namespace, e.g. `DAV:` + `getetag`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropName {
+  pub namespace: String,
+  pub name: String,
+}
+
+impl PropName {
+  // Name of a pre-rendered property such as `<d:getetag>…`
+  fn of(prop: &str) -> Self {
+    let tag = prop.trim_start_matches('<');
+    let tag = &tag[..tag.find(['>', '/', ' ']).unwrap_or(tag.len())];
+    let (prefix, name) = tag.split_once(':').unwrap_or(("", tag));
+    let namespace = PREFIXES.iter()
+      .find(|(known, _)| *known == prefix)
+      .map(|(_, namespace)| namespace.to_string())
+      .unwrap_or_default();
+    Self { namespace, name: name.to_string() }
+  }
+
+  // An empty element, as listed in a 404 propstat
+  fn render(&self) -> String {
+    match PREFIXES.iter().find(|(_, namespace)| *namespace == self.namespace) {
+      Some((prefix, _)) => format!("<{prefix}:{}/>", self.name),
+      None => format!("<{} xmlns=\"{}\"/>", self.name, escape_xml(&self.namespace)),
+    }
+  }
+}
+
+/// What a PROPFIND asks for
+/// An empty body, `<allprop/>` and `<propname/>` all get every property there is
+#[derive(Debug, PartialEq)]
+pub enum PropRequest {
+  All,
+  Named(Vec<PropName>),
+}
+
+// One `<d:response>` entry: a resource and the properties found on it
+// Properties are pre-rendered XML fragments such as `<d:displayname>Tasks</d:displayname>`
+pub struct DavResponse {
+  pub href: String,
+  pub props: Vec<String>,
+  // Asked for but not defined on the resource
+  pub missing: Vec<PropName>,
+  pub found: bool,
+}
+
+impl DavResponse {
+  pub fn new(href: impl Into<String>, props: Vec<String>) -> Self {
+    Self { href: href.into(), props, missing: Vec::new(), found: true }
+  }
+
+  pub fn not_found(href: impl Into<String>) -> Self {
+    Self { href: href.into(), props: Vec::new(), missing: Vec::new(), found: false }
+  }
+
+  /// Keep only the properties `request` names; the others it names are reported missing
+  pub fn select(mut self, request: &PropRequest) -> Self {
+    let PropRequest::Named(names) = request else {
+      return self;
+    };
+    if !self.found {
+      return self;
+    }
+    let present: Vec<PropName> = self.props.iter().map(|prop| PropName::of(prop)).collect();
+    self.props = self.props.into_iter()
+      .zip(&present)
+      .filter(|(_, name)| names.contains(name))
+      .map(|(prop, _)| prop)
+      .collect();
+    self.missing = names.iter()
+      .filter(|name| !present.contains(name))
+      .cloned()
+      .collect();
+    self
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReportKind {
+  CalendarQuery,
+  CalendarMultiget,
+  Other(String),
+}
+
+pub fn multistatus(responses: &[DavResponse]) -> String {
+  let mut body = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus {NAMESPACES}>");
+  for response in responses {
+    body.push_str("<d:response><d:href>");
+    body.push_str(&escape_xml(&response.href));
+    if !response.found {
+      body.push_str("</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>");
+      continue;
+    }
+    body.push_str("</d:href>");
+    // A PROPFIND naming only unknown properties has nothing to put in the 200 propstat
+    if !response.props.is_empty() || response.missing.is_empty() {
+      body.push_str("<d:propstat><d:prop>");
+      for prop in &response.props {
+        body.push_str(prop);
+      }
+      body.push_str("</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>");
+    }
+    if !response.missing.is_empty() {
+      body.push_str("<d:propstat><d:prop>");
+      for name in &response.missing {
+        body.push_str(&name.render());
+      }
+      body.push_str("</d:prop><d:status>HTTP/1.1 404 Not Found</d:status></d:propstat>");
+    }
+    body.push_str("</d:response>");
+  }
+  body.push_str("</d:multistatus>");
+  body
+}
+
+/// Wrap an href in the given property, e.g. `current-user-principal`
+pub fn href_prop(name: &str, href: &str) -> String {
+  format!("<{name}><d:href>{}</d:href></{name}>", escape_xml(href))
+}
+
+pub fn text_prop(name: &str, value: &str) -> String {
+  format!("<{name}>{}</{name}>", escape_xml(value))
+}
+
+pub fn escape_xml(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+/// Name of the root element of a REPORT body
+pub fn report_kind(body: &str) -> Option<ReportKind> {
+  let mut reader = Reader::from_str(body);
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(element)) | Ok(Event::Empty(element)) => {
+        let name = String::from_utf8_lossy(element.local_name().as_ref()).to_string();
+        return Some(match name.as_str() {
+          "calendar-query" => ReportKind::CalendarQuery,
+          "calendar-multiget" => ReportKind::CalendarMultiget,
+          _ => ReportKind::Other(name),
+        });
+      }
+      Ok(Event::Eof) | Err(_) => return None,
+      _ => {}
+    }
+  }
+}
+
+/// The properties a PROPFIND body names in its `<prop>` element
+pub fn requested_props(body: &str) -> PropRequest {
+  let mut reader = NsReader::from_str(body);
+  let mut depth = 0;
+  // Depth of the children of `<prop>`, once inside it
+  let mut prop_depth = None;
+  let mut names = Vec::new();
+
+  loop {
+    match reader.read_resolved_event() {
+      Ok((namespace, Event::Start(element))) => {
+        let name = prop_name(namespace, element.local_name().as_ref());
+        depth += 1;
+        if prop_depth == Some(depth - 1) {
+          names.push(name);
+        } else if prop_depth.is_none() && name.namespace == "DAV:" && name.name == "prop" {
+          prop_depth = Some(depth);
+        }
+      }
+      Ok((namespace, Event::Empty(element))) if prop_depth == Some(depth) => {
+        names.push(prop_name(namespace, element.local_name().as_ref()));
+      }
+      Ok((_, Event::End(_))) => {
+        depth -= 1;
+        if prop_depth.is_some_and(|inside| depth < inside) {
+          return PropRequest::Named(names);
+        }
+      }
+      Ok((_, Event::Eof)) | Err(_) => return PropRequest::All,
+      _ => {}
+    }
+  }
+}
+
+fn prop_name(namespace: ResolveResult, name: &[u8]) -> PropName {
+  let namespace = match namespace {
+    ResolveResult::Bound(namespace) => String::from_utf8_lossy(namespace.as_ref()).to_string(),
+    _ => String::new(),
+  };
+  PropName { namespace, name: String::from_utf8_lossy(name).to_string() }
+}
+
+/// Text of every `<href>` element, as sent in a calendar-multiget REPORT
+pub fn extract_hrefs(body: &str) -> Vec<String> {
+  let mut reader = Reader::from_str(body);
+  let mut hrefs = Vec::new();
+  let mut in_href = false;
+
+  loop {
+    match reader.read_event() {
+      Ok(Event::Start(element)) => in_href = element.local_name().as_ref() == b"href",
+      Ok(Event::End(_)) => in_href = false,
+      Ok(Event::Text(text)) if in_href => {
+        if let Ok(href) = text.unescape() {
+          hrefs.push(href.trim().to_string());
+        }
+      }
+      Ok(Event::Eof) | Err(_) => break,
+      _ => {}
+    }
+  }
+
+  hrefs
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_found_and_missing_resources() {
+    let body = multistatus(&[
+      DavResponse::new("/dav/a b.ics", vec![text_prop("d:getetag", "\"1\""), href_prop("d:owner", "/p/<me>/")]),
+      DavResponse::not_found("/dav/gone.ics"),
+    ]);
+    assert!(body.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<d:multistatus xmlns:d=\"DAV:\""));
+    assert!(body.contains(
+      "<d:response><d:href>/dav/a b.ics</d:href><d:propstat><d:prop>\
+      <d:getetag>&quot;1&quot;</d:getetag><d:owner><d:href>/p/&lt;me&gt;/</d:href></d:owner>\
+      </d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+    ));
+    assert!(body.contains("<d:response><d:href>/dav/gone.ics</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>"));
+    assert!(body.ends_with("</d:multistatus>"));
+  }
+
+  #[test]
+  fn reads_the_report_kind_whatever_the_prefix() {
+    let query = r#"<?xml version="1.0"?><C:calendar-query xmlns:C="urn:ietf:params:xml:ns:caldav"/>"#;
+    assert_eq!(report_kind(query), Some(ReportKind::CalendarQuery));
+    let multiget = r#"<calendar-multiget xmlns="urn:ietf:params:xml:ns:caldav"><prop/></calendar-multiget>"#;
+    assert_eq!(report_kind(multiget), Some(ReportKind::CalendarMultiget));
+    assert_eq!(report_kind("<d:sync-collection xmlns:d=\"DAV:\"/>"), Some(ReportKind::Other("sync-collection".to_string())));
+    assert_eq!(report_kind(""), None);
+  }
+
+  #[test]
+  fn collects_multiget_hrefs() {
+    let body = r#"<c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+      <d:prop><d:getetag/><c:calendar-data/></d:prop>
+      <d:href> /dav/calendars/u/tasks/a.ics </d:href>
+      <d:href>/dav/calendars/u/tasks/b%20c.ics?x=1&amp;y=2</d:href>
+    </c:calendar-multiget>"#;
+    assert_eq!(extract_hrefs(body), vec![
+      "/dav/calendars/u/tasks/a.ics".to_string(),
+      "/dav/calendars/u/tasks/b%20c.ics?x=1&y=2".to_string(),
+    ]);
+  }
+
+  #[test]
+  fn reads_the_properties_a_propfind_names() {
+    assert_eq!(requested_props(""), PropRequest::All);
+    assert_eq!(requested_props(r#"<d:propfind xmlns:d="DAV:"><d:allprop/></d:propfind>"#), PropRequest::All);
+
+    let body = r#"<propfind xmlns="DAV:" xmlns:CS="http://calendarserver.org/ns/">
+      <prop><getetag/><CS:getctag/><resourcetype><collection/></resourcetype><x:color xmlns:x="urn:x"/></prop>
+    </propfind>"#;
+    let name = |namespace: &str, name: &str| PropName { namespace: namespace.to_string(), name: name.to_string() };
+    assert_eq!(requested_props(body), PropRequest::Named(vec![
+      name("DAV:", "getetag"),
+      name("http://calendarserver.org/ns/", "getctag"),
+      name("DAV:", "resourcetype"),
+      name("urn:x", "color"),
+    ]));
+  }
+
+  #[test]
+  fn reports_unknown_properties_as_missing() {
+    let request = requested_props(r#"<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:quota-used-bytes/></d:prop></d:propfind>"#);
+    let response = DavResponse::new("/r", vec![
+      "<d:resourcetype/>".to_string(),
+      text_prop("d:getetag", "\"1\""),
+    ]).select(&request);
+    assert_eq!(response.props, vec![text_prop("d:getetag", "\"1\"")]);
+
+    let body = multistatus(&[response]);
+    assert!(body.contains("<d:prop><d:getetag>&quot;1&quot;</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status>"));
+    assert!(body.contains("<d:prop><d:quota-used-bytes/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status>"));
+  }
+}
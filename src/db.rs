@@ -1,6 +1,31 @@
-use mongodb::{Client, Database};
-use std::env;
+use mongodb::{
+  bson::doc,
+  error::{ErrorKind, WriteFailure},
+  options::{Collation, CollationStrength, IndexOptions},
+  Client, Database, IndexModel,
+};
+use std::{env, sync::Arc, time::Duration};
 use dotenvy::dotenv;
+use crate::{
+  config::{AppConfig, RevocationBackend},
+  auth::{hash_password, oidc::OidcClient, JwtKeys, MemoryRevocationStore, MongoRevocationStore, RevocationStore},
+  mailer::{self, Mailer},
+  models::{Permission, Role, ADMIN_ROLE, USER_ROLE},
+};
+
+/// Compares email addresses without regard to case; lookups that must match the unique
+/// email index use it too
+pub fn email_collation() -> Collation {
+  Collation::builder().locale("en").strength(CollationStrength::Secondary).build()
+}
+
+/// Whether a write was refused by a unique index
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+  matches!(
+    error.kind.as_ref(),
+    ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+  )
+}
 
 pub async fn get_database() -> Result<Database, mongodb::error::Error> {
   dotenv().ok();
@@ -17,13 +42,257 @@ pub async fn get_database() -> Result<Database, mongodb::error::Error> {
   Ok(db)
 }
 
+/// Seed built-in roles, convert numeric roles from before RBAC and hash passwords stored in
+/// plain text by older versions of `update_user`; safe to run on every start
+/// `admin` always gets every permission, including ones added since it was seeded
+pub async fn run_migrations(db: &Database, config: &AppConfig) -> Result<(), mongodb::error::Error> {
+  let roles = db.collection::<Role>("roles");
+  for role in Role::built_in() {
+    let role = mongodb::bson::to_document(&role)?;
+    roles.update_one(doc! { "name": role.get_str("name").unwrap_or_default() }, doc! { "$setOnInsert": role })
+      .upsert(true)
+      .await?;
+  }
+  let all_permissions = mongodb::bson::to_bson(&Permission::ALL)?;
+  roles.update_one(doc! { "name": ADMIN_ROLE }, doc! { "$set": { "permissions": all_permissions } }).await?;
+
+  // Role 0 used to mean admin; every other number was a regular user
+  let result = db.collection::<mongodb::bson::Document>("users")
+    .update_many(
+      doc! { "role": { "$type": "number" } },
+      vec![doc! { "$set": { "role": {
+        "$cond": [{ "$eq": ["$role", 0] }, ADMIN_ROLE, USER_ROLE]
+      } } }],
+    )
+    .await?;
+  if result.modified_count > 0 {
+    tracing::info!(count = result.modified_count, "migrated numeric user roles");
+  }
+
+  // Tasks from before status timestamps were tracked; the last update is the best guess,
+  // flagged so the stats leave these tasks out of lead and cycle time
+  let tasks = db.collection::<mongodb::bson::Document>("tasks");
+  for (status, field) in [("inprogress", "started_at"), ("completed", "completed_at")] {
+    let result = tasks
+      .update_many(
+        doc! { "status": status, field: null, "updated_at": { "$ne": null } },
+        vec![doc! { "$set": { field: "$updated_at", "status_timestamps_estimated": true } }],
+      )
+      .await?;
+    if result.modified_count > 0 {
+      tracing::warn!(count = result.modified_count, field, "estimated task status timestamps from their last update");
+    }
+  }
+
+  // Every real hash is a PHC (`$argon2id$...`) or bcrypt (`$2b$...`) string
+  let users = db.collection::<mongodb::bson::Document>("users");
+  let mut unhashed = users
+    .find(doc! { "password": { "$not": { "$regex": "^\\$" } } })
+    .projection(doc! { "password": 1 })
+    .await?;
+  while unhashed.advance().await? {
+    let user = unhashed.deserialize_current()?;
+    let (Ok(id), Ok(password)) = (user.get_object_id("_id"), user.get_str("password")) else {
+      continue;
+    };
+    match hash_password(&config.password_hash, password.to_string()).await {
+      Ok(hash) => {
+        users.update_one(doc! { "_id": id, "password": password }, doc! { "$set": { "password": hash } }).await?;
+        tracing::warn!(user_id = %id, "hashed a password stored in plain text");
+      }
+      Err(e) => tracing::error!(error = %e.message, user_id = %id, "failed to hash plain-text password"),
+    }
+  }
+
+  Ok(())
+}
+
+/// Create the indexes the handlers rely on; safe to run on every start
+pub async fn ensure_indexes(db: &Database) -> Result<(), mongodb::error::Error> {
+  let refresh_tokens = db.collection::<mongodb::bson::Document>("refresh_tokens");
+  refresh_tokens.create_indexes([
+    IndexModel::builder()
+      .keys(doc! { "token_hash": 1 })
+      .options(IndexOptions::builder().unique(true).build())
+      .build(),
+    IndexModel::builder()
+      .keys(doc! { "family_id": 1 })
+      .build(),
+    // Expired refresh tokens are removed by MongoDB
+    IndexModel::builder()
+      .keys(doc! { "expires_at": 1 })
+      .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+      .build(),
+  ]).await?;
+
+  db.collection::<mongodb::bson::Document>("sessions")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "user_id": 1 })
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build(),
+    ])
+    .await?;
+
+  let action_tokens = db.collection::<mongodb::bson::Document>("action_tokens");
+  action_tokens.create_indexes([
+    IndexModel::builder()
+      .keys(doc! { "token_hash": 1 })
+      .options(IndexOptions::builder().unique(true).build())
+      .build(),
+    IndexModel::builder()
+      .keys(doc! { "expires_at": 1 })
+      .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+      .build(),
+  ]).await?;
+
+  db.collection::<mongodb::bson::Document>("api_tokens")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "token_hash": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "user_id": 1 })
+        .build(),
+    ])
+    .await?;
+
+  db.collection::<mongodb::bson::Document>("invitations")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "token_hash": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "email": 1 })
+        .build(),
+    ])
+    .await?;
+
+  // Calendar feeds look their owner up by token on every poll
+  db.collection::<mongodb::bson::Document>("users")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "calendar_token_hash": 1 })
+        .options(IndexOptions::builder().unique(true).sparse(true).build())
+        .build(),
+    ])
+    .await?;
+
+  // One live account per address; deleted accounts keep theirs until they are anonymised
+  let email_index = IndexModel::builder()
+    .keys(doc! { "email": 1 })
+    .options(IndexOptions::builder()
+      .name("email_unique".to_string())
+      .unique(true)
+      .collation(email_collation())
+      .partial_filter_expression(doc! { "deleted": false })
+      .build())
+    .build();
+  if let Err(e) = db.collection::<mongodb::bson::Document>("users").create_index(email_index).await {
+    // Older data may hold the same address twice; keep serving and let an operator merge them
+    tracing::error!(error = %e, "failed to create the unique email index; check for duplicate addresses");
+  }
+
+  db.collection::<mongodb::bson::Document>("roles")
+    .create_index(
+      IndexModel::builder()
+        .keys(doc! { "name": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+    )
+    .await?;
+
+  db.collection::<mongodb::bson::Document>("oidc_login_states")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "state": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build(),
+    ])
+    .await?;
+
+  db.collection::<mongodb::bson::Document>("rate_limits")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "key": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build(),
+    ])
+    .await?;
+
+  db.collection::<mongodb::bson::Document>("login_attempts")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "key": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build(),
+    ])
+    .await?;
+
+  db.collection::<mongodb::bson::Document>("reminder_deliveries")
+    .create_indexes([
+      IndexModel::builder()
+        .keys(doc! { "user_id": 1, "date": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build(),
+      IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build(),
+    ])
+    .await?;
+
+  db.collection::<mongodb::bson::Document>("revoked_tokens")
+    .create_index(
+      IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build(),
+    )
+    .await?;
+
+  Ok(())
+}
+
 #[derive(Clone)]
 pub struct AppState {
   pub db: Database,
+  pub config: AppConfig,
+  pub jwt: Arc<JwtKeys>,
+  pub revocations: Arc<dyn RevocationStore>,
+  pub mailer: Arc<dyn Mailer>,
+  // Present when an OpenID Connect provider is configured
+  pub oidc: Option<Arc<OidcClient>>,
 }
 
 impl AppState {
-  pub fn new(db: Database) -> Self {
-      Self { db }
+  pub fn new(db: Database, config: AppConfig) -> Self {
+      let revocations: Arc<dyn RevocationStore> = match config.revocation_store {
+        RevocationBackend::Mongo => Arc::new(MongoRevocationStore::new(&db)),
+        RevocationBackend::Memory => Arc::new(MemoryRevocationStore::default()),
+      };
+      let jwt = JwtKeys::from_config(&config.jwt)
+        .expect("Invalid JWT key configuration");
+      let mailer = mailer::from_config(&config)
+        .expect("Invalid mail configuration");
+      let oidc = config.oidc.clone().map(|oidc| Arc::new(OidcClient::new(oidc)));
+      Self { db, config, jwt: Arc::new(jwt), revocations, mailer, oidc }
   }
-}
\ No newline at end of file
+}
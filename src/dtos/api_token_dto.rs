@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::models::{ApiToken, Permission};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenRequest {
+  pub name: String,
+  pub scopes: Vec<Permission>,
+  // Never expires when omitted
+  pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiTokenResponse {
+  pub id: String,
+  pub name: String,
+  pub token_hint: String,
+  pub scopes: Vec<Permission>,
+  pub expires_at: Option<DateTime<Utc>>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+// Only returned at creation: the token cannot be recovered afterwards
+#[derive(Debug, Serialize)]
+pub struct CreatedApiTokenResponse {
+  pub token: String,
+  #[serde(flatten)]
+  pub details: ApiTokenResponse,
+}
+
+impl From<ApiToken> for ApiTokenResponse {
+  fn from(token: ApiToken) -> Self {
+    Self {
+      id: token.id.map(|id| id.to_hex()).unwrap_or_default(),
+      name: token.name,
+      token_hint: token.token_hint,
+      scopes: token.scopes,
+      expires_at: token.expires_at,
+      last_used_at: token.last_used_at,
+      created_at: token.created_at,
+    }
+  }
+}
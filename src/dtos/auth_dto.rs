@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::dtos::UserResponse;
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+  pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+  // Also revoke this refresh token (and every token rotated from it)
+  pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+  pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+  pub token: String,
+  pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmOidcLinkRequest {
+  pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageResponse {
+  pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationRequest {
+  pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+  pub mfa_token: String,
+  // A TOTP code or an unused recovery code
+  pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCodeRequest {
+  pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaSetupResponse {
+  pub secret: String,
+  pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+  // Shown once; only their hashes are kept
+  pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+  pub id: String,
+  pub user_agent: Option<String>,
+  pub ip: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub last_seen_at: DateTime<Utc>,
+  // Whether this is the session making the request
+  pub current: bool,
+}
+
+// Token for acting as another user; its `act` claim names the admin, for a banner
+#[derive(Debug, Serialize)]
+pub struct ImpersonationResponse {
+  pub token: String,
+  pub expires_in: i64,
+  pub user: UserResponse,
+  pub impersonator: ImpersonatorResponse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImpersonatorResponse {
+  pub id: String,
+  pub email: String,
+}
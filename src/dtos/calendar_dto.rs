@@ -0,0 +1,6 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct CalendarFeedResponse {
+  pub feed_url: String,
+}
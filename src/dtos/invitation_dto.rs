@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::models::Invitation;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitationRequest {
+  pub email: String,
+  pub role: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcceptInvitationRequest {
+  pub token: String,
+  pub full_name: String,
+  pub password: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvitationStatus {
+  Pending,
+  Accepted,
+  Revoked,
+  Expired,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvitationResponse {
+  pub id: String,
+  pub email: String,
+  pub role: String,
+  pub invited_by: String,
+  pub status: InvitationStatus,
+  pub expires_at: DateTime<Utc>,
+  pub accepted_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+impl From<Invitation> for InvitationResponse {
+  fn from(invitation: Invitation) -> Self {
+    let status = if invitation.accepted_at.is_some() {
+      InvitationStatus::Accepted
+    } else if invitation.revoked_at.is_some() {
+      InvitationStatus::Revoked
+    } else if invitation.expires_at <= Utc::now() {
+      InvitationStatus::Expired
+    } else {
+      InvitationStatus::Pending
+    };
+
+    Self {
+      id: invitation.id.map(|id| id.to_hex()).unwrap_or_default(),
+      email: invitation.email,
+      role: invitation.role,
+      invited_by: invitation.invited_by.to_hex(),
+      status,
+      expires_at: invitation.expires_at,
+      accepted_at: invitation.accepted_at,
+      created_at: invitation.created_at,
+    }
+  }
+}
@@ -1,5 +1,17 @@
 pub mod user_dto;
 pub mod task_dto;
+pub mod calendar_dto;
+pub mod stats_dto;
+pub mod auth_dto;
+pub mod role_dto;
+pub mod api_token_dto;
+pub mod invitation_dto;
 
 pub use user_dto::*;
-pub use task_dto::*;
\ No newline at end of file
+pub use task_dto::*;
+pub use calendar_dto::*;
+pub use stats_dto::*;
+pub use auth_dto::*;
+pub use role_dto::*;
+pub use api_token_dto::*;
+pub use invitation_dto::*;
\ No newline at end of file
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::models::{Permission, Role};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRoleRequest {
+  pub name: String,
+  pub description: Option<String>,
+  pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRoleRequest {
+  pub description: Option<String>,
+  pub permissions: Option<Vec<Permission>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleResponse {
+  pub id: String,
+  pub name: String,
+  pub description: Option<String>,
+  pub permissions: Vec<Permission>,
+  pub built_in: bool,
+  pub created_at: Option<DateTime<Utc>>,
+  pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<Role> for RoleResponse {
+  fn from(role: Role) -> Self {
+    Self {
+      id: role.id.map(|id| id.to_hex()).unwrap_or_default(),
+      name: role.name,
+      description: role.description,
+      permissions: role.permissions,
+      built_in: role.built_in,
+      created_at: role.created_at,
+      updated_at: role.updated_at,
+    }
+  }
+}
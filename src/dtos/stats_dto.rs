@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsBucket {
+  #[default]
+  Day,
+  Week,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThroughputPoint {
+  // `YYYY-MM-DD` in the user's time zone; for weekly buckets, the first day of the week
+  // as set by the user's `week_start`
+  pub period: String,
+  pub created: i64,
+  pub completed: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BurndownPoint {
+  pub date: String,
+  pub remaining: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+  pub user_id: String,
+  pub from: DateTime<Utc>,
+  pub to: DateTime<Utc>,
+  pub bucket: StatsBucket,
+  pub by_status: BTreeMap<String, i64>,
+  pub by_priority: BTreeMap<String, i64>,
+  pub overdue: i64,
+  pub throughput: Vec<ThroughputPoint>,
+  // Created -> completed
+  pub average_lead_time_hours: Option<f64>,
+  // Started -> completed
+  pub average_cycle_time_hours: Option<f64>,
+  pub burndown: Vec<BurndownPoint>,
+}
@@ -1,7 +1,6 @@
 use serde::{Deserialize, Serialize};
-use mongodb::bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
-use crate::models::{TaskStatus, TaskPriority};
+use crate::models::{Task, TaskStatus, TaskPriority};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateTaskRequest {
@@ -10,9 +9,21 @@ pub struct CreateTaskRequest {
   pub description: Option<String>,
   #[serde(default)] 
   pub status: TaskStatus,
-  #[serde(default)]
-  pub priority: TaskPriority,
+  // Falls back to the creator's `default_priority` preference
+  pub priority: Option<TaskPriority>,
   pub due_date: Option<DateTime<Utc>>,
+  #[serde(default)]
+  pub tags: Vec<String>,
+}
+
+// Due-date windows for task lists, in the caller's time zone
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DueWindow {
+  // Due on an earlier day and not completed or cancelled
+  Overdue,
+  Today,
+  Week,
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,6 +33,7 @@ pub struct UpdateTaskRequest {
   pub status: Option<TaskStatus>,
   pub priority: Option<TaskPriority>,
   pub due_date: Option<DateTime<Utc>>,
+  pub tags: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -33,8 +45,75 @@ pub struct TaskResponse {
   pub status: TaskStatus,
   pub priority: TaskPriority,
   pub due_date: Option<DateTime<Utc>>,
+  pub tags: Vec<String>,
   pub created_by: Option<String>,
   pub updated_by: Option<String>,
   pub created_at: Option<DateTime<Utc>>,
   pub updated_at: Option<DateTime<Utc>>,
-}
\ No newline at end of file
+}
+
+impl From<Task> for TaskResponse {
+  fn from(task: Task) -> Self {
+    Self {
+      id: task.id.map(|id| id.to_hex()).unwrap_or_default(),
+      user_id: task.user_id.to_hex(),
+      title: task.title,
+      description: task.description,
+      status: task.status,
+      priority: task.priority,
+      due_date: task.due_date,
+      tags: task.tags,
+      created_by: task.created_by.map(|id| id.to_hex()),
+      updated_by: task.updated_by.map(|id| id.to_hex()),
+      created_at: task.created_at,
+      updated_at: task.updated_at,
+    }
+  }
+}
+
+// One row of an import file (CSV, JSON or NDJSON)
+// Unknown columns such as `id` or `created_at` from an export are ignored
+#[derive(Debug, Deserialize)]
+pub struct ImportTaskRow {
+  pub title: Option<String>,
+  pub description: Option<String>,
+  pub status: Option<TaskStatus>,
+  pub priority: Option<TaskPriority>,
+  pub due_date: Option<DateTime<Utc>>,
+  pub tags: Option<Vec<String>>,
+}
+
+impl ImportTaskRow {
+  /// Validate the row and map it onto a `CreateTaskRequest` owned by `user_id`
+  pub fn into_create_request(self, user_id: String) -> Result<CreateTaskRequest, String> {
+    let title = self.title
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+        .ok_or_else(|| "Title is required".to_string())?;
+
+    Ok(CreateTaskRequest {
+      user_id,
+      title,
+      description: self.description.filter(|description| !description.is_empty()),
+      status: self.status.unwrap_or_default(),
+      priority: self.priority,
+      due_date: self.due_date,
+      tags: self.tags.unwrap_or_default(),
+    })
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRowError {
+  pub row: usize,
+  pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportTasksResponse {
+  pub dry_run: bool,
+  pub total: usize,
+  pub imported: usize,
+  pub failed: usize,
+  pub errors: Vec<ImportRowError>,
+}
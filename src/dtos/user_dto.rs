@@ -1,12 +1,23 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use crate::models::{TaskPriority, User, WeekStart};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
   pub full_name: String,
   pub email: String,
   pub password: String,
-  pub role: i16,
+  pub role: String,
+}
+
+// Self-service signup; deliberately has no `role`, and unknown fields are refused
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterRequest {
+  pub full_name: String,
+  pub email: String,
+  pub password: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -14,7 +25,69 @@ pub struct UpdateUserRequest {
   pub full_name: Option<String>,
   pub email: Option<String>,
   pub password: Option<String>,
-  pub role: Option<i16>,
+  pub role: Option<String>,
+}
+
+// Fields a user may change on their own account; `role`, `email` and `password` are refused
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProfileRequest {
+  pub full_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+  pub current_password: String,
+  pub new_password: String,
+  // Personal access tokens are revoked along with other sessions unless this is set
+  #[serde(default)]
+  pub keep_api_tokens: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmailRequest {
+  pub current_password: String,
+  pub new_email: String,
+}
+
+// Omitted fields keep their current value
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdatePreferencesRequest {
+  pub timezone: Option<String>,
+  pub locale: Option<String>,
+  pub week_start: Option<WeekStart>,
+  pub default_priority: Option<TaskPriority>,
+  pub notifications: Option<UpdateNotificationsRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateNotificationsRequest {
+  pub due_reminders: Option<bool>,
+  pub reminder_hour: Option<u8>,
+  pub security_alerts: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskDisposition {
+  #[default]
+  Delete,
+  Reassign,
+}
+
+// Query string of account deletion: `?tasks=reassign&reassign_to=<user id>`
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountQuery {
+  #[serde(default)]
+  pub tasks: TaskDisposition,
+  pub reassign_to: Option<ObjectId>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMyAccountRequest {
+  pub current_password: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -22,13 +95,30 @@ pub struct UserResponse {
   pub id: String,
   pub full_name: String,
   pub email: String,
-  pub role: i16,
+  pub verified: bool,
+  pub role: String,
   pub created_by: Option<String>,
   pub updated_by: Option<String>,
   pub created_at: Option<DateTime<Utc>>,
   pub updated_at: Option<DateTime<Utc>>,
 }
 
+impl From<User> for UserResponse {
+  fn from(user: User) -> Self {
+    Self {
+      id: user.id.map(|id| id.to_hex()).unwrap_or_default(),
+      full_name: user.full_name,
+      email: user.email,
+      verified: user.verified,
+      role: user.role,
+      created_by: user.created_by.map(|id| id.to_hex()),
+      updated_by: user.updated_by.map(|id| id.to_hex()),
+      created_at: user.created_at,
+      updated_at: user.updated_at,
+    }
+  }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
   pub email: String,
@@ -38,4 +128,23 @@ pub struct LoginRequest {
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
   pub token: String,
+  pub refresh_token: String,
+  // Lifetime of `token`, in seconds
+  pub expires_in: i64,
+}
+
+// Returned by `login` instead of tokens when the account has 2FA enabled
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+  pub mfa_required: bool,
+  // Exchange it with a code at `POST /api/auth/mfa`
+  pub mfa_token: String,
+  pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+  Tokens(LoginResponse),
+  MfaRequired(MfaChallengeResponse),
 }
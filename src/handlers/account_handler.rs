@@ -0,0 +1,216 @@
+use axum::{
+  extract::{Path, Query, State},
+  http::{header, StatusCode},
+  response::{IntoResponse, Json, Response},
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use std::io::{Cursor, Write};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+use crate::{
+  accounts::{delete_account, TaskPolicy},
+  db::AppState,
+  auth::{verify_current_password, AuthenticatedUser, ClientInfo, PermissionGuard},
+  models::{ApiToken, Permission, SecurityEvent, SecurityEventKind, Session, Task, User},
+  dtos::{
+    ApiTokenResponse, DeleteAccountQuery, DeleteMyAccountRequest, SessionResponse, TaskDisposition, TaskResponse,
+    UserResponse,
+  },
+  utils::{ResultExt, AppError},
+};
+use crate::handlers::ensure_can_manage;
+
+fn task_policy(query: &DeleteAccountQuery) -> Result<TaskPolicy, AppError> {
+  match (query.tasks, query.reassign_to) {
+    (TaskDisposition::Delete, None) => Ok(TaskPolicy::Delete),
+    (TaskDisposition::Reassign, Some(new_owner)) => Ok(TaskPolicy::ReassignTo(new_owner)),
+    (TaskDisposition::Reassign, None) => Err(AppError::bad_request("`reassign_to` is required to reassign tasks")),
+    (TaskDisposition::Delete, Some(_)) => Err(AppError::bad_request("`reassign_to` only applies with `tasks=reassign`")),
+  }
+}
+
+pub async fn delete_user(
+  State(app_state): State<AppState>,
+  admin: AuthenticatedUser,
+  Path(id): Path<ObjectId>,
+  Query(query): Query<DeleteAccountQuery>,
+) -> Result<StatusCode, AppError> {
+  let admin_id = ObjectId::parse_str(&admin.user_id)
+      .bad_request("Invalid user ID")?;
+  let target = app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+  ensure_can_manage(&app_state, &admin, &target).await?;
+  delete_account(&app_state, id, task_policy(&query)?, admin_id).await?;
+
+  tracing::info!(user_id = %id, admin_id = %admin_id, "account deleted by admin");
+  Ok(StatusCode::NO_CONTENT)
+}
+
+// Closing one's own account; handing tasks to someone else needs `tasks:write:any`
+pub async fn delete_me(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  client: ClientInfo,
+  Query(query): Query<DeleteAccountQuery>,
+  Json(payload): Json<DeleteMyAccountRequest>,
+) -> Result<StatusCode, AppError> {
+  user.require_session()?;
+  let policy = task_policy(&query)?;
+  if matches!(policy, TaskPolicy::ReassignTo(_)) {
+    user.require_permission(Permission::TasksWriteAny)?;
+  }
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let account = app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+  verify_current_password(&app_state, &account, payload.current_password, &client).await?;
+
+  delete_account(&app_state, user_id, policy, user_id).await?;
+
+  tracing::info!(user_id = %user_id, "account deleted by its owner");
+  Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+struct ExportedSecurityEvent {
+  kind: SecurityEventKind,
+  ip: Option<String>,
+  detail: Option<String>,
+  created_at: DateTime<Utc>,
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, AppError> {
+  serde_json::to_vec_pretty(value).internal_error("Failed to encode export")
+}
+
+// One JSON file per kind of data, compressed into a single archive
+fn build_archive(files: &[(&str, Vec<u8>)]) -> zip::result::ZipResult<Vec<u8>> {
+  let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+  let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+  for (name, contents) in files {
+    archive.start_file(*name, options)?;
+    archive.write_all(contents)?;
+  }
+  Ok(archive.finish()?.into_inner())
+}
+
+/// Everything stored about the caller, as a ZIP of JSON files
+pub async fn export_me(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Response, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let account = app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+  let preferences = account.preferences.clone();
+
+  let tasks: Vec<Task> = app_state.db.collection::<Task>("tasks")
+    .find(doc! { "user_id": user_id, "deleted": false })
+    .sort(doc! { "created_at": 1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+  let tasks: Vec<TaskResponse> = tasks.into_iter().map(TaskResponse::from).collect();
+
+  let sessions: Vec<Session> = app_state.db.collection::<Session>("sessions")
+    .find(doc! { "user_id": user_id })
+    .sort(doc! { "created_at": 1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+  let sessions: Vec<SessionResponse> = sessions.into_iter()
+    .map(|session| SessionResponse {
+      id: session.id.map(|id| id.to_hex()).unwrap_or_default(),
+      current: session.id.is_some() && session.id == user.session_id,
+      user_agent: session.user_agent,
+      ip: session.ip,
+      created_at: session.created_at,
+      last_seen_at: session.last_seen_at,
+    })
+    .collect();
+
+  let api_tokens: Vec<ApiToken> = app_state.db.collection::<ApiToken>("api_tokens")
+    .find(doc! { "user_id": user_id })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+  let api_tokens: Vec<ApiTokenResponse> = api_tokens.into_iter().map(ApiTokenResponse::from).collect();
+
+  let events: Vec<SecurityEvent> = app_state.db.collection::<SecurityEvent>("security_events")
+    .find(doc! { "user_id": user_id })
+    .sort(doc! { "created_at": 1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+  let events: Vec<ExportedSecurityEvent> = events.into_iter()
+    .map(|event| ExportedSecurityEvent {
+      kind: event.kind,
+      ip: event.ip,
+      detail: event.detail,
+      created_at: event.created_at,
+    })
+    .collect();
+
+  let files = [
+    ("profile.json", to_json(&UserResponse::from(account))?),
+    ("preferences.json", to_json(&preferences)?),
+    ("tasks.json", to_json(&tasks)?),
+    ("sessions.json", to_json(&sessions)?),
+    ("api_tokens.json", to_json(&api_tokens)?),
+    ("security_events.json", to_json(&events)?),
+  ];
+  let archive = tokio::task::spawn_blocking(move || build_archive(&files))
+    .await
+    .internal_error("Failed to build export")?
+    .internal_error("Failed to build export")?;
+
+  let disposition = format!("attachment; filename=\"export-{}.zip\"", Utc::now().format("%Y-%m-%d"));
+  Ok((
+    [
+      (header::CONTENT_TYPE, "application/zip".to_string()),
+      (header::CONTENT_DISPOSITION, disposition),
+    ],
+    archive,
+  ).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+
+  #[test]
+  fn archive_contains_every_file() {
+    let files = [("profile.json", b"{}".to_vec()), ("tasks.json", b"[]".to_vec())];
+    let bytes = build_archive(&files).unwrap();
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(archive.len(), 2);
+    let mut tasks = String::new();
+    archive.by_name("tasks.json").unwrap().read_to_string(&mut tasks).unwrap();
+    assert_eq!(tasks, "[]");
+  }
+}
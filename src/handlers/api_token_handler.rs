@@ -0,0 +1,112 @@
+use axum::{
+  extract::{State, Path},
+  http::StatusCode,
+  response::Json,
+};
+use futures_util::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use chrono::{Duration, Utc};
+use crate::{
+  db::AppState,
+  auth::{generate_api_token, AuthenticatedUser, PermissionGuard},
+  models::ApiToken,
+  dtos::{ApiTokenResponse, CreateApiTokenRequest, CreatedApiTokenResponse},
+  utils::{hash_token, ResultExt, AppError},
+};
+
+const MAX_NAME_LENGTH: usize = 100;
+
+pub async fn create_api_token(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Json(payload): Json<CreateApiTokenRequest>,
+) -> Result<Json<CreatedApiTokenResponse>, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  let name = payload.name.trim().to_string();
+  if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+    return Err(AppError::bad_request(format!("Token name must be 1 to {MAX_NAME_LENGTH} characters")));
+  }
+  if payload.scopes.is_empty() {
+    return Err(AppError::bad_request("A token needs at least one scope"));
+  }
+  // A token cannot be granted more than its owner holds
+  for scope in &payload.scopes {
+    user.require_permission(*scope)?;
+  }
+
+  let now = Utc::now();
+  let expires_at = match payload.expires_in_days {
+    Some(days) if days <= 0 => return Err(AppError::bad_request("`expires_in_days` must be positive")),
+    Some(days) => Some(now + Duration::try_days(days)
+        .ok_or_else(|| AppError::bad_request("`expires_in_days` is too large"))?),
+    None => None,
+  };
+
+  let token = generate_api_token();
+  let mut record = ApiToken {
+    id: None,
+    user_id,
+    name,
+    token_hash: hash_token(&token),
+    token_hint: token[token.len() - 4..].to_string(),
+    scopes: payload.scopes,
+    expires_at,
+    last_used_at: None,
+    created_at: now,
+  };
+
+  let result = app_state.db.collection::<ApiToken>("api_tokens")
+    .insert_one(&record)
+    .await
+    .internal_error("Failed to store API token")?;
+  record.id = result.inserted_id.as_object_id();
+
+  Ok(Json(CreatedApiTokenResponse {
+    token,
+    details: ApiTokenResponse::from(record),
+  }))
+}
+
+pub async fn list_api_tokens(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Json<Vec<ApiTokenResponse>>, AppError> {
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  let tokens: Vec<ApiToken> = app_state.db.collection::<ApiToken>("api_tokens")
+    .find(doc! { "user_id": user_id })
+    .sort(doc! { "created_at": -1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+
+  Ok(Json(tokens.into_iter().map(ApiTokenResponse::from).collect()))
+}
+
+pub async fn revoke_api_token(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Path(id): Path<ObjectId>,
+) -> Result<StatusCode, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  let result = app_state.db.collection::<ApiToken>("api_tokens")
+    .delete_one(doc! { "_id": id, "user_id": user_id })
+    .await
+    .internal_error("Failed to revoke API token")?;
+  if result.deleted_count == 0 {
+    return Err(AppError::not_found("API token not found"));
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
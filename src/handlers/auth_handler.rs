@@ -0,0 +1,485 @@
+use axum::{
+  extract::{Query, State},
+  http::{header, StatusCode},
+  response::{IntoResponse, Json},
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use serde::Deserialize;
+use crate::{
+  db::{email_collation, AppState},
+  auth::{
+    check_password_policy, create_verification_token, end_session, enforce_rate_limit, hash_password, issue_tokens, start_session, verify_verification_token,
+    AuthenticatedUser, ClientInfo, EmailVerificationClaims, PermissionGuard,
+  },
+  models::{ActionToken, ApiToken, RefreshToken, Session, TokenPurpose, User},
+  dtos::{
+    ForgotPasswordRequest, LoginResponse, LogoutRequest, MessageResponse,
+    RefreshTokenRequest, ResendVerificationRequest, ResetPasswordRequest,
+  },
+  mailer::Email,
+  notifications::{send_security_alert, SecurityAlert},
+  utils::{generate_token, hash_token, ResultExt, AppError},
+};
+
+// Exchange a refresh token for a new access token and a new refresh token
+// A refresh token can be used once; presenting a used one revokes its whole family
+pub async fn refresh_token(
+  State(app_state): State<AppState>,
+  client: ClientInfo,
+  Json(payload): Json<RefreshTokenRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+  let collection = app_state.db.collection::<RefreshToken>("refresh_tokens");
+  let token_hash = hash_token(&payload.refresh_token);
+
+  let now = mongodb::bson::to_bson(&Utc::now())
+      .internal_error("Failed to encode timestamp")?;
+
+  // Claim the token atomically so two concurrent refreshes cannot both succeed
+  let claimed = collection.find_one_and_update(
+      doc! { "token_hash": &token_hash, "used_at": null, "revoked": false },
+      doc! { "$set": { "used_at": now } },
+    )
+    .await
+    .internal_error("Failed to query database")?;
+
+  let Some(token) = claimed else {
+    let existing = collection.find_one(doc! { "token_hash": &token_hash })
+        .await
+        .internal_error("Failed to query database")?;
+
+    if let Some(existing) = existing {
+      tracing::warn!(family_id = %existing.family_id, "refresh token reuse detected, revoking family");
+      collection.update_many(
+          doc! { "family_id": &existing.family_id },
+          doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .internal_error("Failed to revoke refresh tokens")?;
+      if let Ok(session_id) = ObjectId::parse_str(&existing.family_id) {
+        end_session(&app_state, session_id).await?;
+      }
+      return Err(AppError::unauthorized("Refresh token has already been used"));
+    }
+    return Err(AppError::unauthorized("Invalid refresh token"));
+  };
+
+  if token.expires_at <= Utc::now() {
+    return Err(AppError::unauthorized("Refresh token has expired"));
+  }
+
+  let user = app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": token.user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::unauthorized("Invalid refresh token"))?;
+  if app_state.config.require_verified_email && !user.verified {
+    return Err(AppError::forbidden("Email address has not been verified"));
+  }
+
+  // Families from before sessions existed have no session yet and get one now
+  let Ok(session_id) = ObjectId::parse_str(&token.family_id) else {
+    return Ok(Json(start_session(&app_state, &user, &client).await?));
+  };
+
+  let now = Utc::now();
+  let session = app_state.db.collection::<Session>("sessions")
+    .update_one(
+      doc! { "_id": session_id, "user_id": token.user_id, "revoked_at": null },
+      doc! { "$set": {
+        "last_seen_at": mongodb::bson::to_bson(&now).internal_error("Failed to encode timestamp")?,
+        "ip": client.ip.map(|ip| ip.to_string()),
+        "user_agent": client.user_agent,
+        "expires_at": mongodb::bson::DateTime::from_chrono(now + app_state.config.refresh_token_ttl),
+      } },
+    )
+    .await
+    .internal_error("Failed to update session")?;
+  if session.matched_count == 0 {
+    return Err(AppError::unauthorized("Session has ended"));
+  }
+
+  let response = issue_tokens(&app_state, &user, session_id).await?;
+  Ok(Json(response))
+}
+
+// Revoke the access token used for this request, and optionally a refresh token
+pub async fn logout(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  payload: Option<Json<LogoutRequest>>,
+) -> Result<StatusCode, AppError> {
+  // Also ends an impersonation
+  user.require_login_token()?;
+
+  app_state.revocations
+    .revoke(&user.token_id, &user.user_id, user.token_expires_at)
+    .await?;
+
+  if let Some(session_id) = user.session_id {
+    end_session(&app_state, session_id).await?;
+  }
+
+  if let Some(refresh_token) = payload.and_then(|Json(payload)| payload.refresh_token) {
+    let user_id = ObjectId::parse_str(&user.user_id)
+        .bad_request("Invalid user ID")?;
+    let collection = app_state.db.collection::<RefreshToken>("refresh_tokens");
+
+    let token = collection.find_one(doc! { "token_hash": hash_token(&refresh_token), "user_id": user_id })
+        .await
+        .internal_error("Failed to query database")?;
+    if let Some(token) = token {
+      collection.update_many(
+          doc! { "family_id": token.family_id },
+          doc! { "$set": { "revoked": true } },
+        )
+        .await
+        .internal_error("Failed to revoke refresh tokens")?;
+    }
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+// Invalidate every access and refresh token the user holds
+pub async fn logout_all(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<StatusCode, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  revoke_all_sessions(&app_state, user_id).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+/// Bump the user's token generation, end their sessions and revoke their refresh and API tokens
+pub async fn revoke_all_sessions(app_state: &AppState, user_id: ObjectId) -> Result<(), AppError> {
+  app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": user_id }, doc! { "$inc": { "token_generation": 1_i64 } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  app_state.db.collection::<RefreshToken>("refresh_tokens")
+    .update_many(doc! { "user_id": user_id }, doc! { "$set": { "revoked": true } })
+    .await
+    .internal_error("Failed to revoke refresh tokens")?;
+
+  app_state.db.collection::<Session>("sessions")
+    .update_many(
+      doc! { "user_id": user_id, "revoked_at": null },
+      doc! { "$set": { "revoked_at": mongodb::bson::to_bson(&Utc::now())
+        .internal_error("Failed to encode timestamp")? } },
+    )
+    .await
+    .internal_error("Failed to end sessions")?;
+
+  revoke_api_tokens(app_state, user_id).await
+}
+
+/// Delete every personal access token of the user
+pub async fn revoke_api_tokens(app_state: &AppState, user_id: ObjectId) -> Result<(), AppError> {
+  app_state.db.collection::<ApiToken>("api_tokens")
+    .delete_many(doc! { "user_id": user_id })
+    .await
+    .internal_error("Failed to revoke API tokens")?;
+  Ok(())
+}
+
+// Always answers the same way, whether or not the email belongs to an account
+// The lookup and the email are handled in the background so timing does not tell either
+pub async fn forgot_password(
+  State(app_state): State<AppState>,
+  client: ClientInfo,
+  Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), AppError> {
+  // Limits on the address stop mailbox flooding, limits on the IP stop sweeps over many addresses
+  let config = &app_state.config;
+  if let Some(ip) = client.ip {
+    enforce_rate_limit(
+      &app_state,
+      &format!("password_reset:ip:{ip}"),
+      config.password_reset_max_per_ip,
+      config.password_reset_rate_window,
+    ).await?;
+  }
+  enforce_rate_limit(
+    &app_state,
+    &format!("password_reset:email:{}", payload.email.trim().to_lowercase()),
+    config.password_reset_max_per_email,
+    config.password_reset_rate_window,
+  ).await?;
+
+  tokio::spawn(async move {
+    if let Err(e) = send_password_reset(&app_state, &payload.email).await {
+      tracing::error!(error = %e.message, "failed to send password reset email");
+    }
+  });
+
+  let response = MessageResponse {
+    message: "If an account exists for this email, a reset link has been sent".to_string(),
+  };
+  Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+async fn send_password_reset(app_state: &AppState, email: &str) -> Result<(), AppError> {
+  let user = app_state.db.collection::<User>("users")
+    .find_one(doc! { "email": email.trim(), "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  let Some(user) = user else {
+    return Ok(());
+  };
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+
+  let collection = app_state.db.collection::<ActionToken>("action_tokens");
+
+  // Only the latest link works
+  collection.delete_many(doc! { "user_id": user_id, "purpose": "password_reset", "used_at": null })
+    .await
+    .internal_error("Failed to clear reset tokens")?;
+
+  let token = generate_token();
+  let now = Utc::now();
+  let ttl = app_state.config.password_reset_ttl;
+  let record = ActionToken {
+    id: None,
+    user_id,
+    purpose: TokenPurpose::PasswordReset,
+    token_hash: hash_token(&token),
+    oidc_identity: None,
+    expires_at: now + ttl,
+    used_at: None,
+    created_at: now,
+  };
+  collection.insert_one(&record)
+    .await
+    .internal_error("Failed to store reset token")?;
+
+  let link = format!("{}/reset-password?token={token}", app_state.config.public_base_url);
+  let body = format!(
+    "Hi {},\n\n\
+    Someone asked to reset the password of your To-Do List account.\n\
+    Use this link within {} minutes to choose a new one:\n\n{link}\n\n\
+    If it wasn't you, you can ignore this email.\n",
+    user.full_name,
+    ttl.num_minutes(),
+  );
+
+  app_state.mailer.send(Email {
+    to: user.email,
+    subject: "Reset your password".to_string(),
+    body,
+  }).await
+}
+
+pub async fn reset_password(
+  State(app_state): State<AppState>,
+  Json(payload): Json<ResetPasswordRequest>,
+) -> Result<Json<MessageResponse>, AppError> {
+  let now = Utc::now();
+  let used_at = mongodb::bson::to_bson(&now)
+      .internal_error("Failed to encode timestamp")?;
+  let tokens = app_state.db.collection::<ActionToken>("action_tokens");
+  let filter = doc! {
+    "token_hash": hash_token(&payload.token),
+    "purpose": "password_reset",
+    "used_at": null,
+    "expires_at": { "$gt": mongodb::bson::DateTime::from_chrono(now) },
+  };
+
+  // Check the new password before using up the link, so a rejected one can be retried
+  let pending = tokens.find_one(filter.clone())
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::bad_request("Invalid or expired reset token"))?;
+  let user = app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": pending.user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::bad_request("Invalid or expired reset token"))?;
+  check_password_policy(
+    &app_state.config.password_policy,
+    "new_password",
+    &payload.new_password,
+    &[&user.email, &user.full_name],
+  ).await?;
+
+  let token = tokens
+    .find_one_and_update(filter, doc! { "$set": { "used_at": used_at.clone() } })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::bad_request("Invalid or expired reset token"))?;
+
+  let password = hash_password(&app_state.config.password_hash, payload.new_password).await?;
+
+  let result = app_state.db.collection::<User>("users")
+    .update_one(
+      doc! { "_id": token.user_id, "deleted": false },
+      doc! { "$set": { "password": password, "updated_at": used_at } },
+    )
+    .await
+    .internal_error("Failed to update user")?;
+  if result.matched_count == 0 {
+    return Err(AppError::bad_request("Invalid or expired reset token"));
+  }
+
+  // Whoever knew the old password should not stay logged in
+  revoke_all_sessions(&app_state, token.user_id).await?;
+  send_security_alert(&app_state, &user, SecurityAlert::PasswordChanged).await;
+
+  Ok(Json(MessageResponse {
+    message: "Password has been reset".to_string(),
+  }))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+  pub token: String,
+}
+
+/// Mail a signed verification link for `email` in the background
+pub fn send_verification_email(app_state: &AppState, user_id: ObjectId, full_name: String, email: String) {
+  let app_state = app_state.clone();
+  tokio::spawn(async move {
+    let ttl = app_state.config.email_verification_ttl;
+    let claims = EmailVerificationClaims::new(user_id.to_hex(), email.clone(), ttl);
+    let token = match create_verification_token(&app_state.jwt, claims) {
+      Ok(token) => token,
+      Err(e) => {
+        tracing::error!(error = %e, "failed to sign verification link");
+        return;
+      }
+    };
+
+    let link = format!("{}/api/auth/verify-email?token={token}", app_state.config.public_base_url);
+    let body = format!(
+      "Hi {full_name},\n\n\
+      Please confirm this address for your To-Do List account by opening the link below within {} hours:\n\n{link}\n",
+      ttl.num_hours(),
+    );
+
+    let email = Email {
+      to: email,
+      subject: "Confirm your email address".to_string(),
+      body,
+    };
+    if let Err(e) = app_state.mailer.send(email).await {
+      tracing::error!(error = %e.message, "failed to send verification email");
+    }
+  });
+}
+
+pub async fn verify_email(
+  State(app_state): State<AppState>,
+  Query(query): Query<VerifyEmailQuery>,
+) -> Result<Json<MessageResponse>, AppError> {
+  let claims = verify_verification_token(&app_state.jwt, &query.token)
+      .ok_or_else(|| AppError::bad_request("Invalid or expired verification link"))?;
+  let user_id = ObjectId::parse_str(&claims.sub)
+      .bad_request("Invalid or expired verification link")?;
+
+  // Matching on the address ignores links sent before the email last changed
+  let result = app_state.db.collection::<User>("users")
+    .update_one(
+      doc! { "_id": user_id, "email": &claims.email, "deleted": false },
+      doc! { "$set": { "verified": true } },
+    )
+    .await
+    .internal_error("Failed to update user")?;
+  if result.matched_count == 0 {
+    return Err(AppError::bad_request("Invalid or expired verification link"));
+  }
+
+  Ok(Json(MessageResponse {
+    message: "Email address verified".to_string(),
+  }))
+}
+
+// Like `forgot_password`, the answer does not depend on the account
+pub async fn resend_verification(
+  State(app_state): State<AppState>,
+  Json(payload): Json<ResendVerificationRequest>,
+) -> (StatusCode, Json<MessageResponse>) {
+  tokio::spawn(async move {
+    if let Err(e) = resend_verification_email(&app_state, &payload.email).await {
+      tracing::error!(error = %e.message, "failed to resend verification email");
+    }
+  });
+
+  let response = MessageResponse {
+    message: "If this account still needs verification, a new link has been sent".to_string(),
+  };
+  (StatusCode::ACCEPTED, Json(response))
+}
+
+async fn resend_verification_email(app_state: &AppState, email: &str) -> Result<(), AppError> {
+  let collection = app_state.db.collection::<User>("users");
+  let user = collection.find_one(doc! { "email": email.trim(), "deleted": false, "verified": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  let Some(user) = user else {
+    return Ok(());
+  };
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+
+  let now = Utc::now();
+  if user.verification_sent_at
+    .is_some_and(|sent_at| now - sent_at < app_state.config.verification_resend_interval)
+  {
+    tracing::debug!(user_id = %user_id, "verification email throttled");
+    return Ok(());
+  }
+
+  let sent_at = mongodb::bson::to_bson(&now)
+      .internal_error("Failed to encode timestamp")?;
+  collection.update_one(doc! { "_id": user_id }, doc! { "$set": { "verification_sent_at": sent_at } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  send_verification_email(app_state, user_id, user.full_name, user.email);
+  Ok(())
+}
+
+// Public keys for verifying our access tokens; empty while tokens are signed with a shared secret
+pub async fn jwks(
+  State(app_state): State<AppState>,
+) -> impl IntoResponse {
+  (
+    // Short enough that verifiers pick up a new key soon after a rotation
+    [(header::CACHE_CONTROL, "public, max-age=300")],
+    Json(app_state.jwt.jwks()),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::{
+    models::USER_ROLE,
+    test_support::{create_user, send, test_state, PASSWORD},
+  };
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn logins_match_the_email_whatever_its_case() {
+    let app_state = test_state().await;
+    create_user(&app_state, "Alice@Example.com", USER_ROLE).await;
+
+    let login = json!({ "email": "alice@example.com", "password": PASSWORD });
+    let (status, body) = send(&app_state, "POST", "/api/auth/login", None, Some(login)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+
+    let wrong = json!({ "email": " ALICE@example.com", "password": "not the password" });
+    let (status, _) = send(&app_state, "POST", "/api/auth/login", None, Some(wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+  }
+}
@@ -0,0 +1,573 @@
+use axum::{
+  http::{header, HeaderMap, Method, StatusCode},
+  extract::{State, Path},
+  response::{IntoResponse, Redirect, Response},
+};
+use futures_util::StreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use sha2::{Digest, Sha256};
+use chrono::Utc;
+use crate::{
+  db::AppState,
+  auth::BasicAuthUser,
+  converters::{
+    ical,
+    webdav::{self, DavResponse, ReportKind},
+  },
+  handlers::load_preferences,
+  models::{Permission, Task},
+  dtos::TaskResponse,
+  utils::{ResultExt, AppError},
+};
+
+const DAV_CAPABILITIES: &str = "1, 3, calendar-access";
+const ALLOWED_METHODS: &str = "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT";
+
+// Every user has a single calendar collection holding all of their tasks
+const COLLECTION_NAME: &str = "tasks";
+
+fn principal_href(user_id: &ObjectId) -> String {
+  format!("/dav/principals/{}/", user_id.to_hex())
+}
+
+fn home_href(user_id: &ObjectId) -> String {
+  format!("/dav/calendars/{}/", user_id.to_hex())
+}
+
+fn collection_href(user_id: &ObjectId) -> String {
+  format!("{}{COLLECTION_NAME}/", home_href(user_id))
+}
+
+// Resource name of a task inside the collection
+// Tasks created by a CalDAV client keep the name the client picked
+fn resource_name(task: &Task) -> String {
+  task.dav_resource.clone()
+    .unwrap_or_else(|| format!("{}.ics", task.id.map(|id| id.to_hex()).unwrap_or_default()))
+}
+
+fn etag(task: &Task) -> String {
+  let version = format!(
+    "{}:{}",
+    task.id.map(|id| id.to_hex()).unwrap_or_default(),
+    task.updated_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+  );
+  format!("\"{}\"", &hex::encode(Sha256::digest(version.as_bytes()))[..32])
+}
+
+// Changes whenever a task in the collection is added, modified or removed
+fn ctag(tasks: &[Task]) -> String {
+  let mut hasher = Sha256::new();
+  for task in tasks {
+    hasher.update(etag(task).as_bytes());
+  }
+  hex::encode(hasher.finalize())
+}
+
+fn calendar_data(task: &Task) -> String {
+  let uid = task.ical_uid.clone()
+    .unwrap_or_else(|| ical::default_uid(&task.id.map(|id| id.to_hex()).unwrap_or_default()));
+  ical::encode_calendar(&TaskResponse::from(task.clone()), &uid)
+}
+
+fn options_response() -> Response {
+  (
+    StatusCode::OK,
+    [("DAV", DAV_CAPABILITIES), ("Allow", ALLOWED_METHODS)],
+  ).into_response()
+}
+
+fn multistatus_response(responses: &[DavResponse]) -> Response {
+  (
+    StatusCode::MULTI_STATUS,
+    [(header::CONTENT_TYPE, "application/xml; charset=utf-8")],
+    webdav::multistatus(responses),
+  ).into_response()
+}
+
+// Answer a PROPFIND with just the properties its body asks for
+fn propfind_response(body: &str, responses: Vec<DavResponse>) -> Response {
+  let request = webdav::requested_props(body);
+  let responses: Vec<DavResponse> = responses.into_iter()
+    .map(|response| response.select(&request))
+    .collect();
+  multistatus_response(&responses)
+}
+
+fn method_not_allowed() -> Response {
+  (StatusCode::METHOD_NOT_ALLOWED, [("Allow", ALLOWED_METHODS)]).into_response()
+}
+
+fn is_depth_zero(headers: &HeaderMap) -> bool {
+  headers.get("Depth").and_then(|h| h.to_str().ok()) == Some("0")
+}
+
+fn ensure_owner(user: &BasicAuthUser, user_id: &str) -> Result<(), AppError> {
+  if user.user_id.to_hex() != user_id {
+    return Err(AppError::forbidden("Cannot access another user's calendar"));
+  }
+  Ok(())
+}
+
+// Reading the calendar needs `tasks:read`, changing it `tasks:write`
+fn ensure_allowed(user: &BasicAuthUser, method: &Method) -> Result<(), AppError> {
+  match method.as_str() {
+    "PROPFIND" | "REPORT" | "GET" => user.require_permission(Permission::TasksRead),
+    "PUT" | "DELETE" => user.require_permission(Permission::TasksWrite),
+    _ => Ok(()),
+  }
+}
+
+/// Decode `%XX` escapes in the last segment of an href
+fn percent_decode(value: &str) -> String {
+  let bytes = value.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] == b'%' && i + 2 < bytes.len() {
+      let high = (bytes[i + 1] as char).to_digit(16);
+      let low = (bytes[i + 2] as char).to_digit(16);
+      if let (Some(high), Some(low)) = (high, low) {
+        decoded.push((high * 16 + low) as u8);
+        i += 3;
+        continue;
+      }
+    }
+    decoded.push(bytes[i]);
+    i += 1;
+  }
+  String::from_utf8_lossy(&decoded).to_string()
+}
+
+async fn load_tasks(app_state: &AppState, user_id: &ObjectId) -> Result<Vec<Task>, AppError> {
+  let collection = app_state.db.collection::<Task>("tasks");
+  let mut cursor = collection.find(doc! { "user_id": user_id, "deleted": false })
+      .sort(doc! { "_id": 1 })
+      .await
+      .internal_error("Failed to query database")?;
+
+  let mut tasks = Vec::new();
+  while let Some(task) = cursor.next().await {
+    tasks.push(task.internal_error("Failed to query database")?);
+  }
+  Ok(tasks)
+}
+
+fn resource_filter(user_id: &ObjectId, resource: &str) -> Document {
+  let mut matches = vec![doc! { "dav_resource": resource }];
+  if let Ok(task_id) = ObjectId::parse_str(resource.trim_end_matches(".ics")) {
+    matches.push(doc! { "_id": task_id });
+  }
+  doc! {
+    "user_id": user_id,
+    "deleted": false,
+    "$or": matches,
+  }
+}
+
+async fn find_task(app_state: &AppState, user_id: &ObjectId, resource: &str) -> Result<Option<Task>, AppError> {
+  app_state.db.collection::<Task>("tasks")
+    .find_one(resource_filter(user_id, resource))
+    .await
+    .internal_error("Failed to query database")
+}
+
+/// Check `If-Match` / `If-None-Match` against the current version of the resource
+fn check_preconditions(headers: &HeaderMap, existing: Option<&Task>) -> Result<(), AppError> {
+  let current = existing.map(etag);
+  let precondition_failed = || AppError::new(StatusCode::PRECONDITION_FAILED, "Resource has been modified");
+
+  if let Some(if_match) = headers.get(header::IF_MATCH).and_then(|h| h.to_str().ok()) {
+    let matches = match &current {
+      Some(current) => if_match.trim() == "*" || if_match.split(',').any(|tag| tag.trim() == current),
+      None => false,
+    };
+    if !matches {
+      return Err(precondition_failed());
+    }
+  }
+
+  let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|h| h.to_str().ok());
+  if if_none_match.map(str::trim) == Some("*") && current.is_some() {
+    return Err(precondition_failed());
+  }
+
+  Ok(())
+}
+
+fn collection_props(user: &BasicAuthUser, tasks: &[Task]) -> Vec<String> {
+  vec![
+    "<d:resourcetype><d:collection/><c:calendar/></d:resourcetype>".to_string(),
+    webdav::text_prop("d:displayname", "Tasks"),
+    "<c:supported-calendar-component-set><c:comp name=\"VTODO\"/></c:supported-calendar-component-set>".to_string(),
+    "<d:supported-report-set>\
+      <d:supported-report><d:report><c:calendar-query/></d:report></d:supported-report>\
+      <d:supported-report><d:report><c:calendar-multiget/></d:report></d:supported-report>\
+    </d:supported-report-set>".to_string(),
+    webdav::href_prop("d:current-user-principal", &principal_href(&user.user_id)),
+    webdav::href_prop("d:owner", &principal_href(&user.user_id)),
+    webdav::text_prop("cs:getctag", &ctag(tasks)),
+  ]
+}
+
+fn task_response(user: &BasicAuthUser, task: &Task, with_data: bool) -> DavResponse {
+  let mut props = vec![
+    "<d:resourcetype/>".to_string(),
+    webdav::text_prop("d:getetag", &etag(task)),
+    webdav::text_prop("d:getcontenttype", "text/calendar; charset=utf-8; component=VTODO"),
+  ];
+  if with_data {
+    props.push(webdav::text_prop("c:calendar-data", &calendar_data(task)));
+  }
+  DavResponse::new(format!("{}{}", collection_href(&user.user_id), resource_name(task)), props)
+}
+
+// `/.well-known/caldav` (RFC 6764) points clients at the DAV root
+pub async fn caldav_well_known() -> Redirect {
+  Redirect::permanent("/dav/")
+}
+
+pub async fn caldav_root(
+  user: BasicAuthUser,
+  method: Method,
+  body: String,
+) -> Response {
+  match method.as_str() {
+    "OPTIONS" => options_response(),
+    "PROPFIND" => propfind_response(&body, vec![DavResponse::new("/dav/", vec![
+      "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+      webdav::href_prop("d:current-user-principal", &principal_href(&user.user_id)),
+      webdav::href_prop("c:calendar-home-set", &home_href(&user.user_id)),
+    ])]),
+    _ => method_not_allowed(),
+  }
+}
+
+pub async fn caldav_principal(
+  user: BasicAuthUser,
+  Path(user_id): Path<String>,
+  method: Method,
+  body: String,
+) -> Result<Response, AppError> {
+  ensure_owner(&user, &user_id)?;
+
+  Ok(match method.as_str() {
+    "OPTIONS" => options_response(),
+    "PROPFIND" => propfind_response(&body, vec![DavResponse::new(principal_href(&user.user_id), vec![
+      "<d:resourcetype><d:principal/></d:resourcetype>".to_string(),
+      webdav::text_prop("d:displayname", &user.email),
+      webdav::href_prop("d:current-user-principal", &principal_href(&user.user_id)),
+      webdav::href_prop("d:principal-URL", &principal_href(&user.user_id)),
+      webdav::href_prop("c:calendar-home-set", &home_href(&user.user_id)),
+      webdav::href_prop("c:calendar-user-address-set", &format!("mailto:{}", user.email)),
+    ])]),
+    _ => method_not_allowed(),
+  })
+}
+
+pub async fn caldav_home(
+  State(app_state): State<AppState>,
+  user: BasicAuthUser,
+  Path(user_id): Path<String>,
+  method: Method,
+  headers: HeaderMap,
+  body: String,
+) -> Result<Response, AppError> {
+  ensure_owner(&user, &user_id)?;
+  ensure_allowed(&user, &method)?;
+
+  match method.as_str() {
+    "OPTIONS" => Ok(options_response()),
+    "PROPFIND" => {
+      let mut responses = vec![DavResponse::new(home_href(&user.user_id), vec![
+        "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+        webdav::href_prop("d:current-user-principal", &principal_href(&user.user_id)),
+      ])];
+      if !is_depth_zero(&headers) {
+        let tasks = load_tasks(&app_state, &user.user_id).await?;
+        responses.push(DavResponse::new(collection_href(&user.user_id), collection_props(&user, &tasks)));
+      }
+      Ok(propfind_response(&body, responses))
+    }
+    _ => Ok(method_not_allowed()),
+  }
+}
+
+pub async fn caldav_collection(
+  State(app_state): State<AppState>,
+  user: BasicAuthUser,
+  Path(user_id): Path<String>,
+  method: Method,
+  headers: HeaderMap,
+  body: String,
+) -> Result<Response, AppError> {
+  ensure_owner(&user, &user_id)?;
+  ensure_allowed(&user, &method)?;
+
+  match method.as_str() {
+    "OPTIONS" => Ok(options_response()),
+    "PROPFIND" => {
+      let tasks = load_tasks(&app_state, &user.user_id).await?;
+      let mut responses = vec![DavResponse::new(collection_href(&user.user_id), collection_props(&user, &tasks))];
+      if !is_depth_zero(&headers) {
+        responses.extend(tasks.iter().map(|task| task_response(&user, task, false)));
+      }
+      Ok(propfind_response(&body, responses))
+    }
+    "REPORT" => {
+      // Every entry carries its etag and calendar data, which is all clients ask a REPORT for
+      let responses = match webdav::report_kind(&body) {
+        // Filters are not evaluated; the collection only ever holds VTODOs
+        Some(ReportKind::CalendarQuery) => load_tasks(&app_state, &user.user_id).await?
+          .iter()
+          .map(|task| task_response(&user, task, true))
+          .collect(),
+        Some(ReportKind::CalendarMultiget) => {
+          let mut responses = Vec::new();
+          for href in webdav::extract_hrefs(&body) {
+            let resource = percent_decode(href.rsplit('/').next().unwrap_or_default());
+            responses.push(match find_task(&app_state, &user.user_id, &resource).await? {
+              Some(task) => task_response(&user, &task, true),
+              None => DavResponse::not_found(href),
+            });
+          }
+          responses
+        }
+        Some(ReportKind::Other(name)) => {
+          return Err(AppError::forbidden(format!("Unsupported report `{name}`")));
+        }
+        None => return Err(AppError::bad_request("Invalid REPORT body")),
+      };
+      Ok(multistatus_response(&responses))
+    }
+    _ => Ok(method_not_allowed()),
+  }
+}
+
+pub async fn caldav_resource(
+  State(app_state): State<AppState>,
+  user: BasicAuthUser,
+  Path((user_id, resource)): Path<(String, String)>,
+  method: Method,
+  headers: HeaderMap,
+  body: String,
+) -> Result<Response, AppError> {
+  ensure_owner(&user, &user_id)?;
+  ensure_allowed(&user, &method)?;
+
+  match method.as_str() {
+    "OPTIONS" => Ok(options_response()),
+    "GET" => {
+      let task = find_task(&app_state, &user.user_id, &resource).await?
+        .ok_or_else(|| AppError::not_found("Task not found"))?;
+      Ok((
+        [
+          (header::CONTENT_TYPE, "text/calendar; charset=utf-8".to_string()),
+          (header::ETAG, etag(&task)),
+        ],
+        calendar_data(&task),
+      ).into_response())
+    }
+    "PUT" => put_resource(&app_state, &user, &resource, &headers, &body).await,
+    "DELETE" => {
+      let task = find_task(&app_state, &user.user_id, &resource).await?
+        .ok_or_else(|| AppError::not_found("Task not found"))?;
+      check_preconditions(&headers, Some(&task))?;
+
+      let now = mongodb::bson::to_bson(&Utc::now())
+          .internal_error("Failed to encode timestamp")?;
+      app_state.db.collection::<Task>("tasks")
+        .update_one(
+          doc! { "_id": task.id },
+          doc! { "$set": { "deleted": true, "updated_by": user.user_id, "updated_at": now } },
+        )
+        .await
+        .internal_error("Failed to delete task in database")?;
+
+      Ok(StatusCode::NO_CONTENT.into_response())
+    }
+    _ => Ok(method_not_allowed()),
+  }
+}
+
+// Create or replace a task from the VTODO sent by the client
+async fn put_resource(
+  app_state: &AppState,
+  user: &BasicAuthUser,
+  resource: &str,
+  headers: &HeaderMap,
+  body: &str,
+) -> Result<Response, AppError> {
+  let collection = app_state.db.collection::<Task>("tasks");
+
+  let todo = ical::parse_todos(body)
+    .into_iter()
+    .next()
+    .ok_or_else(|| AppError::bad_request("Expected a VTODO component"))?
+    .map_err(AppError::bad_request)?;
+
+  let existing = find_task(app_state, &user.user_id, resource).await?;
+  check_preconditions(headers, existing.as_ref())?;
+
+  let payload = todo.row
+    .into_create_request(user.user_id.to_hex())
+    .map_err(AppError::bad_request)?;
+  // A VTODO without PRIORITY gets the user's default
+  let priority = match payload.priority {
+    Some(priority) => priority,
+    None => load_preferences(app_state, user.user_id).await?.default_priority,
+  };
+
+  let (task, status) = match existing {
+    Some(existing) => {
+      let mut task = Task {
+        id: existing.id,
+        user_id: existing.user_id,
+        title: payload.title,
+        description: payload.description,
+        due_date: payload.due_date,
+        status: payload.status,
+        deleted: false,
+        priority,
+        tags: payload.tags,
+        created_by: existing.created_by,
+        updated_by: Some(user.user_id),
+        created_at: existing.created_at,
+        updated_at: Some(Utc::now()),
+        started_at: existing.started_at,
+        completed_at: existing.completed_at,
+        status_timestamps_estimated: existing.status_timestamps_estimated,
+        ical_uid: todo.uid.or(existing.ical_uid),
+        dav_resource: existing.dav_resource,
+      };
+      task.track_status_change(Utc::now());
+      collection.replace_one(doc! { "_id": task.id }, &task)
+        .await
+        .internal_error("Failed to update task in database")?;
+      (task, StatusCode::NO_CONTENT)
+    }
+    None => {
+      let mut task = Task {
+        id: None,
+        user_id: user.user_id,
+        title: payload.title,
+        description: payload.description,
+        due_date: payload.due_date,
+        status: payload.status,
+        deleted: false,
+        priority,
+        tags: payload.tags,
+        created_by: Some(user.user_id),
+        updated_by: Some(user.user_id),
+        created_at: Some(Utc::now()),
+        updated_at: Some(Utc::now()),
+        started_at: None,
+        completed_at: None,
+        status_timestamps_estimated: false,
+        ical_uid: todo.uid,
+        dav_resource: Some(resource.to_string()),
+      };
+      task.track_status_change(Utc::now());
+      let result = collection.insert_one(&task)
+        .await
+        .internal_error("Failed to insert task into database")?;
+      task.id = result.inserted_id.as_object_id();
+      (task, StatusCode::CREATED)
+    }
+  };
+
+  Ok((status, [(header::ETAG, etag(&task))]).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn user(permissions: Vec<Permission>) -> BasicAuthUser {
+    BasicAuthUser { user_id: ObjectId::new(), email: "a@example.com".to_string(), permissions }
+  }
+
+  #[test]
+  fn reads_and_writes_need_task_permissions() {
+    let none = user(vec![]);
+    let reader = user(vec![Permission::TasksRead]);
+    for method in ["PROPFIND", "REPORT", "GET", "PUT", "DELETE"] {
+      assert!(ensure_allowed(&none, &Method::from_bytes(method.as_bytes()).unwrap()).is_err(), "{method}");
+    }
+    assert!(ensure_allowed(&none, &Method::OPTIONS).is_ok());
+    assert!(ensure_allowed(&reader, &Method::GET).is_ok());
+    assert!(ensure_allowed(&reader, &Method::from_bytes(b"REPORT").unwrap()).is_ok());
+    assert!(ensure_allowed(&reader, &Method::PUT).is_err());
+    assert!(ensure_allowed(&reader, &Method::DELETE).is_err());
+  }
+
+  fn task(updated_at: &str) -> Task {
+    mongodb::bson::from_document(doc! {
+      "_id": ObjectId::parse_str("65f000000000000000000001").unwrap(),
+      "user_id": ObjectId::new(),
+      "title": "Pay rent",
+      "deleted": false,
+      "updated_at": updated_at,
+    }).unwrap()
+  }
+
+  fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(name, value.parse().unwrap());
+    headers
+  }
+
+  #[test]
+  fn etags_and_ctags_follow_every_change() {
+    let first = task("2025-03-10T08:00:00Z");
+    let edited = task("2025-03-10T09:00:00Z");
+    let tag = etag(&first);
+    assert_eq!(tag, etag(&task("2025-03-10T08:00:00Z")));
+    assert!(tag.starts_with('"') && tag.ends_with('"') && tag.len() == 34);
+    assert_ne!(tag, etag(&edited));
+
+    let other = Task { id: Some(ObjectId::new()), ..first.clone() };
+    let ctag_before = ctag(&[first.clone(), other.clone()]);
+    assert_eq!(ctag_before, ctag(&[first.clone(), other.clone()]));
+    assert_ne!(ctag_before, ctag(&[edited, other.clone()]));
+    assert_ne!(ctag_before, ctag(&[first]));
+  }
+
+  #[test]
+  fn stale_versions_fail_their_preconditions() {
+    let task = task("2025-03-10T08:00:00Z");
+    let current = etag(&task);
+    let failed = |result: Result<(), AppError>| result.unwrap_err().status == StatusCode::PRECONDITION_FAILED;
+
+    assert!(check_preconditions(&HeaderMap::new(), Some(&task)).is_ok());
+    assert!(check_preconditions(&headers(header::IF_MATCH, &current), Some(&task)).is_ok());
+    assert!(check_preconditions(&headers(header::IF_MATCH, &format!("\"old\", {current}")), Some(&task)).is_ok());
+    assert!(check_preconditions(&headers(header::IF_MATCH, "*"), Some(&task)).is_ok());
+    assert!(failed(check_preconditions(&headers(header::IF_MATCH, "\"old\""), Some(&task))));
+    // Nothing to match against
+    assert!(failed(check_preconditions(&headers(header::IF_MATCH, "*"), None)));
+
+    // `If-None-Match: *` only creates
+    assert!(check_preconditions(&headers(header::IF_NONE_MATCH, "*"), None).is_ok());
+    assert!(failed(check_preconditions(&headers(header::IF_NONE_MATCH, "*"), Some(&task))));
+  }
+
+  #[tokio::test]
+  async fn propfind_answers_only_what_was_asked() {
+    let body = r#"<d:propfind xmlns:d="DAV:" xmlns:cs="http://calendarserver.org/ns/">
+      <d:prop><cs:getctag/><d:displayname/><d:quota-used-bytes/></d:prop>
+    </d:propfind>"#;
+    let user = user(vec![Permission::TasksRead]);
+    let tasks = [task("2025-03-10T08:00:00Z")];
+    let response = propfind_response(body, vec![DavResponse::new("/c/", collection_props(&user, &tasks))]);
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let xml = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(xml.contains(&format!(
+      "<d:prop><d:displayname>Tasks</d:displayname><cs:getctag>{}</cs:getctag></d:prop><d:status>HTTP/1.1 200 OK</d:status>",
+      ctag(&tasks),
+    )));
+    assert!(xml.contains("<d:prop><d:quota-used-bytes/></d:prop><d:status>HTTP/1.1 404 Not Found</d:status>"));
+    assert!(!xml.contains("resourcetype"));
+  }
+}
@@ -0,0 +1,115 @@
+use axum::{
+  http::{header, StatusCode},
+  body::Body,
+  extract::{State, Path, Query},
+  response::{Json, IntoResponse, Response},
+  BoxError,
+};
+use futures_util::StreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::Deserialize;
+use chrono::Utc;
+use crate::{
+  db::AppState,
+  auth::{AuthenticatedUser, PermissionGuard},
+  converters::ical::{self, CalendarComponent},
+  models::{Task, User},
+  dtos::{CalendarFeedResponse, TaskResponse},
+  utils::{generate_token, hash_token, ResultExt, AppError},
+};
+
+#[derive(Deserialize)]
+pub struct CalendarFeedQuery {
+  #[serde(default)]
+  pub component: CalendarComponent,
+}
+
+// Public feed polled by calendar clients; the secret token in the path replaces the JWT
+pub async fn calendar_feed(
+  State(app_state): State<AppState>,
+  Path(token): Path<String>,
+  Query(query): Query<CalendarFeedQuery>,
+) -> Result<Response, AppError> {
+  let users = app_state.db.collection::<User>("users");
+  let tasks = app_state.db.collection::<Task>("tasks");
+
+  let user = users.find_one(doc! {
+      "calendar_token_hash": hash_token(&token),
+      "deleted": false,
+    })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("Calendar feed not found"))?;
+
+  let filter = doc! {
+    "user_id": user.id,
+    "deleted": false,
+  };
+  let cursor = tasks.find(filter)
+      .sort(doc! { "due_date": 1 })
+      .await
+      .internal_error("Failed to query database")?;
+
+  let tasks = cursor.map(|task| task.map(TaskResponse::from).map_err(BoxError::from));
+  let body = Body::from_stream(ical::encode(tasks, query.component));
+
+  Ok((
+    [(header::CONTENT_TYPE, "text/calendar; charset=utf-8")],
+    body,
+  ).into_response())
+}
+
+// Issue a new feed token; any previously shared feed URL stops working
+pub async fn regenerate_calendar_token(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Json<CalendarFeedResponse>, AppError> {
+  user.require_session()?;
+
+  let collection = app_state.db.collection::<User>("users");
+
+  let user_object_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  let token = generate_token();
+  let now = mongodb::bson::to_bson(&Utc::now())
+      .internal_error("Failed to encode timestamp")?;
+  let result = collection.update_one(
+      doc! { "_id": user_object_id, "deleted": false },
+      doc! { "$set": {
+        "calendar_token_hash": hash_token(&token),
+        "updated_at": now,
+      } },
+    )
+    .await
+    .internal_error("Failed to update user in database")?;
+
+  if result.matched_count == 0 {
+    return Err(AppError::not_found("User not found"));
+  }
+
+  Ok(Json(CalendarFeedResponse {
+    feed_url: format!("{}/api/calendar/{}/tasks.ics", app_state.config.public_base_url, token),
+  }))
+}
+
+pub async fn revoke_calendar_token(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<StatusCode, AppError> {
+  user.require_session()?;
+
+  let collection = app_state.db.collection::<User>("users");
+
+  let user_object_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  collection.update_one(
+      doc! { "_id": user_object_id, "deleted": false },
+      doc! { "$unset": { "calendar_token_hash": "" } },
+    )
+    .await
+    .internal_error("Failed to update user in database")?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,127 @@
+use axum::{
+  extract::{Path, State},
+  response::Json,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use crate::{
+  db::AppState,
+  auth::{record_security_event, start_impersonation, Actor, AuthenticatedUser, ClientInfo, PermissionGuard},
+  models::{Role, SecurityEvent, SecurityEventKind, User},
+  dtos::{ImpersonationResponse, ImpersonatorResponse},
+  utils::{ResultExt, AppError},
+};
+
+// Support staff see the app as `user_id` sees it, with that user's permissions
+// Credential endpoints stay closed and every write is audited under both identities
+pub async fn impersonate_user(
+  State(app_state): State<AppState>,
+  admin: AuthenticatedUser,
+  client: ClientInfo,
+  Path(user_id): Path<ObjectId>,
+) -> Result<Json<ImpersonationResponse>, AppError> {
+  // Also refuses to impersonate from an impersonation token
+  admin.require_session()?;
+  let admin_id = ObjectId::parse_str(&admin.user_id)
+      .bad_request("Invalid user ID")?;
+  if admin_id == user_id {
+    return Err(AppError::bad_request("You cannot impersonate yourself"));
+  }
+
+  let user = app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
+
+  // Impersonation must not grant anything the admin could not do already
+  let permissions = app_state.db.collection::<Role>("roles")
+    .find_one(doc! { "name": &user.role })
+    .await
+    .internal_error("Failed to query roles")?
+    .map(|role| role.permissions)
+    .unwrap_or_default();
+  if !permissions.iter().all(|permission| admin.has_permission(*permission)) {
+    return Err(AppError::forbidden("Cannot impersonate a user with permissions you do not have"));
+  }
+
+  let token = start_impersonation(
+    &app_state,
+    &user,
+    Actor { sub: admin.user_id.clone(), email: admin.email.clone() },
+    &client,
+  ).await?;
+
+  let mut event = SecurityEvent::new(SecurityEventKind::ImpersonationStarted);
+  event.user_id = Some(user_id);
+  event.email = Some(user.email.clone());
+  event.ip = client.ip.map(|ip| ip.to_string());
+  event.actor_id = Some(admin_id);
+  record_security_event(&app_state, event).await;
+
+  Ok(Json(ImpersonationResponse {
+    token,
+    expires_in: app_state.config.impersonation_ttl.num_seconds(),
+    user: user.into(),
+    impersonator: ImpersonatorResponse { id: admin.user_id, email: admin.email },
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::http::StatusCode;
+  use serde_json::json;
+  use crate::{
+    models::{ADMIN_ROLE, USER_ROLE},
+    test_support::{create_user, login_token, send, test_state, PASSWORD},
+  };
+
+  // An admin's impersonation token for a fresh user, and that user's ID
+  async fn impersonate(app_state: &AppState) -> (String, ObjectId) {
+    let admin = create_user(app_state, "admin@example.com", ADMIN_ROLE).await;
+    let user = create_user(app_state, "user@example.com", USER_ROLE).await;
+    let user_id = user.id.unwrap();
+    let admin_token = login_token(app_state, &admin).await;
+
+    let uri = format!("/api/admin/impersonate/{user_id}");
+    let (status, body) = send(app_state, "POST", &uri, Some(&admin_token), None).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    (body["token"].as_str().unwrap().to_string(), user_id)
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn impersonation_tokens_cannot_touch_credentials() {
+    let app_state = test_state().await;
+    let (token, _) = impersonate(&app_state).await;
+
+    let body = json!({ "current_password": PASSWORD, "new_password": "another long passphrase here" });
+    let (status, _) = send(&app_state, "POST", "/api/me/password", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    for (method, uri) in [("POST", "/api/calendar/feed-token"), ("POST", "/api/auth/mfa/setup")] {
+      let (status, _) = send(&app_state, method, uri, Some(&token), None).await;
+      assert_eq!(status, StatusCode::FORBIDDEN, "{method} {uri}");
+    }
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn impersonated_writes_are_audited() {
+    let app_state = test_state().await;
+    let (token, user_id) = impersonate(&app_state).await;
+
+    let body = json!({ "full_name": "Renamed" });
+    let (status, _) = send(&app_state, "PATCH", "/api/me", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let kind = mongodb::bson::to_bson(&SecurityEventKind::ImpersonatedRequest).unwrap();
+    let event = app_state.db.collection::<SecurityEvent>("security_events")
+      .find_one(doc! { "kind": kind, "user_id": user_id })
+      .await
+      .unwrap()
+      .expect("no audit event");
+    assert!(event.actor_id.is_some());
+    assert_eq!(event.detail.as_deref(), Some("PATCH /api/me -> 200"));
+  }
+}
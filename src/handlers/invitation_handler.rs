@@ -0,0 +1,311 @@
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::Json,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use crate::{
+  db::{email_collation, is_duplicate_key, AppState},
+  auth::{check_password_policy, hash_password, AuthenticatedUser},
+  models::{Invitation, User},
+  dtos::{AcceptInvitationRequest, CreateInvitationRequest, InvitationResponse, UserResponse},
+  mailer::Email,
+  utils::{generate_token, hash_token, ResultExt, AppError},
+};
+use crate::handlers::ensure_role_grantable;
+
+// Invitations that can still be accepted
+fn pending_filter() -> mongodb::bson::Document {
+  doc! {
+    "accepted_at": null,
+    "revoked_at": null,
+    "expires_at": { "$gt": mongodb::bson::DateTime::from_chrono(Utc::now()) },
+  }
+}
+
+pub async fn create_invitation(
+  State(app_state): State<AppState>,
+  admin: AuthenticatedUser,
+  Json(payload): Json<CreateInvitationRequest>,
+) -> Result<(StatusCode, Json<InvitationResponse>), AppError> {
+  let admin_id = ObjectId::parse_str(&admin.user_id)
+      .bad_request("Invalid user ID")?;
+  payload.email.parse::<lettre::Address>()
+      .bad_request("Invalid email address")?;
+  ensure_role_grantable(&app_state, &admin, &payload.role).await?;
+
+  let existing_user = app_state.db.collection::<User>("users")
+    .find_one(doc! { "email": &payload.email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  if existing_user.is_some() {
+    return Err(AppError::conflict("User already exists"));
+  }
+
+  let collection = app_state.db.collection::<Invitation>("invitations");
+  let now = Utc::now();
+
+  // Inviting the same address again replaces the earlier link
+  let mut filter = pending_filter();
+  filter.insert("email", &payload.email);
+  collection.update_many(filter, doc! { "$set": {
+    "revoked_at": mongodb::bson::to_bson(&now).internal_error("Failed to encode timestamp")?,
+  } })
+    .await
+    .internal_error("Failed to revoke earlier invitations")?;
+
+  let token = generate_token();
+  let ttl = app_state.config.invitation_ttl;
+  let mut invitation = Invitation {
+    id: None,
+    email: payload.email,
+    role: payload.role,
+    invited_by: admin_id,
+    token_hash: hash_token(&token),
+    expires_at: now + ttl,
+    accepted_at: None,
+    revoked_at: None,
+    created_at: now,
+  };
+  let result = collection.insert_one(&invitation)
+    .await
+    .internal_error("Failed to store invitation")?;
+  invitation.id = result.inserted_id.as_object_id();
+
+  let link = format!("{}/accept-invitation?token={token}", app_state.config.public_base_url);
+  let body = format!(
+    "Hi,\n\n\
+    {} has invited you to their To-Do List workspace.\n\
+    Use this link within {} days to choose your name and password:\n\n{link}\n\n\
+    If you weren't expecting this, you can ignore this email.\n",
+    admin.email,
+    ttl.num_days(),
+  );
+  let email = Email {
+    to: invitation.email.clone(),
+    subject: "You're invited to To-Do List".to_string(),
+    body,
+  };
+  let mailer = app_state.mailer.clone();
+  tokio::spawn(async move {
+    if let Err(e) = mailer.send(email).await {
+      tracing::error!(error = %e.message, "failed to send invitation email");
+    }
+  });
+
+  Ok((StatusCode::CREATED, Json(invitation.into())))
+}
+
+pub async fn list_invitations(
+  State(app_state): State<AppState>,
+) -> Result<Json<Vec<InvitationResponse>>, AppError> {
+  let invitations: Vec<Invitation> = app_state.db.collection::<Invitation>("invitations")
+    .find(doc! {})
+    .sort(doc! { "created_at": -1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+
+  Ok(Json(invitations.into_iter().map(InvitationResponse::from).collect()))
+}
+
+pub async fn revoke_invitation(
+  State(app_state): State<AppState>,
+  Path(id): Path<ObjectId>,
+) -> Result<StatusCode, AppError> {
+  let mut filter = pending_filter();
+  filter.insert("_id", id);
+  let result = app_state.db.collection::<Invitation>("invitations")
+    .update_one(filter, doc! { "$set": {
+      "revoked_at": mongodb::bson::to_bson(&Utc::now()).internal_error("Failed to encode timestamp")?,
+    } })
+    .await
+    .internal_error("Failed to revoke invitation")?;
+  if result.matched_count == 0 {
+    return Err(AppError::not_found("No pending invitation with this ID"));
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+// The invitee picks their name and password; the link already proved they own the address
+pub async fn accept_invitation(
+  State(app_state): State<AppState>,
+  Json(payload): Json<AcceptInvitationRequest>,
+) -> Result<(StatusCode, Json<UserResponse>), AppError> {
+  let invitations = app_state.db.collection::<Invitation>("invitations");
+  let mut filter = pending_filter();
+  filter.insert("token_hash", hash_token(&payload.token));
+  let invitation = invitations.find_one(filter.clone())
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::bad_request("Invalid or expired invitation"))?;
+
+  let users = app_state.db.collection::<User>("users");
+  let existing_user = users
+    .find_one(doc! { "email": &invitation.email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  if existing_user.is_some() {
+    return Err(AppError::conflict("User already exists"));
+  }
+
+  check_password_policy(
+    &app_state.config.password_policy,
+    "password",
+    &payload.password,
+    &[&invitation.email, &payload.full_name],
+  ).await?;
+  let password = hash_password(&app_state.config.password_hash, payload.password).await?;
+
+  // Claim the invitation before creating the account, so a link cannot be used twice,
+  // and hand it back if the account cannot be created
+  let now = Utc::now();
+  let accepted_at = mongodb::bson::to_bson(&now).internal_error("Failed to encode timestamp")?;
+  let claimed = invitations.update_one(filter, doc! { "$set": { "accepted_at": accepted_at.clone() } })
+    .await
+    .internal_error("Failed to accept invitation")?;
+  if claimed.modified_count == 0 {
+    return Err(AppError::bad_request("Invalid or expired invitation"));
+  }
+
+  let user = User {
+    id: None,
+    full_name: payload.full_name,
+    email: invitation.email,
+    password,
+    role: invitation.role,
+    created_by: Some(invitation.invited_by),
+    updated_by: Some(invitation.invited_by),
+    deleted: false,
+    deleted_at: None,
+    anonymized_at: None,
+    verified: true,
+    verification_sent_at: None,
+    calendar_token_hash: None,
+    token_generation: 0,
+    mfa: None,
+    oidc_identity: None,
+    preferences: Default::default(),
+    created_at: Some(now),
+    updated_at: Some(now),
+  };
+  let inserted = match users.insert_one(&user).await {
+    Ok(result) => result.inserted_id.as_object_id()
+      .ok_or_else(|| AppError::internal_error("Failed to get inserted user ID")),
+    // The unique email index caught an account created since the check above
+    Err(e) if is_duplicate_key(&e) => Err(AppError::conflict("User already exists")),
+    Err(_) => Err(AppError::internal_error("Failed to insert user into database")),
+  };
+  let user_id = match inserted {
+    Ok(user_id) => user_id,
+    Err(e) => {
+      let unclaimed = invitations
+        .update_one(
+          doc! { "_id": invitation.id, "accepted_at": accepted_at },
+          doc! { "$set": { "accepted_at": null } },
+        )
+        .await;
+      if let Err(unclaim_error) = unclaimed {
+        tracing::error!(error = %unclaim_error, "failed to release invitation after account creation failed");
+      }
+      return Err(e);
+    }
+  };
+
+  tracing::info!(user = %user.email, "invitation accepted");
+
+  let response = UserResponse {
+    id: user_id.to_hex(),
+    full_name: user.full_name,
+    email: user.email,
+    verified: user.verified,
+    role: user.role,
+    created_at: user.created_at,
+    updated_at: user.updated_at,
+    created_by: user.created_by.map(|id| id.to_hex()),
+    updated_by: user.updated_by.map(|id| id.to_hex()),
+  };
+
+  Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Duration;
+  use serde_json::json;
+  use crate::{
+    models::USER_ROLE,
+    test_support::{create_user, send, test_state, PASSWORD},
+  };
+
+  // Store a pending invitation and return the token its link would carry
+  async fn invite(app_state: &AppState, email: &str) -> String {
+    let inviter = create_user(app_state, &format!("admin-{}@example.com", ObjectId::new()), USER_ROLE).await;
+    let token = crate::utils::generate_token();
+    let invitation = Invitation {
+      id: None,
+      email: email.to_string(),
+      role: USER_ROLE.to_string(),
+      invited_by: inviter.id.unwrap(),
+      token_hash: hash_token(&token),
+      expires_at: Utc::now() + Duration::days(1),
+      accepted_at: None,
+      revoked_at: None,
+      created_at: Utc::now(),
+    };
+    app_state.db.collection::<Invitation>("invitations").insert_one(&invitation).await.unwrap();
+    token
+  }
+
+  async fn accept(app_state: &AppState, token: &str) -> StatusCode {
+    let body = json!({ "token": token, "full_name": "New User", "password": PASSWORD });
+    send(app_state, "POST", "/api/auth/accept-invitation", None, Some(body)).await.0
+  }
+
+  async fn is_pending(app_state: &AppState, token: &str) -> bool {
+    let mut filter = pending_filter();
+    filter.insert("token_hash", hash_token(token));
+    app_state.db.collection::<Invitation>("invitations")
+      .find_one(filter)
+      .await
+      .unwrap()
+      .is_some()
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn accepts_an_invitation_once() {
+    let app_state = test_state().await;
+    let token = invite(&app_state, "new@example.com").await;
+
+    assert_eq!(accept(&app_state, &token).await, StatusCode::CREATED);
+    assert!(!is_pending(&app_state, &token).await);
+    assert_eq!(accept(&app_state, &token).await, StatusCode::BAD_REQUEST);
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn keeps_the_invitation_when_the_account_cannot_be_created() {
+    let app_state = test_state().await;
+    let token = invite(&app_state, "taken@example.com").await;
+    let existing = create_user(&app_state, "Taken@Example.com", USER_ROLE).await;
+
+    assert_eq!(accept(&app_state, &token).await, StatusCode::CONFLICT);
+    assert!(is_pending(&app_state, &token).await);
+
+    // Once the address is free again the same link still works
+    app_state.db.collection::<User>("users")
+      .update_one(doc! { "_id": existing.id }, doc! { "$set": { "deleted": true } })
+      .await
+      .unwrap();
+    assert_eq!(accept(&app_state, &token).await, StatusCode::CREATED);
+  }
+}
@@ -0,0 +1,226 @@
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::Json,
+};
+use mongodb::bson::{doc, oid::ObjectId, to_bson};
+use chrono::Utc;
+use crate::{
+  db::AppState,
+  auth::{
+    enforce_rate_limit, login_keys, mfa, start_session, throttle_attempt, verify_mfa_challenge_token, AuthenticatedUser, ClientInfo,
+    PermissionGuard,
+  },
+  models::{Mfa, User},
+  dtos::{
+    LoginResponse, MfaCodeRequest, MfaLoginRequest, MfaSetupResponse, RecoveryCodesResponse,
+  },
+  utils::{ResultExt, AppError},
+};
+use crate::handlers::ensure_can_manage;
+
+async fn load_user(app_state: &AppState, user_id: ObjectId) -> Result<User, AppError> {
+  app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))
+}
+
+fn now_seconds() -> u64 {
+  Utc::now().timestamp().max(0) as u64
+}
+
+/// Accept a TOTP code or a recovery code for a user with 2FA enabled
+/// Either one is consumed atomically, so the same code never works twice
+async fn check_second_factor(app_state: &AppState, user: &User, code: &str) -> Result<(), AppError> {
+  let collection = app_state.db.collection::<User>("users");
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  let settings = user.mfa.as_ref()
+      .filter(|mfa| mfa.enabled)
+      .ok_or_else(|| AppError::bad_request("Two-factor authentication is not enabled"))?;
+
+  if let Some(step) = mfa::verify_code(&settings.secret, code, now_seconds(), settings.last_step) {
+    let result = collection.update_one(
+        doc! {
+          "_id": user_id,
+          "$or": [{ "mfa.last_step": null }, { "mfa.last_step": { "$lt": step } }],
+        },
+        doc! { "$set": { "mfa.last_step": step } },
+      )
+      .await
+      .internal_error("Failed to update user")?;
+    if result.modified_count == 1 {
+      return Ok(());
+    }
+  } else {
+    let code_hash = mfa::hash_recovery_code(code);
+    let result = collection.update_one(
+        doc! { "_id": user_id, "mfa.recovery_codes": &code_hash },
+        doc! { "$pull": { "mfa.recovery_codes": &code_hash } },
+      )
+      .await
+      .internal_error("Failed to update user")?;
+    if result.modified_count == 1 {
+      tracing::info!(user_id = %user_id, "recovery code used");
+      return Ok(());
+    }
+  }
+
+  Err(AppError::unauthorized("Invalid code"))
+}
+
+// Second step of `login`: exchange the MFA challenge and a code for tokens
+pub async fn mfa_login(
+  State(app_state): State<AppState>,
+  client: ClientInfo,
+  Json(payload): Json<MfaLoginRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+  let claims = verify_mfa_challenge_token(&app_state.jwt, &payload.mfa_token)
+      .ok_or_else(|| AppError::unauthorized("Invalid or expired MFA token"))?;
+  let user_id = ObjectId::parse_str(&claims.sub)
+      .map_err(|_| AppError::unauthorized("Invalid or expired MFA token"))?;
+
+  let revoked = app_state.revocations.is_revoked(&claims.jti).await?;
+  if revoked {
+    return Err(AppError::unauthorized("Invalid or expired MFA token"));
+  }
+
+  let user = load_user(&app_state, user_id).await
+      .map_err(|_| AppError::unauthorized("Invalid or expired MFA token"))?;
+  // Each challenge allows a few codes; after that the password has to be entered again
+  enforce_rate_limit(
+    &app_state,
+    &format!("mfa_challenge:{}", claims.jti),
+    app_state.config.mfa_challenge_max_attempts,
+    app_state.config.mfa_challenge_ttl,
+  )
+    .await
+    .map_err(|e| match e.status {
+      StatusCode::TOO_MANY_REQUESTS => AppError::unauthorized("Too many wrong codes; sign in again"),
+      _ => e,
+    })?;
+  // Codes are guessed like passwords, so they count against the same limits
+  let keys = login_keys(&user.email, client.ip);
+  throttle_attempt(&app_state, keys, check_second_factor(&app_state, &user, &payload.code)).await?;
+  app_state.revocations.revoke(&claims.jti, &claims.sub, claims.expires_at()).await?;
+
+  let response = start_session(&app_state, &user, &client).await?;
+  Ok(Json(response))
+}
+
+// Start enrolment: a new secret replaces any unconfirmed one
+pub async fn mfa_setup(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Json<MfaSetupResponse>, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let existing = load_user(&app_state, user_id).await?;
+  if existing.mfa.as_ref().is_some_and(|mfa| mfa.enabled) {
+    return Err(AppError::conflict("Two-factor authentication is already enabled"));
+  }
+
+  let secret = mfa::generate_secret();
+  let otpauth_uri = mfa::otpauth_uri(&secret, &existing.email)
+      .ok_or_else(|| AppError::internal_error("Failed to build otpauth URI"))?;
+
+  let settings = Mfa {
+    secret: secret.clone(),
+    enabled: false,
+    recovery_codes: Vec::new(),
+    last_step: None,
+  };
+  let settings = to_bson(&settings)
+      .internal_error("Failed to encode MFA settings")?;
+  app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": user_id }, doc! { "$set": { "mfa": settings } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  Ok(Json(MfaSetupResponse { secret, otpauth_uri }))
+}
+
+// Finish enrolment with the first code from the authenticator app
+pub async fn mfa_confirm(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Json(payload): Json<MfaCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let existing = load_user(&app_state, user_id).await?;
+  let settings = existing.mfa
+      .filter(|mfa| !mfa.enabled)
+      .ok_or_else(|| AppError::bad_request("Start two-factor setup first"))?;
+
+  let step = mfa::verify_code(&settings.secret, &payload.code, now_seconds(), None)
+      .ok_or_else(|| AppError::bad_request("Invalid code"))?;
+
+  let (recovery_codes, hashes) = mfa::generate_recovery_codes();
+  let result = app_state.db.collection::<User>("users")
+    .update_one(
+      doc! { "_id": user_id, "mfa.secret": &settings.secret, "mfa.enabled": false },
+      doc! { "$set": {
+        "mfa.enabled": true,
+        "mfa.recovery_codes": hashes,
+        "mfa.last_step": step,
+      } },
+    )
+    .await
+    .internal_error("Failed to update user")?;
+  if result.modified_count == 0 {
+    return Err(AppError::conflict("Two-factor setup changed, start again"));
+  }
+
+  Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// Turning 2FA off needs a current code, not just a stolen access token
+pub async fn mfa_disable(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  client: ClientInfo,
+  Json(payload): Json<MfaCodeRequest>,
+) -> Result<StatusCode, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let existing = load_user(&app_state, user_id).await?;
+  let keys = login_keys(&existing.email, client.ip);
+  throttle_attempt(&app_state, keys, check_second_factor(&app_state, &existing, &payload.code)).await?;
+
+  app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": user_id }, doc! { "$unset": { "mfa": "" } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+// Admin escape hatch for users who lost both their device and recovery codes
+pub async fn reset_user_mfa(
+  State(app_state): State<AppState>,
+  admin: AuthenticatedUser,
+  Path(id): Path<ObjectId>,
+) -> Result<StatusCode, AppError> {
+  let target = load_user(&app_state, id).await?;
+  ensure_can_manage(&app_state, &admin, &target).await?;
+
+  let result = app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": id, "deleted": false }, doc! { "$unset": { "mfa": "" } })
+    .await
+    .internal_error("Failed to update user")?;
+  if result.matched_count == 0 {
+    return Err(AppError::not_found("User not found"));
+  }
+
+  tracing::info!(user_id = %id, admin_id = %admin.user_id, "two-factor authentication reset by admin");
+  Ok(StatusCode::NO_CONTENT)
+}
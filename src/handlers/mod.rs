@@ -4,4 +4,60 @@ pub use user_handler::*;
 
 pub mod task_handler;
 
-pub use task_handler::*;
\ No newline at end of file
+pub use task_handler::*;
+
+pub mod calendar_handler;
+
+pub use calendar_handler::*;
+
+pub mod caldav_handler;
+
+pub use caldav_handler::*;
+
+pub mod stats_handler;
+
+pub use stats_handler::*;
+
+pub mod auth_handler;
+
+pub use auth_handler::*;
+
+pub mod mfa_handler;
+
+pub use mfa_handler::*;
+
+pub mod role_handler;
+
+pub use role_handler::*;
+
+pub mod api_token_handler;
+
+pub use api_token_handler::*;
+
+pub mod oidc_handler;
+
+pub use oidc_handler::*;
+
+pub mod registration_handler;
+
+pub use registration_handler::*;
+
+pub mod invitation_handler;
+
+pub use invitation_handler::*;
+
+pub mod session_handler;
+
+pub use session_handler::*;
+
+pub mod profile_handler;
+
+pub use profile_handler::*;
+
+pub mod account_handler;
+
+pub use account_handler::*;
+
+pub mod impersonation_handler;
+
+pub use impersonation_handler::*;
@@ -0,0 +1,444 @@
+use axum::{
+  extract::{Query, State},
+  http::{header, HeaderMap, StatusCode},
+  response::{IntoResponse, Json, Redirect, Response},
+};
+use mongodb::bson::doc;
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use std::sync::Arc;
+use crate::{
+  db::{email_collation, AppState},
+  auth::{hash_password, oidc::{IdTokenClaims, OidcClient}, ClientInfo},
+  mailer::Email,
+  models::{ActionToken, OidcIdentity, OidcLoginState, Role, TokenPurpose, User},
+  dtos::{ConfirmOidcLinkRequest, MessageResponse},
+  utils::{generate_token, hash_token, ResultExt, AppError},
+};
+use crate::handlers::complete_login;
+
+// How long the user has to finish signing in at the provider
+const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+
+// How long the owner of an existing account has to allow linking it
+const LINK_CONFIRMATION_TTL_MINUTES: i64 = 60;
+
+// Holds the hash of the login's state, so only the browser that started a login can finish it
+const LOGIN_STATE_COOKIE: &str = "oidc_login_state";
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+  pub code: Option<String>,
+  pub state: String,
+  // Set by the provider instead of `code` when the user cancels or the request is refused
+  pub error: Option<String>,
+}
+
+fn oidc_client(app_state: &AppState) -> Result<Arc<OidcClient>, AppError> {
+  app_state.oidc.clone()
+    .ok_or_else(|| AppError::not_found("Single sign-on is not configured"))
+}
+
+// Start a login: remember state, nonce and PKCE verifier, then send the browser to the provider
+pub async fn oidc_login(
+  State(app_state): State<AppState>,
+) -> Result<Response, AppError> {
+  let oidc = oidc_client(&app_state)?;
+
+  let login = OidcLoginState {
+    id: None,
+    state: generate_token(),
+    nonce: generate_token(),
+    code_verifier: generate_token(),
+    expires_at: Utc::now() + Duration::minutes(LOGIN_STATE_TTL_MINUTES),
+  };
+  let url = oidc.authorization_url(&login.state, &login.nonce, &login.code_verifier).await?;
+
+  app_state.db.collection::<OidcLoginState>("oidc_login_states")
+    .insert_one(&login)
+    .await
+    .internal_error("Failed to store login state")?;
+
+  let cookie = state_cookie(&app_state, &hash_token(&login.state), LOGIN_STATE_TTL_MINUTES * 60);
+  Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response())
+}
+
+// The provider sends the browser back here with an authorization code
+pub async fn oidc_callback(
+  State(app_state): State<AppState>,
+  client: ClientInfo,
+  headers: HeaderMap,
+  Query(query): Query<OidcCallbackQuery>,
+) -> Result<Response, AppError> {
+  let oidc = oidc_client(&app_state)?;
+
+  // Otherwise an attacker could send a victim's browser their own callback and sign them in as the attacker
+  if cookie(&headers, LOGIN_STATE_COOKIE) != Some(hash_token(&query.state).as_str()) {
+    return Err(AppError::bad_request("This login was started in another browser; sign in again"));
+  }
+
+  // Each state is good for one callback, whatever its outcome
+  let login = app_state.db.collection::<OidcLoginState>("oidc_login_states")
+    .find_one_and_delete(doc! {
+      "state": &query.state,
+      "expires_at": { "$gt": mongodb::bson::DateTime::from_chrono(Utc::now()) },
+    })
+    .await
+    .internal_error("Failed to load login state")?
+    .ok_or_else(|| AppError::bad_request("Invalid or expired login state"))?;
+
+  if let Some(error) = query.error {
+    return Err(AppError::unauthorized(format!("Sign-in was not completed: {error}")));
+  }
+  let code = query.code
+    .ok_or_else(|| AppError::bad_request("Missing authorization code"))?;
+
+  let claims = oidc.exchange_code(&code, &login.code_verifier, &login.nonce).await?;
+  let clear_cookie = [(header::SET_COOKIE, state_cookie(&app_state, "", 0))];
+  let Some(user) = find_or_create_user(&app_state, &oidc, &claims).await? else {
+    let response = MessageResponse {
+      message: "An account already uses this email address; follow the link sent to it to connect this sign-in".to_string(),
+    };
+    return Ok((StatusCode::ACCEPTED, clear_cookie, Json(response)).into_response());
+  };
+
+  Ok((clear_cookie, Json(complete_login(&app_state, &user, &client).await?)).into_response())
+}
+
+// Scoped to the OIDC endpoints; `Lax` so it comes along on the provider's redirect back
+fn state_cookie(app_state: &AppState, value: &str, max_age_seconds: i64) -> String {
+  let secure = if app_state.config.public_base_url.starts_with("https://") { "; Secure" } else { "" };
+  format!("{LOGIN_STATE_COOKIE}={value}; Max-Age={max_age_seconds}; Path=/api/auth/oidc; HttpOnly; SameSite=Lax{secure}")
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+  headers.get_all(header::COOKIE)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .flat_map(|value| value.split(';'))
+    .filter_map(|pair| pair.trim().split_once('='))
+    .find(|(key, _)| *key == name)
+    .map(|(_, value)| value)
+}
+
+// The owner of an existing account follows the mailed link to connect a sign-in identity to it
+pub async fn confirm_oidc_link(
+  State(app_state): State<AppState>,
+  Json(payload): Json<ConfirmOidcLinkRequest>,
+) -> Result<Json<MessageResponse>, AppError> {
+  oidc_client(&app_state)?;
+
+  let now = Utc::now();
+  let used_at = mongodb::bson::to_bson(&now)
+      .internal_error("Failed to encode timestamp")?;
+  let token = app_state.db.collection::<ActionToken>("action_tokens")
+    .find_one_and_update(
+      doc! {
+        "token_hash": hash_token(&payload.token),
+        "purpose": "oidc_link",
+        "used_at": null,
+        "expires_at": { "$gt": mongodb::bson::DateTime::from_chrono(now) },
+      },
+      doc! { "$set": { "used_at": used_at.clone() } },
+    )
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::bad_request("Invalid or expired link"))?;
+  let identity = token.oidc_identity
+    .ok_or_else(|| AppError::bad_request("Invalid or expired link"))?;
+
+  let collection = app_state.db.collection::<User>("users");
+  let linked = collection
+    .find_one(doc! {
+      "oidc_identity.issuer": &identity.issuer,
+      "oidc_identity.subject": &identity.subject,
+      "deleted": false,
+    })
+    .await
+    .internal_error("Failed to query database")?;
+  if linked.is_some() {
+    return Err(AppError::conflict("This sign-in identity is already linked to an account"));
+  }
+
+  let result = collection
+    .update_one(
+      doc! { "_id": token.user_id, "deleted": false, "oidc_identity": null },
+      doc! { "$set": {
+        "oidc_identity": mongodb::bson::to_bson(&identity)
+          .internal_error("Failed to encode identity")?,
+        "verified": true,
+        "updated_at": used_at,
+      } },
+    )
+    .await
+    .internal_error("Failed to link account")?;
+  if result.matched_count == 0 {
+    return Err(AppError::conflict("This account is linked to another sign-in identity"));
+  }
+
+  tracing::info!(user_id = %token.user_id, issuer = %identity.issuer, "sign-in identity linked by account owner");
+  Ok(Json(MessageResponse {
+    message: "Sign-in identity linked; you can now sign in with single sign-on".to_string(),
+  }))
+}
+
+// Match the provider's account to a local user: by linked identity, then by email, then create one
+// `None` when an account with the email exists and its owner has been asked to confirm the link
+async fn find_or_create_user(
+  app_state: &AppState,
+  oidc: &OidcClient,
+  claims: &IdTokenClaims,
+) -> Result<Option<User>, AppError> {
+  let collection = app_state.db.collection::<User>("users");
+  let config = oidc.config();
+  let identity = OidcIdentity {
+    issuer: claims.iss.clone(),
+    subject: claims.sub.clone(),
+  };
+  let mapped_role = oidc.map_role(claims);
+
+  let linked = collection
+    .find_one(doc! {
+      "oidc_identity.issuer": &identity.issuer,
+      "oidc_identity.subject": &identity.subject,
+      "deleted": false,
+    })
+    .await
+    .internal_error("Failed to query database")?;
+
+  let mut user = match linked {
+    Some(user) => user,
+    None => {
+      let email = claims.email.clone()
+        .ok_or_else(|| AppError::forbidden("The identity provider did not share an email address"))?;
+      // Otherwise anyone who can set an arbitrary address at the provider could take over the account
+      if config.require_verified_email && claims.email_verified != Some(true) {
+        return Err(AppError::forbidden("The identity provider has not verified this email address"));
+      }
+
+      let existing = collection
+        .find_one(doc! { "email": &email, "deleted": false })
+        .collation(email_collation())
+        .await
+        .internal_error("Failed to query database")?;
+
+      match existing {
+        Some(user) => {
+          if let Some(other) = &user.oidc_identity {
+            tracing::warn!(user = %email, issuer = %other.issuer, "account is linked to another identity");
+            return Err(AppError::conflict("This account is linked to another sign-in identity"));
+          }
+          // An address the provider vouches for is not proof of owning this account, which may be an admin's
+          request_link_confirmation(app_state, &user, identity).await?;
+          return Ok(None);
+        }
+        None if config.auto_create_users => {
+          let role = mapped_role.clone().unwrap_or_else(|| config.default_role.clone());
+          create_oidc_user(app_state, claims, email, role, identity).await?
+        }
+        None => return Err(AppError::forbidden("No account exists for this email address")),
+      }
+    }
+  };
+
+  // The provider stays the source of truth for roles it maps
+  if let Some(role) = mapped_role && role != user.role {
+    if role_exists(app_state, &role).await? {
+      collection
+        .update_one(
+          doc! { "_id": user.id },
+          doc! { "$set": {
+            "role": &role,
+            "updated_at": mongodb::bson::to_bson(&Utc::now())
+              .internal_error("Failed to encode date")?,
+          } },
+        )
+        .await
+        .internal_error("Failed to update role")?;
+      user.role = role;
+    } else {
+      tracing::warn!(role = %role, "OIDC role mapping names an unknown role");
+    }
+  }
+
+  Ok(Some(user))
+}
+
+// Mail the account's owner a single-use link that connects `identity` to the account
+async fn request_link_confirmation(app_state: &AppState, user: &User, identity: OidcIdentity) -> Result<(), AppError> {
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  let collection = app_state.db.collection::<ActionToken>("action_tokens");
+
+  // Only the latest link works
+  collection.delete_many(doc! { "user_id": user_id, "purpose": "oidc_link", "used_at": null })
+    .await
+    .internal_error("Failed to clear link tokens")?;
+
+  let token = generate_token();
+  let now = Utc::now();
+  let issuer = identity.issuer.clone();
+  let record = ActionToken {
+    id: None,
+    user_id,
+    purpose: TokenPurpose::OidcLink,
+    token_hash: hash_token(&token),
+    oidc_identity: Some(identity),
+    expires_at: now + Duration::minutes(LINK_CONFIRMATION_TTL_MINUTES),
+    used_at: None,
+    created_at: now,
+  };
+  collection.insert_one(&record)
+    .await
+    .internal_error("Failed to store link token")?;
+
+  let link = format!("{}/link-sign-in?token={token}", app_state.config.public_base_url);
+  let body = format!(
+    "Hi {},\n\n\
+    Someone signed in at {issuer} with this email address and asked to connect that sign-in to your To-Do List account.\n\
+    Use this link within {LINK_CONFIRMATION_TTL_MINUTES} minutes to allow it:\n\n{link}\n\n\
+    If it wasn't you, ignore this email and nothing will be connected.\n",
+    user.full_name,
+  );
+
+  app_state.mailer.send(Email {
+    to: user.email.clone(),
+    subject: "Connect a sign-in to your account".to_string(),
+    body,
+  }).await
+}
+
+async fn create_oidc_user(
+  app_state: &AppState,
+  claims: &IdTokenClaims,
+  email: String,
+  role: String,
+  identity: OidcIdentity,
+) -> Result<User, AppError> {
+  if !role_exists(app_state, &role).await? {
+    tracing::error!(role = %role, "OIDC default role does not exist");
+    return Err(AppError::internal_error("Single sign-on is misconfigured"));
+  }
+
+  let mut user = User {
+    id: None,
+    full_name: claims.name.clone().unwrap_or_else(|| email.clone()),
+    email,
+    // Nobody knows this password; the account signs in through the provider or a password reset
+    password: hash_password(&app_state.config.password_hash, generate_token()).await?,
+    role,
+    created_by: None,
+    updated_by: None,
+    deleted: false,
+    deleted_at: None,
+    anonymized_at: None,
+    verified: true,
+    verification_sent_at: None,
+    calendar_token_hash: None,
+    token_generation: 0,
+    mfa: None,
+    oidc_identity: Some(identity),
+    preferences: Default::default(),
+    created_at: Some(Utc::now()),
+    updated_at: Some(Utc::now()),
+  };
+
+  let result = app_state.db.collection::<User>("users")
+    .insert_one(&user)
+    .await
+    .internal_error("Failed to insert user into database")?;
+  user.id = result.inserted_id.as_object_id();
+
+  tracing::info!(user = %user.email, "created user from OIDC login");
+  Ok(user)
+}
+
+async fn role_exists(app_state: &AppState, name: &str) -> Result<bool, AppError> {
+  let role = app_state.db.collection::<Role>("roles")
+    .find_one(doc! { "name": name })
+    .await
+    .internal_error("Failed to query roles")?;
+  Ok(role.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::body::Body;
+  use axum::http::Request;
+  use serde_json::json;
+  use crate::{
+    models::ADMIN_ROLE,
+    test_support::{create_user, request, send, test_state_with, MockIdp},
+  };
+
+  // Start a login and return its state and nonce, and the cookie the browser was given
+  async fn start_login(app_state: &AppState) -> (String, String, String) {
+    let get = Request::get("/api/auth/oidc/login").body(Body::empty()).unwrap();
+    let response = request(app_state, get).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    let location = reqwest::Url::parse(response.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+    let param = |name: &str| location.query_pairs().find(|(key, _)| key == name).unwrap().1.into_owned();
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+    (param("state"), param("nonce"), cookie)
+  }
+
+  async fn callback(app_state: &AppState, state: &str, cookie: Option<&str>) -> StatusCode {
+    let mut get = Request::get(format!("/api/auth/oidc/callback?code=code&state={state}"));
+    if let Some(cookie) = cookie {
+      get = get.header(header::COOKIE, cookie);
+    }
+    request(app_state, get.body(Body::empty()).unwrap()).await.status()
+  }
+
+  async fn find_user(app_state: &AppState, email: &str) -> User {
+    app_state.db.collection::<User>("users").find_one(doc! { "email": email }).await.unwrap().unwrap()
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn callback_needs_the_browser_that_started_the_login() {
+    let idp = MockIdp::start().await;
+    let app_state = test_state_with(|config| config.oidc = Some(idp.config())).await;
+    let (state, nonce, cookie) = start_login(&app_state).await;
+    idp.issue_id_token(json!({ "sub": "alice", "email": "alice@example.com", "email_verified": true, "nonce": nonce })).await;
+
+    assert_eq!(callback(&app_state, &state, None).await, StatusCode::BAD_REQUEST);
+    let (_, _, other_cookie) = start_login(&app_state).await;
+    assert_eq!(callback(&app_state, &state, Some(&other_cookie)).await, StatusCode::BAD_REQUEST);
+
+    assert_eq!(callback(&app_state, &state, Some(&cookie)).await, StatusCode::OK);
+    assert!(find_user(&app_state, "alice@example.com").await.oidc_identity.is_some());
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn existing_accounts_are_linked_only_after_their_owner_confirms() {
+    let idp = MockIdp::start().await;
+    let app_state = test_state_with(|config| config.oidc = Some(idp.config())).await;
+    let admin = create_user(&app_state, "admin@example.com", ADMIN_ROLE).await;
+
+    let (state, nonce, cookie) = start_login(&app_state).await;
+    idp.issue_id_token(json!({ "sub": "mallory", "email": "Admin@example.com", "email_verified": true, "nonce": nonce })).await;
+    assert_eq!(callback(&app_state, &state, Some(&cookie)).await, StatusCode::ACCEPTED);
+    assert!(find_user(&app_state, "admin@example.com").await.oidc_identity.is_none());
+
+    // Stand in for the link mailed to the account
+    app_state.db.collection::<ActionToken>("action_tokens")
+      .update_one(
+        doc! { "user_id": admin.id, "purpose": "oidc_link" },
+        doc! { "$set": { "token_hash": hash_token("mailed") } },
+      )
+      .await
+      .unwrap();
+    let (status, _) = send(&app_state, "POST", "/api/auth/oidc/link", None, Some(json!({ "token": "mailed" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let identity = find_user(&app_state, "admin@example.com").await.oidc_identity.unwrap();
+    assert_eq!(identity.subject, "mallory");
+
+    let (status, _) = send(&app_state, "POST", "/api/auth/oidc/link", None, Some(json!({ "token": "mailed" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+  }
+}
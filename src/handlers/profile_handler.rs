@@ -0,0 +1,266 @@
+use axum::{
+  extract::State,
+  response::Json,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use crate::{
+  db::{email_collation, is_duplicate_key, AppState},
+  auth::{check_password_policy, end_other_sessions, hash_password, verify_current_password, AuthenticatedUser, ClientInfo, PermissionGuard},
+  models::{User, UserPreferences},
+  dtos::{
+    ChangeEmailRequest, ChangePasswordRequest, MessageResponse, UpdatePreferencesRequest, UpdateProfileRequest,
+    UserResponse,
+  },
+  notifications::{send_security_alert, SecurityAlert},
+  utils::{ResultExt, AppError, FieldError},
+};
+use crate::handlers::{revoke_api_tokens, send_verification_email};
+
+async fn load_own_account(app_state: &AppState, user: &AuthenticatedUser) -> Result<User, AppError> {
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  app_state.db.collection::<User>("users")
+    .find_one(doc! { "_id": user_id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))
+}
+
+/// Preferences of `user_id`, or the defaults when the account has none or is gone
+pub async fn load_preferences(app_state: &AppState, user_id: ObjectId) -> Result<UserPreferences, AppError> {
+  #[derive(serde::Deserialize)]
+  struct Projection {
+    #[serde(default)]
+    preferences: UserPreferences,
+  }
+
+  let projection = app_state.db.collection::<Projection>("users")
+    .find_one(doc! { "_id": user_id })
+    .projection(doc! { "preferences": 1 })
+    .await
+    .internal_error("Failed to query database")?;
+  Ok(projection.map(|projection| projection.preferences).unwrap_or_default())
+}
+
+// A language tag such as `en`, `vi-VN` or `zh-Hant-TW`
+fn is_language_tag(locale: &str) -> bool {
+  let mut subtags = locale.split('-');
+  let language = subtags.next().unwrap_or_default();
+  (2..=3).contains(&language.len())
+    && language.chars().all(|c| c.is_ascii_alphabetic())
+    && subtags.all(|subtag| (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+pub async fn get_me(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Json<UserResponse>, AppError> {
+  let account = load_own_account(&app_state, &user).await?;
+  Ok(Json(account.into()))
+}
+
+pub async fn update_me(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Json(payload): Json<UpdateProfileRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+  let mut account = load_own_account(&app_state, &user).await?;
+  let user_id = account.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+
+  if let Some(full_name) = payload.full_name {
+    let full_name = full_name.trim().to_string();
+    if full_name.is_empty() {
+      return Err(AppError::bad_request("Full name cannot be empty"));
+    }
+    account.full_name = full_name;
+  }
+  account.updated_by = Some(user_id);
+  account.updated_at = Some(Utc::now());
+
+  app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": user_id, "deleted": false }, doc! { "$set": {
+      "full_name": &account.full_name,
+      "updated_by": user_id,
+      "updated_at": mongodb::bson::to_bson(&account.updated_at).internal_error("Failed to encode timestamp")?,
+    } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  Ok(Json(account.into()))
+}
+
+// Keeps the session the change was made from and ends all others
+pub async fn change_my_password(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  client: ClientInfo,
+  Json(payload): Json<ChangePasswordRequest>,
+) -> Result<Json<MessageResponse>, AppError> {
+  user.require_session()?;
+  let session_id = user.session_id
+      .ok_or_else(|| AppError::forbidden("This endpoint needs a login session; sign in again"))?;
+
+  let account = load_own_account(&app_state, &user).await?;
+  let user_id = account.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  // Changing credentials needs the current password, so a stolen access token is not enough
+  verify_current_password(&app_state, &account, payload.current_password, &client).await?;
+  check_password_policy(
+    &app_state.config.password_policy,
+    "new_password",
+    &payload.new_password,
+    &[&account.email, &account.full_name],
+  ).await?;
+
+  let password = hash_password(&app_state.config.password_hash, payload.new_password).await?;
+  app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": user_id, "deleted": false }, doc! { "$set": {
+      "password": password,
+      "updated_by": user_id,
+      "updated_at": mongodb::bson::to_bson(&Utc::now()).internal_error("Failed to encode timestamp")?,
+    } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  end_other_sessions(&app_state, user_id, session_id).await?;
+  if !payload.keep_api_tokens {
+    revoke_api_tokens(&app_state, user_id).await?;
+  }
+  tracing::info!(user_id = %user_id, "password changed by user");
+  send_security_alert(&app_state, &account, SecurityAlert::PasswordChanged).await;
+
+  let message = if payload.keep_api_tokens {
+    "Password has been changed; other sessions were logged out"
+  } else {
+    "Password has been changed; other sessions were logged out and API tokens revoked"
+  };
+  Ok(Json(MessageResponse { message: message.to_string() }))
+}
+
+// The new address is unverified until its link is followed
+pub async fn change_my_email(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  client: ClientInfo,
+  Json(payload): Json<ChangeEmailRequest>,
+) -> Result<Json<UserResponse>, AppError> {
+  user.require_session()?;
+
+  let mut account = load_own_account(&app_state, &user).await?;
+  let user_id = account.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  verify_current_password(&app_state, &account, payload.current_password, &client).await?;
+
+  payload.new_email.parse::<lettre::Address>()
+      .bad_request("Invalid email address")?;
+  if payload.new_email == account.email {
+    return Err(AppError::bad_request("This is already your email address"));
+  }
+
+  let users = app_state.db.collection::<User>("users");
+  let existing_user = users
+    .find_one(doc! { "email": &payload.new_email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  if existing_user.is_some() {
+    return Err(AppError::conflict("User already exists"));
+  }
+
+  // The alert goes to the address being replaced
+  let previous_account = account.clone();
+  let now = Utc::now();
+  account.email = payload.new_email;
+  account.verified = false;
+  account.verification_sent_at = Some(now);
+  account.updated_by = Some(user_id);
+  account.updated_at = Some(now);
+  let now = mongodb::bson::to_bson(&now).internal_error("Failed to encode timestamp")?;
+  let update = doc! { "$set": {
+    "email": &account.email,
+    "verified": false,
+    "verification_sent_at": now.clone(),
+    "updated_by": user_id,
+    "updated_at": now,
+  } };
+  match users.update_one(doc! { "_id": user_id, "deleted": false }, update).await {
+    Ok(_) => {}
+    // The unique email index caught an account that took the address since the check above
+    Err(e) if is_duplicate_key(&e) => return Err(AppError::conflict("Email already in use")),
+    Err(_) => return Err(AppError::internal_error("Failed to update user")),
+  }
+
+  tracing::info!(user_id = %user_id, "email address changed by user");
+  send_verification_email(&app_state, user_id, account.full_name.clone(), account.email.clone());
+  let alert = SecurityAlert::EmailChanged { new_email: account.email.clone() };
+  send_security_alert(&app_state, &previous_account, alert).await;
+
+  Ok(Json(account.into()))
+}
+
+pub async fn get_my_preferences(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Json<UserPreferences>, AppError> {
+  let account = load_own_account(&app_state, &user).await?;
+  Ok(Json(account.preferences))
+}
+
+pub async fn update_my_preferences(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Json(payload): Json<UpdatePreferencesRequest>,
+) -> Result<Json<UserPreferences>, AppError> {
+  let account = load_own_account(&app_state, &user).await?;
+  let user_id = account.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  let mut preferences = account.preferences;
+  let mut errors = Vec::new();
+
+  if let Some(timezone) = payload.timezone {
+    match timezone.parse() {
+      Ok(timezone) => preferences.timezone = timezone,
+      Err(_) => errors.push(FieldError::new("timezone", "invalid", "Use an IANA time zone such as `Europe/Berlin`")),
+    }
+  }
+  if let Some(locale) = payload.locale {
+    if is_language_tag(&locale) {
+      preferences.locale = locale;
+    } else {
+      errors.push(FieldError::new("locale", "invalid", "Use a language tag such as `en` or `vi-VN`"));
+    }
+  }
+  if let Some(week_start) = payload.week_start {
+    preferences.week_start = week_start;
+  }
+  if let Some(priority) = payload.default_priority {
+    preferences.default_priority = priority;
+  }
+  if let Some(notifications) = payload.notifications {
+    let settings = &mut preferences.notifications;
+    settings.due_reminders = notifications.due_reminders.unwrap_or(settings.due_reminders);
+    settings.security_alerts = notifications.security_alerts.unwrap_or(settings.security_alerts);
+    match notifications.reminder_hour {
+      Some(hour) if hour > 23 => {
+        errors.push(FieldError::new("notifications.reminder_hour", "invalid", "Use an hour from 0 to 23"));
+      }
+      Some(hour) => settings.reminder_hour = hour,
+      None => {}
+    }
+  }
+  if !errors.is_empty() {
+    return Err(AppError::validation(errors));
+  }
+
+  app_state.db.collection::<User>("users")
+    .update_one(doc! { "_id": user_id, "deleted": false }, doc! { "$set": {
+      "preferences": mongodb::bson::to_bson(&preferences).internal_error("Failed to encode preferences")?,
+      "updated_at": mongodb::bson::to_bson(&Utc::now()).internal_error("Failed to encode timestamp")?,
+    } })
+    .await
+    .internal_error("Failed to update user")?;
+
+  Ok(Json(preferences))
+}
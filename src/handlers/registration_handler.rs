@@ -0,0 +1,149 @@
+use axum::{
+  extract::State,
+  http::StatusCode,
+  response::Json,
+};
+use mongodb::bson::doc;
+use chrono::Utc;
+use crate::{
+  db::{email_collation, AppState},
+  auth::{check_password_policy, hash_password},
+  config::SignupPolicy,
+  mailer::Email,
+  models::{Permission, Role, User},
+  dtos::{MessageResponse, RegisterRequest},
+  utils::{ResultExt, AppError},
+};
+use crate::handlers::send_verification_email;
+
+// Everything a self-registered account may be given
+const SIGNUP_PERMISSIONS: [Permission; 2] = [Permission::TasksRead, Permission::TasksWrite];
+
+// A role is safe for strangers when it only touches their own tasks
+fn is_signup_role(permissions: &[Permission]) -> bool {
+  permissions.iter().all(|permission| SIGNUP_PERMISSIONS.contains(permission))
+}
+
+// Create an account for oneself, as far as `SIGNUP_POLICY` allows
+// Answers the same way whether or not the address is taken; its owner is told by email instead
+pub async fn register(
+  State(app_state): State<AppState>,
+  Json(payload): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<MessageResponse>), AppError> {
+  let address = payload.email.parse::<lettre::Address>()
+      .bad_request("Invalid email address")?;
+
+  match &app_state.config.signup_policy {
+    SignupPolicy::Open => {}
+    SignupPolicy::AllowedDomains(domains) => {
+      if !domains.contains(&address.domain().to_lowercase()) {
+        return Err(AppError::forbidden("Registration is not open to this email domain"));
+      }
+    }
+    SignupPolicy::InviteOnly => return Err(AppError::forbidden("Registration is by invitation only")),
+    SignupPolicy::Disabled => return Err(AppError::forbidden("Registration is disabled")),
+  }
+
+  let role = app_state.db.collection::<Role>("roles")
+    .find_one(doc! { "name": &app_state.config.signup_default_role })
+    .await
+    .internal_error("Failed to query roles")?;
+  if !role.is_some_and(|role| is_signup_role(&role.permissions)) {
+    tracing::error!(role = %app_state.config.signup_default_role, "signup default role is missing or grants more than task access");
+    return Err(AppError::internal_error("Registration is misconfigured"));
+  }
+
+  check_password_policy(
+    &app_state.config.password_policy,
+    "password",
+    &payload.password,
+    &[&payload.email, &payload.full_name],
+  ).await?;
+
+  // The lookup, hashing and email happen in the background so timing does not tell either case
+  tokio::spawn(async move {
+    if let Err(e) = create_or_notify(&app_state, payload).await {
+      tracing::error!(error = %e.message, "failed to register user");
+    }
+  });
+
+  let response = MessageResponse {
+    message: "Check your email to finish signing up".to_string(),
+  };
+  Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+async fn create_or_notify(app_state: &AppState, payload: RegisterRequest) -> Result<(), AppError> {
+  let collection = app_state.db.collection::<User>("users");
+  let existing_user = collection
+    .find_one(doc! { "email": &payload.email, "deleted": false })
+    .collation(email_collation())
+    .await
+    .internal_error("Failed to query database")?;
+  if let Some(existing_user) = existing_user {
+    return notify_existing_owner(app_state, existing_user).await;
+  }
+
+  let user = User {
+    id: None,
+    full_name: payload.full_name,
+    email: payload.email,
+    password: hash_password(&app_state.config.password_hash, payload.password).await?,
+    role: app_state.config.signup_default_role.clone(),
+    created_by: None,
+    updated_by: None,
+    deleted: false,
+    deleted_at: None,
+    anonymized_at: None,
+    verified: false,
+    verification_sent_at: Some(Utc::now()),
+    calendar_token_hash: None,
+    token_generation: 0,
+    mfa: None,
+    oidc_identity: None,
+    preferences: Default::default(),
+    created_at: Some(Utc::now()),
+    updated_at: Some(Utc::now()),
+  };
+
+  let result = collection.insert_one(&user).await
+      .internal_error("Failed to insert user into database")?;
+  let user_id = result.inserted_id.as_object_id()
+      .ok_or_else(|| AppError::internal_error("Failed to get inserted user ID"))?;
+
+  send_verification_email(app_state, user_id, user.full_name.clone(), user.email.clone());
+  tracing::info!(user = %user.email, "user registered");
+  Ok(())
+}
+
+// Someone signed up with an address that already has an account
+async fn notify_existing_owner(app_state: &AppState, user: User) -> Result<(), AppError> {
+  let body = format!(
+    "Hi {},\n\n\
+    Someone tried to create a To-Do List account with this address, which already has one.\n\
+    If it was you, sign in at {} or reset your password there. Otherwise you can ignore this email.\n",
+    user.full_name,
+    app_state.config.public_base_url,
+  );
+  app_state.mailer.send(Email {
+    to: user.email,
+    subject: "You already have an account".to_string(),
+    body,
+  }).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn signup_roles_only_grant_task_access() {
+    assert!(is_signup_role(&[Permission::TasksRead, Permission::TasksWrite]));
+    assert!(is_signup_role(&[Permission::TasksRead]));
+    assert!(is_signup_role(&[]));
+
+    assert!(!is_signup_role(&[Permission::TasksRead, Permission::TasksReadAny]));
+    assert!(!is_signup_role(&[Permission::TasksWrite, Permission::UsersWrite]));
+    assert!(!is_signup_role(&Permission::ALL));
+  }
+}
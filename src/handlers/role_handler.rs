@@ -0,0 +1,237 @@
+use axum::{
+  extract::{State, Path},
+  http::StatusCode,
+  response::Json,
+};
+use futures_util::TryStreamExt;
+use mongodb::bson::{doc, to_bson};
+use chrono::Utc;
+use std::collections::HashSet;
+use crate::{
+  db::AppState,
+  auth::{AuthenticatedUser, PermissionGuard},
+  models::{Permission, Role, User, ADMIN_ROLE},
+  dtos::{CreateRoleRequest, UpdateRoleRequest, RoleResponse},
+  utils::{ResultExt, AppError},
+};
+
+/// Reject role names that do not match a defined role
+pub async fn ensure_role_exists(app_state: &AppState, name: &str) -> Result<(), AppError> {
+  let role = app_state.db.collection::<Role>("roles")
+    .find_one(doc! { "name": name })
+    .await
+    .internal_error("Failed to query database")?;
+  if role.is_none() {
+    return Err(AppError::bad_request(format!("Unknown role `{name}`")));
+  }
+  Ok(())
+}
+
+/// Reject roles that would give someone a permission the caller does not have
+pub async fn ensure_role_grantable(
+  app_state: &AppState,
+  caller: &AuthenticatedUser,
+  name: &str,
+) -> Result<(), AppError> {
+  let role = app_state.db.collection::<Role>("roles")
+    .find_one(doc! { "name": name })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::bad_request(format!("Unknown role `{name}`")))?;
+  ensure_permissions_held(caller, &role.permissions, "Cannot assign a role with permissions you do not have")
+}
+
+/// Reject changes to an account whose role has a permission the caller does not have,
+/// so a lesser admin cannot take over a greater one
+pub async fn ensure_can_manage(
+  app_state: &AppState,
+  caller: &AuthenticatedUser,
+  target: &User,
+) -> Result<(), AppError> {
+  let role = app_state.db.collection::<Role>("roles")
+    .find_one(doc! { "name": &target.role })
+    .await
+    .internal_error("Failed to query database")?;
+  let permissions = role.map(|role| role.permissions).unwrap_or_default();
+  ensure_permissions_held(caller, &permissions, "Cannot manage an account with permissions you do not have")
+}
+
+fn ensure_permissions_held(caller: &AuthenticatedUser, permissions: &[Permission], message: &str) -> Result<(), AppError> {
+  if !permissions.iter().all(|permission| caller.has_permission(*permission)) {
+    return Err(AppError::forbidden(message));
+  }
+  Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+  let valid = !name.is_empty()
+    && name.len() <= 64
+    && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+  if !valid {
+    return Err(AppError::bad_request("Role names use lowercase letters, digits, `_` and `-`"));
+  }
+  Ok(())
+}
+
+fn dedup(permissions: &mut Vec<Permission>) {
+  let mut seen = HashSet::new();
+  permissions.retain(|permission| seen.insert(*permission));
+}
+
+pub async fn list_roles(
+  State(app_state): State<AppState>,
+) -> Result<Json<Vec<RoleResponse>>, AppError> {
+  let roles: Vec<Role> = app_state.db.collection::<Role>("roles")
+    .find(doc! {})
+    .sort(doc! { "name": 1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+
+  Ok(Json(roles.into_iter().map(RoleResponse::from).collect()))
+}
+
+pub async fn create_role(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Json(payload): Json<CreateRoleRequest>,
+) -> Result<Json<RoleResponse>, AppError> {
+  validate_name(&payload.name)?;
+  ensure_permissions_held(&user, &payload.permissions, "Cannot grant permissions you do not have")?;
+
+  let collection = app_state.db.collection::<Role>("roles");
+  let existing = collection.find_one(doc! { "name": &payload.name })
+    .await
+    .internal_error("Failed to query database")?;
+  if existing.is_some() {
+    return Err(AppError::conflict("Role already exists"));
+  }
+
+  let mut role = Role {
+    id: None,
+    name: payload.name,
+    description: payload.description,
+    permissions: payload.permissions,
+    built_in: false,
+    created_at: Some(Utc::now()),
+    updated_at: Some(Utc::now()),
+  };
+  dedup(&mut role.permissions);
+
+  let result = collection.insert_one(&role)
+    .await
+    .conflict("Role already exists")?;
+  role.id = result.inserted_id.as_object_id();
+
+  Ok(Json(RoleResponse::from(role)))
+}
+
+pub async fn update_role(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Path(name): Path<String>,
+  Json(payload): Json<UpdateRoleRequest>,
+) -> Result<Json<RoleResponse>, AppError> {
+  // Editing `admin` could lock everyone out of role management
+  if name == ADMIN_ROLE {
+    return Err(AppError::forbidden("The admin role cannot be changed"));
+  }
+  if name == user.role {
+    return Err(AppError::forbidden("You cannot change your own role"));
+  }
+
+  let collection = app_state.db.collection::<Role>("roles");
+  let mut role = collection.find_one(doc! { "name": &name })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("Role not found"))?;
+  // Neither the role as it is nor as it would become may outrank the caller
+  ensure_permissions_held(&user, &role.permissions, "Cannot change a role with permissions you do not have")?;
+
+  if let Some(description) = payload.description {
+    role.description = Some(description);
+  }
+  if let Some(permissions) = payload.permissions {
+    ensure_permissions_held(&user, &permissions, "Cannot grant permissions you do not have")?;
+    role.permissions = permissions;
+    dedup(&mut role.permissions);
+  }
+  role.updated_at = Some(Utc::now());
+
+  let permissions = to_bson(&role.permissions)
+      .internal_error("Failed to encode permissions")?;
+  let updated_at = to_bson(&role.updated_at)
+      .internal_error("Failed to encode timestamp")?;
+  collection.update_one(
+      doc! { "name": &name },
+      doc! { "$set": {
+        "description": role.description.clone(),
+        "permissions": permissions,
+        "updated_at": updated_at,
+      } },
+    )
+    .await
+    .internal_error("Failed to update role")?;
+
+  Ok(Json(RoleResponse::from(role)))
+}
+
+pub async fn delete_role(
+  State(app_state): State<AppState>,
+  Path(name): Path<String>,
+) -> Result<StatusCode, AppError> {
+  let collection = app_state.db.collection::<Role>("roles");
+  let role = collection.find_one(doc! { "name": &name })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("Role not found"))?;
+  if role.built_in {
+    return Err(AppError::forbidden("Built-in roles cannot be deleted"));
+  }
+
+  let in_use = app_state.db.collection::<User>("users")
+    .count_documents(doc! { "role": &name, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?;
+  if in_use > 0 {
+    return Err(AppError::conflict(format!("Role is still assigned to {in_use} user(s)")));
+  }
+
+  collection.delete_one(doc! { "name": &name })
+    .await
+    .internal_error("Failed to delete role")?;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use crate::test_support::{create_user, login_token, send, test_state};
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn roles_cannot_grant_more_than_the_caller_has() {
+    let app_state = test_state().await;
+    app_state.db.collection::<mongodb::bson::Document>("roles")
+      .insert_one(doc! { "name": "role-editor", "permissions": ["roles:read", "roles:write"], "built_in": false })
+      .await
+      .unwrap();
+    let editor = create_user(&app_state, "editor@example.com", "role-editor").await;
+    let token = login_token(&app_state, &editor).await;
+
+    let escalate = json!({ "name": "helpdesk", "permissions": ["roles:read", "users:write"] });
+    let (status, _) = send(&app_state, "POST", "/api/roles", Some(&token), Some(escalate)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app_state, "PUT", "/api/roles/user", Some(&token), Some(json!({ "permissions": ["users:write"] }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app_state, "PUT", "/api/roles/role-editor", Some(&token), Some(json!({ "permissions": ["roles:read"] }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(&app_state, "POST", "/api/roles", Some(&token), Some(json!({ "name": "viewer", "permissions": ["roles:read"] }))).await;
+    assert_eq!(status, StatusCode::OK);
+  }
+}
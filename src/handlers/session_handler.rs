@@ -0,0 +1,74 @@
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::Json,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use crate::{
+  db::AppState,
+  auth::{end_session, AuthenticatedUser, PermissionGuard},
+  models::Session,
+  dtos::SessionResponse,
+  utils::{ResultExt, AppError},
+};
+
+// Where the caller is currently logged in, most recently used first
+pub async fn list_sessions(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+) -> Result<Json<Vec<SessionResponse>>, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let sessions: Vec<Session> = app_state.db.collection::<Session>("sessions")
+    .find(doc! {
+      "user_id": user_id,
+      "revoked_at": null,
+      "impersonator_id": null,
+      "expires_at": { "$gt": mongodb::bson::DateTime::from_chrono(Utc::now()) },
+    })
+    .sort(doc! { "last_seen_at": -1 })
+    .await
+    .internal_error("Failed to query database")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query database")?;
+
+  let response = sessions.into_iter()
+    .map(|session| SessionResponse {
+      id: session.id.map(|id| id.to_hex()).unwrap_or_default(),
+      current: session.id.is_some() && session.id == user.session_id,
+      user_agent: session.user_agent,
+      ip: session.ip,
+      created_at: session.created_at,
+      last_seen_at: session.last_seen_at,
+    })
+    .collect();
+
+  Ok(Json(response))
+}
+
+// Log out one of the caller's sessions, e.g. a lost device
+pub async fn delete_session(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Path(id): Path<ObjectId>,
+) -> Result<StatusCode, AppError> {
+  user.require_session()?;
+
+  let user_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+  let session = app_state.db.collection::<Session>("sessions")
+    .find_one(doc! { "_id": id, "user_id": user_id, "revoked_at": null })
+    .await
+    .internal_error("Failed to query database")?;
+  if session.is_none() {
+    return Err(AppError::not_found("Session not found"));
+  }
+
+  end_session(&app_state, id).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,424 @@
+use axum::{
+  extract::{State, Query},
+  response::Json,
+};
+use futures_util::StreamExt;
+use mongodb::bson::{self, bson, doc, oid::ObjectId, Bson, Document};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use chrono::{DateTime, Duration, Utc};
+use crate::{
+  db::AppState,
+  auth::AuthenticatedUser,
+  handlers::{load_preferences, TaskQuery},
+  models::{Task, TaskPriority, TaskStatus, UserPreferences, WeekStart},
+  dtos::{BurndownPoint, StatsBucket, StatsResponse, ThroughputPoint},
+  utils::{ResultExt, AppError},
+};
+
+const DEFAULT_RANGE_DAYS: i64 = 30;
+const MAX_RANGE_DAYS: i64 = 366;
+
+const DAY_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+  // Only honoured for admins
+  pub user_id: Option<ObjectId>,
+  pub from: Option<DateTime<Utc>>,
+  pub to: Option<DateTime<Utc>>,
+  #[serde(default)]
+  pub bucket: StatsBucket,
+}
+
+#[derive(Deserialize)]
+struct Bucket {
+  #[serde(rename = "_id")]
+  key: Option<String>,
+  count: i64,
+}
+
+#[derive(Deserialize)]
+struct Count {
+  count: i64,
+}
+
+#[derive(Deserialize)]
+struct Durations {
+  lead_ms: Option<f64>,
+  cycle_ms: Option<f64>,
+}
+
+// Output of the `$facet` stage, one field per sub-pipeline
+#[derive(Deserialize)]
+struct Facets {
+  by_status: Vec<Bucket>,
+  by_priority: Vec<Bucket>,
+  overdue: Vec<Count>,
+  created: Vec<Bucket>,
+  completed: Vec<Bucket>,
+  durations: Vec<Durations>,
+  backlog: Vec<Count>,
+  burndown_created: Vec<Bucket>,
+  burndown_completed: Vec<Bucket>,
+}
+
+/// Convert a stored timestamp into a BSON date
+/// Task timestamps are written as RFC 3339 strings, so only the seconds are parsed
+pub fn to_date(field: &str) -> Bson {
+  let path = format!("${field}");
+  bson!({
+    "$switch": {
+      "branches": [
+        { "case": { "$eq": [{ "$type": path.as_str() }, "date"] }, "then": path.as_str() },
+        { "case": { "$eq": [{ "$type": path.as_str() }, "string"] }, "then": {
+          "$dateFromString": {
+            "dateString": { "$substrBytes": [path.as_str(), 0, 19] },
+            "format": "%Y-%m-%dT%H:%M:%S",
+            "timezone": "UTC",
+            "onError": null,
+          }
+        } },
+      ],
+      "default": null,
+    }
+  })
+}
+
+fn count_by(key: Bson) -> Document {
+  doc! { "$group": { "_id": key, "count": { "$sum": 1 } } }
+}
+
+// Periods are days or weeks of the user's calendar, not of UTC; a week is labelled by its first day
+fn count_per_period(field: &str, bucket: StatsBucket, preferences: &UserPreferences) -> Document {
+  let timezone = preferences.timezone.name();
+  let date = match bucket {
+    StatsBucket::Day => Bson::String(format!("${field}")),
+    StatsBucket::Week => {
+      let start_of_week = match preferences.week_start {
+        WeekStart::Monday => "monday",
+        WeekStart::Saturday => "saturday",
+        WeekStart::Sunday => "sunday",
+      };
+      bson!({ "$dateTrunc": { "date": format!("${field}"), "unit": "week", "timezone": timezone, "startOfWeek": start_of_week } })
+    }
+  };
+  count_by(bson!({ "$dateToString": { "format": DAY_FORMAT, "date": date, "timezone": timezone } }))
+}
+
+fn build_pipeline(
+  filter: Document,
+  from: DateTime<Utc>,
+  to: DateTime<Utc>,
+  bucket: StatsBucket,
+  preferences: &UserPreferences,
+) -> Vec<Document> {
+  // Tasks due today are not overdue yet
+  let start_of_today = bson::DateTime::from_chrono(preferences.today(Utc::now()).start);
+  let from = bson::DateTime::from_millis(from.timestamp_millis());
+  let to = bson::DateTime::from_millis(to.timestamp_millis());
+  let closed = vec!["completed", "cancelled"];
+
+  vec![
+    doc! { "$match": filter },
+    doc! { "$addFields": {
+      "created_on": to_date("created_at"),
+      "started_on": to_date("started_at"),
+      "completed_on": to_date("completed_at"),
+      "due_on": to_date("due_date"),
+    } },
+    doc! { "$facet": {
+      "by_status": [count_by(Bson::String("$status".to_string()))],
+      "by_priority": [count_by(Bson::String("$priority".to_string()))],
+      "overdue": [
+        { "$match": { "due_on": { "$ne": null, "$lt": start_of_today }, "status": { "$nin": closed } } },
+        { "$count": "count" },
+      ],
+      "created": [
+        { "$match": { "created_on": { "$gte": from, "$lt": to } } },
+        count_per_period("created_on", bucket, preferences),
+      ],
+      "completed": [
+        { "$match": { "completed_on": { "$gte": from, "$lt": to } } },
+        count_per_period("completed_on", bucket, preferences),
+      ],
+      // Backfilled timestamps are guesses and would skew the averages
+      "durations": [
+        { "$match": { "completed_on": { "$gte": from, "$lt": to }, "status_timestamps_estimated": { "$ne": true } } },
+        { "$group": {
+          "_id": null,
+          "lead_ms": { "$avg": { "$subtract": ["$completed_on", "$created_on"] } },
+          "cycle_ms": { "$avg": { "$subtract": ["$completed_on", "$started_on"] } },
+        } },
+      ],
+      // Burndown ignores cancelled tasks: they were dropped, not done
+      "backlog": [
+        { "$match": {
+          "status": { "$ne": "cancelled" },
+          "created_on": { "$ne": null, "$lt": from },
+          "$or": [{ "completed_on": null }, { "completed_on": { "$gte": from } }],
+        } },
+        { "$count": "count" },
+      ],
+      "burndown_created": [
+        { "$match": { "status": { "$ne": "cancelled" }, "created_on": { "$gte": from, "$lt": to } } },
+        count_per_period("created_on", StatsBucket::Day, preferences),
+      ],
+      "burndown_completed": [
+        { "$match": { "completed_on": { "$gte": from, "$lt": to } } },
+        count_per_period("completed_on", StatsBucket::Day, preferences),
+      ],
+    } },
+  ]
+}
+
+fn to_counts(buckets: Vec<Bucket>) -> HashMap<String, i64> {
+  buckets
+    .into_iter()
+    .filter_map(|bucket| bucket.key.map(|key| (key, bucket.count)))
+    .collect()
+}
+
+/// Every period label between `from` and `to`, in order, on the user's calendar
+fn periods(from: DateTime<Utc>, to: DateTime<Utc>, bucket: StatsBucket, preferences: &UserPreferences) -> Vec<String> {
+  let last = preferences.local_date(to - Duration::nanoseconds(1));
+  let mut labels: Vec<String> = Vec::new();
+  for day in preferences.local_date(from).iter_days().take_while(|day| *day <= last) {
+    let first = match bucket {
+      StatsBucket::Day => day,
+      StatsBucket::Week => preferences.week_of(day),
+    };
+    let label = first.format(DAY_FORMAT).to_string();
+    if labels.last() != Some(&label) {
+      labels.push(label);
+    }
+  }
+  labels
+}
+
+fn enum_keys<T: serde::Serialize>(values: &[T]) -> BTreeMap<String, i64> {
+  values
+    .iter()
+    .filter_map(|value| match bson::to_bson(value) {
+      Ok(Bson::String(key)) => Some((key, 0)),
+      _ => None,
+    })
+    .collect()
+}
+
+pub async fn get_stats(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Query(query): Query<StatsQuery>,
+) -> Result<Json<StatsResponse>, AppError> {
+  let collection = app_state.db.collection::<Task>("tasks");
+
+  let to = query.to.unwrap_or_else(Utc::now);
+  let from = query.from.unwrap_or(to - Duration::days(DEFAULT_RANGE_DAYS));
+  if from >= to {
+    return Err(AppError::bad_request("`from` must be before `to`"));
+  }
+  if to - from > Duration::days(MAX_RANGE_DAYS) {
+    return Err(AppError::bad_request(format!("Date range cannot exceed {MAX_RANGE_DAYS} days")));
+  }
+
+  let filter = TaskQuery { user_id: query.user_id, status: None, priority: None, due: None }
+    .to_scoped_filter(&user)?;
+  let user_id = filter.get_object_id("user_id")
+    .internal_error("Failed to build task filter")?;
+  // Days and weeks are those of the user whose tasks these are, not of an admin looking at them
+  let preferences = load_preferences(&app_state, user_id).await?;
+
+  let mut cursor = collection.aggregate(build_pipeline(filter, from, to, query.bucket, &preferences))
+    .await
+    .internal_error("Failed to aggregate tasks")?;
+  let facets = cursor.next()
+    .await
+    .ok_or_else(|| AppError::internal_error("Failed to aggregate tasks"))?
+    .internal_error("Failed to aggregate tasks")?;
+  let facets: Facets = bson::from_document(facets)
+    .internal_error("Failed to read task statistics")?;
+
+  let mut by_status = enum_keys(&[TaskStatus::Pending, TaskStatus::InProgress, TaskStatus::Completed, TaskStatus::Cancelled]);
+  by_status.extend(to_counts(facets.by_status));
+  let mut by_priority = enum_keys(&[TaskPriority::Low, TaskPriority::Medium, TaskPriority::High, TaskPriority::Urgent]);
+  by_priority.extend(to_counts(facets.by_priority));
+
+  let created = to_counts(facets.created);
+  let completed = to_counts(facets.completed);
+  let throughput = periods(from, to, query.bucket, &preferences)
+    .into_iter()
+    .map(|period| ThroughputPoint {
+      created: created.get(&period).copied().unwrap_or(0),
+      completed: completed.get(&period).copied().unwrap_or(0),
+      period,
+    })
+    .collect();
+
+  let burndown_created = to_counts(facets.burndown_created);
+  let burndown_completed = to_counts(facets.burndown_completed);
+  let mut remaining = facets.backlog.first().map(|count| count.count).unwrap_or(0);
+  let burndown = periods(from, to, StatsBucket::Day, &preferences)
+    .into_iter()
+    .map(|date| {
+      remaining += burndown_created.get(&date).copied().unwrap_or(0);
+      remaining -= burndown_completed.get(&date).copied().unwrap_or(0);
+      BurndownPoint { date, remaining }
+    })
+    .collect();
+
+  let durations = facets.durations.first();
+  let to_hours = |ms: f64| ms / 3_600_000.0;
+
+  Ok(Json(StatsResponse {
+    user_id: user_id.to_hex(),
+    from,
+    to,
+    bucket: query.bucket,
+    by_status,
+    by_priority,
+    overdue: facets.overdue.first().map(|count| count.count).unwrap_or(0),
+    throughput,
+    average_lead_time_hours: durations.and_then(|d| d.lead_ms).map(to_hours),
+    average_cycle_time_hours: durations.and_then(|d| d.cycle_ms).map(to_hours),
+    burndown,
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::http::StatusCode;
+  use crate::{
+    db::run_migrations,
+    models::{ADMIN_ROLE, USER_ROLE},
+    test_support::{create_user, login_token, send, test_state},
+  };
+
+  fn utc(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn weeks_are_labelled_by_their_first_day() {
+    // Wednesday 2025-03-05 to Tuesday 2025-03-18
+    let (from, to) = (utc("2025-03-05T00:00:00Z"), utc("2025-03-19T00:00:00Z"));
+    let monday = UserPreferences::default();
+    assert_eq!(periods(from, to, StatsBucket::Week, &monday), vec!["2025-03-03", "2025-03-10", "2025-03-17"]);
+    let sunday = UserPreferences { week_start: WeekStart::Sunday, ..Default::default() };
+    assert_eq!(periods(from, to, StatsBucket::Week, &sunday), vec!["2025-03-02", "2025-03-09", "2025-03-16"]);
+    assert_eq!(periods(from, to, StatsBucket::Day, &sunday).len(), 14);
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn backfilled_timestamps_stay_out_of_durations() {
+    let app_state = test_state().await;
+    let member = create_user(&app_state, "member@example.com", USER_ROLE).await;
+    let member_id = member.id.unwrap();
+    let tasks = app_state.db.collection::<Document>("tasks");
+    let task = |title: &str| doc! {
+      "user_id": member_id,
+      "title": title,
+      "status": "completed",
+      "priority": "medium",
+      "deleted": false,
+      "created_at": "2025-03-10T08:00:00Z",
+    };
+    let mut tracked = task("Tracked");
+    tracked.insert("started_at", "2025-03-10T09:00:00Z");
+    tracked.insert("completed_at", "2025-03-10T10:00:00Z");
+    tracked.insert("updated_at", "2025-03-10T10:00:00Z");
+    tasks.insert_one(tracked).await.unwrap();
+    // Completed before status timestamps were recorded
+    let mut legacy = task("Legacy");
+    legacy.insert("updated_at", "2025-03-11T08:00:00Z");
+    let legacy_id = tasks.insert_one(legacy).await.unwrap().inserted_id;
+
+    run_migrations(&app_state.db, &app_state.config).await.unwrap();
+    let legacy = tasks.find_one(doc! { "_id": legacy_id }).await.unwrap().unwrap();
+    assert_eq!(legacy.get_str("completed_at").unwrap(), "2025-03-11T08:00:00Z");
+    assert!(legacy.get_bool("status_timestamps_estimated").unwrap());
+
+    let token = login_token(&app_state, &member).await;
+    let uri = "/api/stats?from=2025-03-10T00:00:00Z&to=2025-03-12T00:00:00Z";
+    let (status, body) = send(&app_state, "GET", uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    // Only the tracked task counts towards the averages, but both were completed
+    assert_eq!(body["average_lead_time_hours"], 2.0);
+    assert_eq!(body["average_cycle_time_hours"], 1.0);
+    assert_eq!(body["by_status"]["completed"], 2);
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn weekly_stats_follow_the_users_week_start() {
+    let app_state = test_state().await;
+    let member = create_user(&app_state, "member@example.com", USER_ROLE).await;
+    let member_id = member.id.unwrap();
+    app_state.db.collection::<Document>("users")
+      .update_one(doc! { "_id": member_id }, doc! { "$set": { "preferences.week_start": "sunday" } })
+      .await
+      .unwrap();
+    // Saturday, Sunday and Monday: the Sunday opens a new week, the Monday does not
+    for created_at in ["2025-03-08T12:00:00Z", "2025-03-09T12:00:00Z", "2025-03-10T12:00:00Z"] {
+      app_state.db.collection::<Document>("tasks")
+        .insert_one(doc! {
+          "user_id": member_id,
+          "title": "Task",
+          "status": "pending",
+          "priority": "medium",
+          "deleted": false,
+          "created_at": created_at,
+        })
+        .await
+        .unwrap();
+    }
+
+    let token = login_token(&app_state, &member).await;
+    let uri = "/api/stats?bucket=week&from=2025-03-05T00:00:00Z&to=2025-03-19T00:00:00Z";
+    let (status, body) = send(&app_state, "GET", uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let created: Vec<(&str, i64)> = body["throughput"].as_array().unwrap()
+      .iter()
+      .map(|point| (point["period"].as_str().unwrap(), point["created"].as_i64().unwrap()))
+      .collect();
+    assert_eq!(created, vec![("2025-03-02", 1), ("2025-03-09", 2), ("2025-03-16", 0)]);
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn another_users_stats_follow_their_time_zone() {
+    let app_state = test_state().await;
+    let admin = create_user(&app_state, "admin@example.com", ADMIN_ROLE).await;
+    let member = create_user(&app_state, "member@example.com", USER_ROLE).await;
+    let member_id = member.id.unwrap();
+    app_state.db.collection::<Document>("users")
+      .update_one(doc! { "_id": member_id }, doc! { "$set": { "preferences.timezone": "Asia/Ho_Chi_Minh" } })
+      .await
+      .unwrap();
+    // 20:00 UTC is already the next morning in Hanoi
+    app_state.db.collection::<Document>("tasks")
+      .insert_one(doc! {
+        "user_id": member_id,
+        "title": "Late task",
+        "status": "pending",
+        "priority": "medium",
+        "deleted": false,
+        "created_at": "2025-03-10T20:00:00Z",
+      })
+      .await
+      .unwrap();
+
+    let token = login_token(&app_state, &admin).await;
+    let uri = format!("/api/stats?user_id={}&from=2025-03-10T00:00:00Z&to=2025-03-12T00:00:00Z", member_id.to_hex());
+    let (status, body) = send(&app_state, "GET", &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let created: Vec<&str> = body["throughput"].as_array().unwrap()
+      .iter()
+      .filter(|point| point["created"] == 1)
+      .map(|point| point["period"].as_str().unwrap())
+      .collect();
+    assert_eq!(created, vec!["2025-03-11"]);
+  }
+}
@@ -1,15 +1,20 @@
 use axum::{
-  http::StatusCode, 
+  http::{header, StatusCode},
+  body::Body,
   extract::{State, Path, Query},
-  response::{Json, IntoResponse},
+  response::{Json, IntoResponse, Response},
+  BoxError,
 };
 use futures_util::StreamExt;
-use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, oid::ObjectId, Document};
 use serde::Deserialize;
 use crate::{
   db::AppState,
-  models::{Task, TaskStatus, TaskPriority},
-  dtos::{CreateTaskRequest, UpdateTaskRequest, TaskResponse},
+  auth::{AuthenticatedUser, PermissionGuard},
+  converters::{self, TransferFormat},
+  handlers::{load_preferences, to_date},
+  models::{Permission, Task, TaskStatus, TaskPriority, UserPreferences},
+  dtos::{CreateTaskRequest, DueWindow, UpdateTaskRequest, TaskResponse, ImportRowError, ImportTasksResponse},
 };
 use chrono::{DateTime, Utc};
 use crate::utils::{ResultExt, AppError};
@@ -20,18 +25,123 @@ pub struct TaskQuery {
   pub user_id: Option<ObjectId>,
   pub status: Option<TaskStatus>,
   pub priority: Option<TaskPriority>,
+  pub due: Option<DueWindow>,
+}
+
+impl TaskQuery {
+  /// Build the MongoDB filter for the requested status/priority/user
+  pub fn to_filter(&self) -> Result<Document, AppError> {
+    let mut filter = doc! { "deleted": false };
+    if let Some(user_id) = self.user_id {
+      filter.insert("user_id", user_id);
+    }
+    if let Some(status) = &self.status {
+      filter.insert("status", mongodb::bson::to_bson(status)
+          .internal_error("Failed to build task filter")?);
+    }
+    if let Some(priority) = &self.priority {
+      filter.insert("priority", mongodb::bson::to_bson(priority)
+          .internal_error("Failed to build task filter")?);
+    }
+    Ok(filter)
+  }
+
+  /// Same as `to_filter`, but restricted to the caller's own tasks
+  /// Callers with `tasks:read:any` may pick another user through `user_id`
+  pub fn to_scoped_filter(&self, user: &AuthenticatedUser) -> Result<Document, AppError> {
+    let mut filter = self.to_filter()?;
+    if self.user_id.is_none() || !user.has_permission(Permission::TasksReadAny) {
+      let user_object_id = ObjectId::parse_str(&user.user_id)
+          .bad_request("Invalid user ID")?;
+      filter.insert("user_id", user_object_id);
+    }
+    Ok(filter)
+  }
+
+  /// Same as `to_scoped_filter`, plus the `due` window worked out in the caller's time zone
+  pub async fn to_user_filter(&self, app_state: &AppState, user: &AuthenticatedUser) -> Result<Document, AppError> {
+    let mut filter = self.to_scoped_filter(user)?;
+    if let Some(window) = self.due {
+      let user_id = ObjectId::parse_str(&user.user_id)
+          .bad_request("Invalid user ID")?;
+      let preferences = load_preferences(app_state, user_id).await?;
+      filter.extend(due_filter(window, &preferences, Utc::now()));
+    }
+    Ok(filter)
+  }
+}
+
+// Due dates are stored as strings, so they are compared as dates through `$expr`
+fn due_filter(window: DueWindow, preferences: &UserPreferences, now: DateTime<Utc>) -> Document {
+  let date = |instant: DateTime<Utc>| mongodb::bson::DateTime::from_chrono(instant);
+  let due_on = to_date("due_date");
+  match window {
+    DueWindow::Overdue => doc! {
+      "due_date": { "$ne": null },
+      "status": { "$nin": ["completed", "cancelled"] },
+      "$expr": { "$lt": [due_on, date(preferences.today(now).start)] },
+    },
+    DueWindow::Today | DueWindow::Week => {
+      let range = if window == DueWindow::Today { preferences.today(now) } else { preferences.this_week(now) };
+      doc! {
+        "due_date": { "$ne": null },
+        "$expr": { "$and": [
+          { "$gte": [due_on.clone(), date(range.start)] },
+          { "$lt": [due_on, date(range.end)] },
+        ] },
+      }
+    }
+  }
+}
+
+/// Filter for a single task, limited to the caller's own unless they hold `any`
+fn task_filter(id: ObjectId, user: &AuthenticatedUser, any: Permission) -> Result<Document, AppError> {
+  let mut filter = doc! { "_id": id, "deleted": false };
+  if !user.has_permission(any) {
+    let user_object_id = ObjectId::parse_str(&user.user_id)
+        .bad_request("Invalid user ID")?;
+    filter.insert("user_id", user_object_id);
+  }
+  Ok(filter)
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+  #[serde(default)]
+  pub format: TransferFormat,
+}
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+  #[serde(default)]
+  pub format: TransferFormat,
+  #[serde(default)]
+  pub dry_run: bool,
 }
 
 pub async fn create_task(
   State(app_state): State<AppState>,
+  user: AuthenticatedUser,
   Json(payload): Json<CreateTaskRequest>,
 ) -> Result<Json<TaskResponse>, AppError> {
   let collection = app_state.db.collection::<Task>("tasks");
 
   let user_object_id = ObjectId::parse_str(&payload.user_id)
       .bad_request("Invalid user ID")?;
+  if payload.user_id != user.user_id {
+    user.require_permission(Permission::TasksWriteAny)?;
+  }
+
+  let priority = match payload.priority {
+    Some(priority) => priority,
+    None => {
+      let creator_id = ObjectId::parse_str(&user.user_id)
+          .bad_request("Invalid user ID")?;
+      load_preferences(&app_state, creator_id).await?.default_priority
+    }
+  };
 
-  let task = Task {
+  let mut task = Task {
     id: None,
     user_id: user_object_id,
     title: payload.title,
@@ -39,12 +149,19 @@ pub async fn create_task(
     due_date: payload.due_date,
     status: payload.status,
     deleted: false,
-    priority: payload.priority,
+    priority,
+    tags: payload.tags,
     created_by: None,
     updated_by: None,
     created_at: Some(Utc::now()),
     updated_at: Some(Utc::now()),
+    started_at: None,
+    completed_at: None,
+    status_timestamps_estimated: false,
+    ical_uid: None,
+    dav_resource: None,
   };
+  task.track_status_change(Utc::now());
 
   let result = collection.insert_one(&task).await
       .internal_error("Failed to insert task into database")?;
@@ -60,6 +177,7 @@ pub async fn create_task(
     status: task.status, 
     priority: task.priority, 
     due_date: task.due_date,
+    tags: task.tags,
     created_by: task.created_by.map(|id| id.to_hex()),      
     updated_by: task.updated_by.map(|id| id.to_hex()),  
     created_at: task.created_at,  
@@ -71,13 +189,11 @@ pub async fn create_task(
 
 pub async fn get_task(
   State(app_state): State<AppState>,
+  user: AuthenticatedUser,
   Path(id): Path<ObjectId>,
 ) -> Result<Json<TaskResponse>, AppError> {
   let collection = app_state.db.collection::<Task>("tasks");
-  let filter = mongodb::bson::doc! { 
-    "_id": id,
-    "deleted": false
-  };
+  let filter = task_filter(id, &user, Permission::TasksReadAny)?;
 
   let task = collection.find_one(filter)
       .await
@@ -95,6 +211,7 @@ pub async fn get_task(
     status: task.status,
     priority: task.priority,
     due_date: task.due_date,
+    tags: task.tags,
     created_by: task.created_by.map(|id| id.to_hex()),
     updated_by: task.updated_by.map(|id| id.to_hex()),
     created_at: task.created_at,
@@ -105,11 +222,13 @@ pub async fn get_task(
 }
 
 pub async fn list_tasks(
-  State(app_state): State<AppState>
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Query(query): Query<TaskQuery>,
 ) -> Result<Json<Vec<TaskResponse>>, StatusCode> {
   let collection = app_state.db.collection::<Task>("tasks");
 
-  let filter = mongodb::bson::doc! { "deleted": false };
+  let filter = query.to_user_filter(&app_state, &user).await.map_err(|e| e.status)?;
   
   let mut cursor = collection.find(filter).await
       .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -126,6 +245,7 @@ pub async fn list_tasks(
       status: task.status,
       priority: task.priority,
       due_date: task.due_date,
+      tags: task.tags,
       created_by: task.created_by.map(|id| id.to_hex()),
       updated_by: task.updated_by.map(|id| id.to_hex()),
       created_at: task.created_at,
@@ -138,6 +258,7 @@ pub async fn list_tasks(
 
 pub async fn update_task(
   State(app_state): State<AppState>,
+  user: AuthenticatedUser,
   Path(id): Path<String>,  
   Json(payload): Json<UpdateTaskRequest>,
 ) -> Result<Json<TaskResponse>, AppError> {
@@ -146,10 +267,7 @@ pub async fn update_task(
   let task_id = ObjectId::parse_str(&id)  
       .bad_request("Invalid task ID")?;
 
-  let filter = mongodb::bson::doc! { 
-    "_id": task_id,  
-    "deleted": false 
-  };
+  let filter = task_filter(task_id, &user, Permission::TasksWriteAny)?;
   
   let existing_task = collection
       .find_one(filter.clone())  
@@ -157,7 +275,7 @@ pub async fn update_task(
       .internal_error("Failed to query database")?
       .ok_or_else(|| AppError::not_found("Task not found"))?;
 
-  let updated_task = Task {
+  let mut updated_task = Task {
     id: Some(task_id), 
     user_id: existing_task.user_id,
     title: payload.title.unwrap_or(existing_task.title),
@@ -165,12 +283,19 @@ pub async fn update_task(
     status: payload.status.unwrap_or(existing_task.status),
     deleted: existing_task.deleted,
     priority: payload.priority.unwrap_or(existing_task.priority),
+    tags: payload.tags.unwrap_or(existing_task.tags),
     created_by: existing_task.created_by,
     updated_by: existing_task.updated_by,
     created_at: existing_task.created_at,
     updated_at: Some(Utc::now()),
     due_date: payload.due_date.or(existing_task.due_date),
+    started_at: existing_task.started_at,
+    completed_at: existing_task.completed_at,
+    status_timestamps_estimated: existing_task.status_timestamps_estimated,
+    ical_uid: existing_task.ical_uid,
+    dav_resource: existing_task.dav_resource,
   };
+  updated_task.track_status_change(Utc::now());
 
   collection
     .replace_one(filter, &updated_task)  
@@ -185,6 +310,7 @@ pub async fn update_task(
     status: updated_task.status,
     priority: updated_task.priority,
     due_date: updated_task.due_date,
+    tags: updated_task.tags,
     created_by: updated_task.created_by.map(|id| id.to_hex()),
     updated_by: updated_task.updated_by.map(|id| id.to_hex()),
     created_at: updated_task.created_at,
@@ -196,18 +322,109 @@ pub async fn update_task(
 
 pub async fn delete_task(
   State(app_state): State<AppState>,
+  user: AuthenticatedUser,
   Path(id): Path<ObjectId>,
 ) -> Result<StatusCode, AppError> {
   let collection = app_state.db.collection::<Task>("tasks");
-  let filter = mongodb::bson::doc! { 
-    "_id": id,
-    "deleted": false
-  };
+  let filter = task_filter(id, &user, Permission::TasksWriteAny)?;
 
-  let result = collection.update_one(filter, mongodb::bson::doc! { "$set": { "deleted": true } })
+  collection.update_one(filter, mongodb::bson::doc! { "$set": { "deleted": true } })
     .await
     .internal_error("Failed to delete task in database")?;
 
   Ok(StatusCode::NO_CONTENT)
 }
 
+pub async fn export_tasks(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Query(export): Query<ExportQuery>,
+  Query(query): Query<TaskQuery>,
+) -> Result<Response, AppError> {
+  let collection = app_state.db.collection::<Task>("tasks");
+  let filter = query.to_user_filter(&app_state, &user).await?;
+
+  let cursor = collection.find(filter)
+      .sort(doc! { "created_at": 1 })
+      .await
+      .internal_error("Failed to query database")?;
+
+  let tasks = cursor.map(|task| task.map(TaskResponse::from).map_err(BoxError::from));
+  let body = Body::from_stream(converters::encode_tasks(export.format, tasks));
+
+  let disposition = format!("attachment; filename=\"tasks.{}\"", export.format.file_extension());
+  Ok((
+    [
+      (header::CONTENT_TYPE, export.format.content_type().to_string()),
+      (header::CONTENT_DISPOSITION, disposition),
+    ],
+    body,
+  ).into_response())
+}
+
+pub async fn import_tasks(
+  State(app_state): State<AppState>,
+  user: AuthenticatedUser,
+  Query(query): Query<ImportQuery>,
+  body: String,
+) -> Result<Json<ImportTasksResponse>, AppError> {
+  let collection = app_state.db.collection::<Task>("tasks");
+
+  let user_object_id = ObjectId::parse_str(&user.user_id)
+      .bad_request("Invalid user ID")?;
+
+  let rows = converters::decode_rows(query.format, &body)
+      .map_err(AppError::bad_request)?;
+  let default_priority = load_preferences(&app_state, user_object_id).await?.default_priority;
+
+  let total = rows.len();
+  let mut tasks = Vec::new();
+  let mut errors = Vec::new();
+
+  // Rows are numbered from 1, not counting a CSV header
+  for (index, row) in rows.into_iter().enumerate() {
+    let payload = row.and_then(|row| row.into_create_request(user.user_id.clone()));
+    match payload {
+      Ok(payload) => {
+        let mut task = Task {
+          id: None,
+          user_id: user_object_id,
+          title: payload.title,
+          description: payload.description,
+          due_date: payload.due_date,
+          status: payload.status,
+          deleted: false,
+          priority: payload.priority.unwrap_or_else(|| default_priority.clone()),
+          tags: payload.tags,
+          created_by: Some(user_object_id),
+          updated_by: Some(user_object_id),
+          created_at: Some(Utc::now()),
+          updated_at: Some(Utc::now()),
+          started_at: None,
+          completed_at: None,
+          status_timestamps_estimated: false,
+          ical_uid: None,
+          dav_resource: None,
+        };
+        task.track_status_change(Utc::now());
+        tasks.push(task);
+      }
+      Err(message) => errors.push(ImportRowError { row: index + 1, message }),
+    }
+  }
+
+  let imported = tasks.len();
+  if !query.dry_run && !tasks.is_empty() {
+    collection.insert_many(&tasks).await
+        .internal_error("Failed to insert tasks into database")?;
+  }
+
+  Ok(Json(ImportTasksResponse {
+    dry_run: query.dry_run,
+    total,
+    imported,
+    failed: errors.len(),
+    errors,
+  }))
+}
+
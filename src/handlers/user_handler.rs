@@ -1,74 +1,53 @@
 use axum::{
-  http::StatusCode, 
-  extract::{State, Path, Query},
-  response::{Json, IntoResponse},
+  extract::{State, Path},
+  http::StatusCode,
+  response::Json,
 };
 use mongodb::bson::oid::ObjectId;
 use futures_util::StreamExt;
 use crate::{
-  db::AppState,
-  models::{User},
-  dtos::{CreateUserRequest, UpdateUserRequest, UserResponse, LoginRequest, LoginResponse},  // Import DTOs từ dtos
+  db::{is_duplicate_key, AppState},
+  models::{SecurityEvent, SecurityEventKind, User},
+  dtos::{CreateUserRequest, UpdateUserRequest, UserResponse, LoginRequest, LoginOutcome, MfaChallengeResponse},  // Import DTOs từ dtos
 };
-use bcrypt::{hash, DEFAULT_COST};
 use chrono::Utc;
 
-use crate::auth::jwt::Claims;
-use crate::auth::jwt::create_token;
-use bcrypt::verify;
-use std::time::Instant;
-use crate::auth::middleware::{AuthenticatedUser, RoleGuard};
+use crate::auth::{
+  check_password_policy, clear_login_failures, create_mfa_challenge_token, hash_password, record_security_event,
+  start_session, verify_login_password, PasswordCheck, AuthenticatedUser, ClientInfo, MfaChallengeClaims, ThrottleKey,
+};
 use crate::utils::{ResultExt, AppError};
+use crate::accounts::{create_account, ensure_not_last_admin, NewAccount};
+use crate::notifications::{send_security_alert, SecurityAlert};
+use crate::handlers::{ensure_can_manage, ensure_role_grantable, revoke_all_sessions, send_verification_email};
 
 pub async fn create_user(
   State(app_state): State<AppState>,
   admin: AuthenticatedUser,
   Json(payload): Json<CreateUserRequest>,
 ) -> Result<Json<UserResponse>, AppError> {
-  admin.require_role(0).map_err(|e| AppError::forbidden(e.message))?;
-
   let admin_object_id = ObjectId::parse_str(&admin.user_id)
       .bad_request("Invalid user ID")?;
+  ensure_role_grantable(&app_state, &admin, &payload.role).await?;
 
-  let collection = app_state.db.collection::<User>("users");
-  
-  let existing_user_filter = mongodb::bson::doc! { 
-    "email": &payload.email,
-    "deleted": false
-  };
-
-  let existing_user = collection.find_one(existing_user_filter).await
-      .internal_error("Failed to query database")?;
-  
-  if existing_user.is_some() {
-    return Err(AppError::conflict("User already exists"));
-  }
-
-  // Get the "users" collection from MongoDB, mapping each document to the User struct
-  let user = User {
-    id: None,
+  let account = NewAccount {
     full_name: payload.full_name,
     email: payload.email,
-    password: hash(payload.password, DEFAULT_COST)
-        .internal_error("Failed to hash password")?,
+    password: payload.password,
     role: payload.role,
-    created_by: Some(admin_object_id),
-    updated_by: Some(admin_object_id),
-    deleted:false,
-    created_at: Some(Utc::now()),
-    updated_at: Some(Utc::now()),
+    verified: false,
   };
-
-  let result = collection.insert_one(&user).await
-      .internal_error("Failed to insert user into database")?;
-  
-  let user_id = result.inserted_id.as_object_id()
+  let user = create_account(&app_state, account, Some(admin_object_id)).await?;
+  let user_id = user.id
       .ok_or_else(|| AppError::internal_error("Failed to get inserted user ID"))?;
+
+  send_verification_email(&app_state, user_id, user.full_name.clone(), user.email.clone());
   
   let response = UserResponse {
     id: user_id.to_hex(),
     full_name: user.full_name,
     email: user.email,
+    verified: user.verified,
     role: user.role,
     created_at: user.created_at,
     updated_at: user.updated_at,
@@ -100,6 +79,7 @@ pub async fn get_user(
     id: user.id.unwrap().to_hex(),
     full_name: user.full_name,
     email: user.email,
+    verified: user.verified,
     role: user.role,
     created_at: user.created_at,
     updated_at: user.updated_at,
@@ -127,6 +107,7 @@ pub async fn list_users(
       id: user.id.unwrap().to_hex(),
       full_name: user.full_name,
       email: user.email,
+      verified: user.verified,
       role: user.role,
       created_by: user.created_by.map(|id| id.to_hex()),
       updated_by: user.updated_by.map(|id| id.to_hex()),
@@ -144,8 +125,6 @@ pub async fn update_user(
   Path(id): Path<ObjectId>,
   Json(payload): Json<UpdateUserRequest>,
 ) -> Result<Json<UserResponse>, AppError> {
-  admin.require_role(0).map_err(|e| AppError::forbidden(e.message))?;
-
   let collection = app_state.db.collection::<User>("users");
   
   let admin_object_id = ObjectId::parse_str(&admin.user_id)
@@ -159,26 +138,83 @@ pub async fn update_user(
       .await
       .internal_error("Failed to query database")?
       .ok_or_else(|| AppError::not_found("User not found"))?;
+  let password_changed = payload.password.is_some();
+  let email_changed = payload.email.as_ref()
+      .is_some_and(|email| *email != existing_user.email);
+  if let Some(email) = &payload.email {
+    email.parse::<lettre::Address>()
+        .bad_request("Invalid email address")?;
+  }
+  // Neither the account as it is nor its new role may outrank the caller
+  ensure_can_manage(&app_state, &admin, &existing_user).await?;
+  if let Some(role) = &payload.role {
+    ensure_role_grantable(&app_state, &admin, role).await?;
+    ensure_not_last_admin(&app_state, &existing_user, Some(role), "Cannot demote the last admin").await?;
+  }
+  if let Some(password) = &payload.password {
+    let email = payload.email.as_deref().unwrap_or(&existing_user.email);
+    let full_name = payload.full_name.as_deref().unwrap_or(&existing_user.full_name);
+    check_password_policy(&app_state.config.password_policy, "password", password, &[email, full_name]).await?;
+  }
+  // Alerts go to the account as it was, so a changed address still reaches its owner
+  let previous_user = (password_changed || email_changed).then(|| existing_user.clone());
+  let password = match payload.password {
+    Some(password) => hash_password(&app_state.config.password_hash, password).await?,
+    None => existing_user.password,
+  };
   let updated_user = User {
     id: Some(id),
     full_name: payload.full_name.unwrap_or(existing_user.full_name),
     email: payload.email.unwrap_or(existing_user.email),
-    password: payload.password.unwrap_or(existing_user.password),
+    password,
     role: payload.role.unwrap_or(existing_user.role),
     deleted: existing_user.deleted,
+    deleted_at: existing_user.deleted_at,
+    anonymized_at: existing_user.anonymized_at,
+    // A new address has to be confirmed again
+    verified: existing_user.verified && !email_changed,
+    verification_sent_at: if email_changed { Some(Utc::now()) } else { existing_user.verification_sent_at },
+    calendar_token_hash: existing_user.calendar_token_hash,
+    token_generation: existing_user.token_generation,
+    mfa: existing_user.mfa,
+    oidc_identity: existing_user.oidc_identity,
+    preferences: existing_user.preferences,
     created_by: Some(admin_object_id),
     updated_by: Some(admin_object_id),
     created_at: existing_user.created_at,
     updated_at: Some(Utc::now()),
   };
-  collection.replace_one(filter, &updated_user)
-      .await
-      .internal_error("Failed to update user in database")?;
+  match collection.replace_one(filter, &updated_user).await {
+    Ok(_) => {}
+    // The unique email index: another account already has the new address
+    Err(e) if is_duplicate_key(&e) => return Err(AppError::conflict("Email already in use")),
+    Err(_) => return Err(AppError::internal_error("Failed to update user in database")),
+  }
+
+  // A new password invalidates every session opened with the old one
+  if password_changed {
+    revoke_all_sessions(&app_state, id).await?;
+  }
+
+  if email_changed {
+    send_verification_email(&app_state, id, updated_user.full_name.clone(), updated_user.email.clone());
+  }
+
+  if let Some(previous_user) = &previous_user {
+    if password_changed {
+      send_security_alert(&app_state, previous_user, SecurityAlert::PasswordChanged).await;
+    }
+    if email_changed {
+      let alert = SecurityAlert::EmailChanged { new_email: updated_user.email.clone() };
+      send_security_alert(&app_state, previous_user, alert).await;
+    }
+  }
       
   let response = UserResponse {
     id: id.to_hex(),
     full_name: updated_user.full_name,
     email: updated_user.email,
+    verified: updated_user.verified,
     role: updated_user.role,
     created_by: updated_user.created_by.map(|id| id.to_hex()),
     updated_by: updated_user.updated_by.map(|id| id.to_hex()),
@@ -190,40 +226,127 @@ pub async fn update_user(
 
 pub async fn login(
   State(app_state): State<AppState>,
+  client: ClientInfo,
   Json(payload): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, AppError> {
-  let collection = app_state.db.collection::<User>("users");
+) -> Result<Json<LoginOutcome>, AppError> {
+  let (user, check) = verify_login_password(&app_state, &payload.email, &payload.password, &client).await?;
 
-  let filter = mongodb::bson::doc! { 
-    "email": payload.email, 
-    "deleted": false 
-  };
+  // Upgrade bcrypt and outdated Argon2 hashes while the plain password is at hand
+  if check == (PasswordCheck::Valid { needs_rehash: true }) {
+    rehash_password(&app_state, &user, payload.password).await;
+  }
 
-  let start = Instant::now();
+  let outcome = complete_login(&app_state, &user, &client).await?;
+  Ok(Json(outcome))
+}
 
-  let user = collection.find_one(filter).await
-      .internal_error("Failed to query database")?
-      .ok_or_else(|| AppError::not_found("User not found"))?;
-  
-  verify(&payload.password, &user.password)
-    .internal_error("Failed to verify password")?;
-  
-    if !verify(&payload.password, &user.password)
-      .internal_error("Failed to verify password")? {
-    }
+// Lift a lockout before it runs out
+pub async fn unlock_user(
+  State(app_state): State<AppState>,
+  admin: AuthenticatedUser,
+  Path(id): Path<ObjectId>,
+) -> Result<StatusCode, AppError> {
+  let user = app_state.db.collection::<User>("users")
+    .find_one(mongodb::bson::doc! { "_id": id, "deleted": false })
+    .await
+    .internal_error("Failed to query database")?
+    .ok_or_else(|| AppError::not_found("User not found"))?;
 
+  if clear_login_failures(&app_state, &ThrottleKey::account(&user.email)).await? {
+    let mut event = SecurityEvent::new(SecurityEventKind::AccountUnlocked);
+    event.user_id = Some(id);
+    event.email = Some(user.email);
+    event.actor_id = ObjectId::parse_str(&admin.user_id).ok();
+    record_security_event(&app_state, event).await;
+  }
 
-  let user_id = user.id.unwrap().to_hex();
-  let claims = Claims::new(
-    user_id.clone(),
-    user.email.clone(),
-    user.role,
-  );
-  
-  let token = create_token(claims)
-      .internal_error("Failed to create token")?;
-  let response = LoginResponse { token };
-  println!("⏱️ Handler took {:?}", start.elapsed());
-  Ok(Json(response))
+  Ok(StatusCode::NO_CONTENT)
 }
 
+async fn rehash_password(app_state: &AppState, user: &User, password: String) {
+  let result = async {
+    let hash = hash_password(&app_state.config.password_hash, password).await?;
+    app_state.db.collection::<User>("users")
+      .update_one(
+        // Only if the password has not changed in the meantime
+        mongodb::bson::doc! { "_id": user.id, "password": &user.password },
+        mongodb::bson::doc! { "$set": { "password": hash } },
+      )
+      .await
+      .internal_error("Failed to update password hash")
+  }.await;
+
+  if let Err(e) = result {
+    tracing::error!(error = %e.message, user = %user.email, "failed to upgrade password hash");
+  }
+}
+
+/// Everything after the user has proven who they are: verification check, 2FA challenge, tokens
+pub async fn complete_login(app_state: &AppState, user: &User, client: &ClientInfo) -> Result<LoginOutcome, AppError> {
+  if app_state.config.require_verified_email && !user.verified {
+    return Err(AppError::forbidden("Email address has not been verified"));
+  }
+
+  // With 2FA on, the first factor only earns a challenge to exchange at `POST /api/auth/mfa`
+  if user.mfa.as_ref().is_some_and(|mfa| mfa.enabled) {
+    let user_id = user.id
+        .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+    let ttl = app_state.config.mfa_challenge_ttl;
+    let mfa_token = create_mfa_challenge_token(&app_state.jwt, MfaChallengeClaims::new(user_id.to_hex(), ttl))
+        .internal_error("Failed to create token")?;
+    return Ok(LoginOutcome::MfaRequired(MfaChallengeResponse {
+      mfa_required: true,
+      mfa_token,
+      expires_in: ttl.num_seconds(),
+    }));
+  }
+
+  let response = start_session(app_state, user, client).await?;
+  Ok(LoginOutcome::Tokens(response))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use axum::http::StatusCode;
+  use serde_json::json;
+  use crate::{
+    models::ADMIN_ROLE,
+    test_support::{create_user, login_token, send, test_state},
+  };
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn lesser_admins_cannot_touch_greater_ones() {
+    let app_state = test_state().await;
+    app_state.db.collection::<mongodb::bson::Document>("roles")
+      .insert_one(mongodb::bson::doc! { "name": "helpdesk", "permissions": ["users:read", "users:write"], "built_in": false })
+      .await
+      .unwrap();
+    let admin = create_user(&app_state, "admin@example.com", ADMIN_ROLE).await;
+    let helpdesk = create_user(&app_state, "helpdesk@example.com", "helpdesk").await;
+    let token = login_token(&app_state, &helpdesk).await;
+    let admin_uri = format!("/api/users/{}", admin.id.unwrap().to_hex());
+
+    let takeover = json!({ "email": "mallory@example.com" });
+    let (status, _) = send(&app_state, "PUT", &admin_uri, Some(&token), Some(takeover)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app_state, "DELETE", &format!("{admin_uri}/mfa"), Some(&token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app_state, "DELETE", &admin_uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn taking_another_accounts_email_is_a_conflict() {
+    let app_state = test_state().await;
+    let admin = create_user(&app_state, "admin@example.com", ADMIN_ROLE).await;
+    let member = create_user(&app_state, "member@example.com", crate::models::USER_ROLE).await;
+    let token = login_token(&app_state, &admin).await;
+
+    let uri = format!("/api/users/{}", member.id.unwrap().to_hex());
+    let (status, _) = send(&app_state, "PUT", &uri, Some(&token), Some(json!({ "email": "Admin@Example.com" }))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+  }
+}
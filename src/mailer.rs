@@ -0,0 +1,138 @@
+use axum::async_trait;
+use lettre::{
+  message::{header::ContentType, Mailbox},
+  AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::sync::Arc;
+use crate::{
+  config::{AppConfig, MailerBackend},
+  utils::{ResultExt, AppError},
+};
+
+// A plain-text email
+#[derive(Debug, Clone)]
+pub struct Email {
+  pub to: String,
+  pub subject: String,
+  pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+  async fn send(&self, email: Email) -> Result<(), AppError>;
+}
+
+/// Build the mailer selected by `MAILER` (`log`, `file` or `smtp`)
+pub fn from_config(config: &AppConfig) -> Result<Arc<dyn Mailer>, String> {
+  let from: Mailbox = config.mail_from.parse()
+    .map_err(|e| format!("Invalid MAIL_FROM: {e}"))?;
+
+  Ok(match &config.mailer {
+    MailerBackend::Log => Arc::new(LogMailer),
+    MailerBackend::File(dir) => {
+      std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+      Arc::new(FileMailer {
+        from,
+        transport: AsyncFileTransport::<Tokio1Executor>::new(dir),
+      })
+    }
+    MailerBackend::Smtp(url) => Arc::new(SmtpMailer {
+      from,
+      transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+        .map_err(|e| format!("Invalid SMTP_URL: {e}"))?
+        .build(),
+    }),
+  })
+}
+
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, AppError> {
+  let to: Mailbox = email.to.parse()
+    .bad_request("Invalid email address")?;
+
+  Message::builder()
+    .from(from.clone())
+    .to(to)
+    .subject(email.subject)
+    .header(ContentType::TEXT_PLAIN)
+    .body(email.body)
+    .internal_error("Failed to build email")
+}
+
+pub struct SmtpMailer {
+  from: Mailbox,
+  transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+  async fn send(&self, email: Email) -> Result<(), AppError> {
+    let message = build_message(&self.from, email)?;
+    self.transport.send(message)
+      .await
+      .map_err(|e| {
+        tracing::error!(error = %e, "SMTP delivery failed");
+        AppError::internal_error("Failed to send email")
+      })?;
+    Ok(())
+  }
+}
+
+pub struct FileMailer {
+  from: Mailbox,
+  transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+  async fn send(&self, email: Email) -> Result<(), AppError> {
+    let message = build_message(&self.from, email)?;
+    self.transport.send(message)
+      .await
+      .internal_error("Failed to write email")?;
+    Ok(())
+  }
+}
+
+// Logs every email; links keep their path but not their token, since logs outlive the link
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+  async fn send(&self, email: Email) -> Result<(), AppError> {
+    tracing::info!(to = %email.to, subject = %email.subject, "email:\n{}", redact_tokens(&email.body));
+    Ok(())
+  }
+}
+
+/// Replace the value of every `token=` query parameter
+fn redact_tokens(body: &str) -> String {
+  let mut redacted = String::with_capacity(body.len());
+  let mut rest = body;
+  while let Some(start) = rest.find("token=") {
+    let (before, after) = rest.split_at(start + "token=".len());
+    redacted.push_str(before);
+    redacted.push_str("[redacted]");
+    let end = after
+      .find(|c: char| c.is_whitespace() || c == '&' || c == '#')
+      .unwrap_or(after.len());
+    rest = &after[end..];
+  }
+  redacted.push_str(rest);
+  redacted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn redacts_link_tokens() {
+    let body = "Reset it:\n\nhttps://todo.example/reset-password?token=abc123&x=1\n\nOr https://todo.example/verify?token=def456";
+    assert_eq!(
+      redact_tokens(body),
+      "Reset it:\n\nhttps://todo.example/reset-password?token=[redacted]&x=1\n\nOr https://todo.example/verify?token=[redacted]",
+    );
+    assert_eq!(redact_tokens("No links here"), "No links here");
+  }
+}
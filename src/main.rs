@@ -5,22 +5,58 @@ mod routes;
 mod dtos;
 mod auth;
 mod utils;
+mod converters;
+mod config;
+mod mailer;
+mod accounts;
+mod notifications;
+mod cli;
+#[cfg(test)]
+mod test_support;
 
-use axum::Router;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use std::net::SocketAddr;
-use db::{get_database, AppState};
+use db::{ensure_indexes, get_database, run_migrations, AppState};
+use config::AppConfig;
+use clap::Parser;
+use cli::{Cli, Command};
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
 
     let database = get_database()
         .await
         .expect("Failed to connect to database");
 
-    let app_state = AppState::new(database);
+    ensure_indexes(&database)
+        .await
+        .expect("Failed to create database indexes");
+
+    let config = AppConfig::from_env();
+
+    run_migrations(&database, &config)
+        .await
+        .expect("Failed to migrate database");
+
+    let app_state = AppState::new(database, config);
+
+    if let Some(Command::User(command)) = cli.command {
+        if let Err(message) = cli::run_user_command(&app_state, command).await {
+            eprintln!("error: {message}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    accounts::seed_admin(&app_state)
+        .await
+        .map_err(|e| e.message)
+        .expect("Failed to create seed admin");
+    accounts::spawn_anonymiser(app_state.clone());
+    notifications::spawn_reminders(app_state.clone());
 
     let app = routes::create_router(app_state)
         .layer(
@@ -32,5 +68,6 @@ async fn main() {
     println!("🚀 Server running on http://{}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Peer addresses feed the per-IP login throttle
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
 }
\ No newline at end of file
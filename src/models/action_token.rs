@@ -0,0 +1,38 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use super::OidcIdentity;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+  PasswordReset,
+  // Connects a single sign-on identity to an existing account with the same email
+  OidcLink,
+}
+
+// A single-use token mailed to a user to confirm an action
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ActionToken {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub user_id: ObjectId,
+
+  pub purpose: TokenPurpose,
+
+  // SHA-256 of the token; the token itself only appears in the email
+  pub token_hash: String,
+
+  // The identity an `oidc_link` token connects to the account
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub oidc_identity: Option<OidcIdentity>,
+
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub used_at: Option<DateTime<Utc>>,
+
+  pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,32 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use crate::models::Permission;
+
+// A personal access token for scripts that cannot go through `login`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub user_id: ObjectId,
+
+  pub name: String,
+
+  // SHA-256 of the token; the token itself is only shown once, at creation
+  pub token_hash: String,
+
+  // Last characters of the token, to tell tokens apart in listings
+  pub token_hint: String,
+
+  // The token can never do more than both its scopes and the owner's role allow
+  pub scopes: Vec<Permission>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub expires_at: Option<DateTime<Utc>>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub last_used_at: Option<DateTime<Utc>>,
+
+  pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,31 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// An admin's offer of an account to an email address, redeemed once through a mailed link
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Invitation {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub email: String,
+
+  // Role the account gets when the invitation is accepted
+  pub role: String,
+
+  pub invited_by: ObjectId,
+
+  // SHA-256 of the token; the token itself only appears in the email
+  pub token_hash: String,
+
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub accepted_at: Option<DateTime<Utc>>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub revoked_at: Option<DateTime<Utc>>,
+
+  pub created_at: DateTime<Utc>,
+}
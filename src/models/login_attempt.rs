@@ -0,0 +1,31 @@
+use mongodb::bson::{
+  oid::ObjectId,
+  serde_helpers::{chrono_datetime_as_bson_datetime, chrono_datetime_as_bson_datetime_optional},
+};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// Recent failed logins for one account or one client IP
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoginAttempt {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  // `account:<email>` or `ip:<address>`
+  pub key: String,
+
+  // Attempts since the last success or served lockout, counting ones still in flight
+  pub failures: i64,
+
+  // No password is checked for this key before then
+  #[serde(default, skip_serializing_if = "Option::is_none", with = "chrono_datetime_as_bson_datetime_optional")]
+  pub locked_until: Option<DateTime<Utc>>,
+
+  // Whether the latest attempt was turned away because the key was locked
+  #[serde(default)]
+  pub refused: bool,
+
+  // Forgotten by MongoDB once nothing has failed for a lockout period
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+}
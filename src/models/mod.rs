@@ -1,5 +1,31 @@
 pub mod user;
 pub mod task;
+pub mod refresh_token;
+pub mod revoked_token;
+pub mod action_token;
+pub mod role;
+pub mod api_token;
+pub mod oidc_login_state;
+pub mod login_attempt;
+pub mod security_event;
+pub mod invitation;
+pub mod session;
+pub mod preferences;
+pub mod rate_limit;
+pub mod reminder_delivery;
 
 pub use user::*;
-pub use task::*;
\ No newline at end of file
+pub use task::*;
+pub use refresh_token::*;
+pub use revoked_token::*;
+pub use action_token::*;
+pub use role::*;
+pub use api_token::*;
+pub use oidc_login_state::*;
+pub use login_attempt::*;
+pub use security_event::*;
+pub use invitation::*;
+pub use session::*;
+pub use preferences::*;
+pub use rate_limit::*;
+pub use reminder_delivery::*;
@@ -0,0 +1,22 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// An OpenID Connect login between the redirect to the provider and its callback
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OidcLoginState {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  // Random value echoed back by the provider, tying the callback to this login
+  pub state: String,
+
+  // Must come back inside the ID token, so a token cannot be replayed into another login
+  pub nonce: String,
+
+  // PKCE secret; only its hash was sent to the provider
+  pub code_verifier: String,
+
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Datelike, Days, NaiveDate, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use std::ops::Range;
+use crate::models::TaskPriority;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WeekStart {
+  #[default]
+  Monday,
+  Saturday,
+  Sunday,
+}
+
+impl From<WeekStart> for Weekday {
+  fn from(week_start: WeekStart) -> Self {
+    match week_start {
+      WeekStart::Monday => Weekday::Mon,
+      WeekStart::Saturday => Weekday::Sat,
+      WeekStart::Sunday => Weekday::Sun,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct NotificationSettings {
+  // Email about tasks that are due today or overdue
+  pub due_reminders: bool,
+  // Local hour of day (0-23) reminders go out at
+  pub reminder_hour: u8,
+  // Email when the account is locked, or its password or email address changes
+  pub security_alerts: bool,
+}
+
+impl Default for NotificationSettings {
+  fn default() -> Self {
+    Self { due_reminders: false, reminder_hour: 8, security_alerts: true }
+  }
+}
+
+// How a user wants dates and defaults interpreted; every field has a default,
+// so accounts created before preferences existed read as UTC, English, Monday
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct UserPreferences {
+  // IANA zone such as `Asia/Ho_Chi_Minh` or `Europe/Berlin`
+  pub timezone: Tz,
+  // BCP 47 language tag, e.g. `vi-VN`
+  pub locale: String,
+  pub week_start: WeekStart,
+  // Priority of new tasks that do not name one
+  pub default_priority: TaskPriority,
+  pub notifications: NotificationSettings,
+}
+
+impl Default for UserPreferences {
+  fn default() -> Self {
+    Self {
+      timezone: Tz::UTC,
+      locale: "en".to_string(),
+      week_start: WeekStart::default(),
+      default_priority: TaskPriority::default(),
+      notifications: NotificationSettings::default(),
+    }
+  }
+}
+
+impl UserPreferences {
+  /// The calendar date it is in the user's zone at `now`
+  pub fn local_date(&self, now: DateTime<Utc>) -> NaiveDate {
+    now.with_timezone(&self.timezone).date_naive()
+  }
+
+  /// When `date` begins in the user's zone
+  /// Zones that skip midnight for daylight saving start the day at the first hour that exists
+  pub fn start_of_day(&self, date: NaiveDate) -> DateTime<Utc> {
+    (0..24)
+      .find_map(|hour| self.timezone.from_local_datetime(&date.and_hms_opt(hour, 0, 0)?).earliest())
+      .map(|start| start.with_timezone(&Utc))
+      .unwrap_or_else(|| date.and_time(Default::default()).and_utc())
+  }
+
+  /// The user's current day, as a UTC range
+  pub fn today(&self, now: DateTime<Utc>) -> Range<DateTime<Utc>> {
+    let today = self.local_date(now);
+    self.start_of_day(today)..self.start_of_day(today + Days::new(1))
+  }
+
+  /// `date` in the order the user's locale writes dates, e.g. `03/10/2025` for `en-US`
+  pub fn format_date(&self, date: NaiveDate) -> String {
+    date.format(self.date_format()).to_string()
+  }
+
+  /// `instant` as a date and time on the user's clock, with the zone spelled out
+  pub fn format_datetime(&self, instant: DateTime<Utc>) -> String {
+    let local = instant.with_timezone(&self.timezone);
+    format!("{} {} ({})", self.format_date(local.date_naive()), local.format("%H:%M"), self.timezone.name())
+  }
+
+  // Only the order and separator of the numeric date follow the locale; names stay English
+  fn date_format(&self) -> &'static str {
+    let mut subtags = self.locale.split('-');
+    let language = subtags.next().unwrap_or_default().to_ascii_lowercase();
+    let region = subtags
+      .find(|subtag| subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()))
+      .map(|region| region.to_ascii_uppercase());
+    match (language.as_str(), region.as_deref()) {
+      ("en", None | Some("US")) => "%m/%d/%Y",
+      ("zh" | "ja" | "ko" | "hu" | "lt" | "sv", _) => "%Y-%m-%d",
+      ("de" | "ru" | "pl" | "cs" | "fi" | "da" | "nb" | "tr" | "uk", _) => "%d.%m.%Y",
+      _ => "%d/%m/%Y",
+    }
+  }
+
+  /// The first day of the week `date` falls in, by the user's chosen weekday
+  pub fn week_of(&self, date: NaiveDate) -> NaiveDate {
+    date - Days::new(u64::from(date.weekday().days_since(self.week_start.into())))
+  }
+
+  /// The user's current week, starting on their chosen weekday
+  pub fn this_week(&self, now: DateTime<Utc>) -> Range<DateTime<Utc>> {
+    let first = self.week_of(self.local_date(now));
+    self.start_of_day(first)..self.start_of_day(first + Days::new(7))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn preferences(timezone: Tz, week_start: WeekStart) -> UserPreferences {
+    UserPreferences { timezone, week_start, ..Default::default() }
+  }
+
+  fn utc(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn today_follows_the_users_zone() {
+    // 20:00 UTC is already the next morning in Hanoi
+    let hanoi = preferences(chrono_tz::Asia::Ho_Chi_Minh, WeekStart::Monday);
+    assert_eq!(hanoi.today(utc("2025-03-10T20:00:00Z")), utc("2025-03-10T17:00:00Z")..utc("2025-03-11T17:00:00Z"));
+
+    // Berlin's day of the spring change is 23 hours long
+    let berlin = preferences(chrono_tz::Europe::Berlin, WeekStart::Monday);
+    assert_eq!(berlin.today(utc("2025-03-30T12:00:00Z")), utc("2025-03-29T23:00:00Z")..utc("2025-03-30T22:00:00Z"));
+  }
+
+  #[test]
+  fn weeks_start_on_the_chosen_day() {
+    // Wednesday 2025-03-12
+    let now = utc("2025-03-12T12:00:00Z");
+    let monday = preferences(Tz::UTC, WeekStart::Monday).this_week(now);
+    assert_eq!(monday, utc("2025-03-10T00:00:00Z")..utc("2025-03-17T00:00:00Z"));
+    let sunday = preferences(Tz::UTC, WeekStart::Sunday).this_week(now);
+    assert_eq!(sunday, utc("2025-03-09T00:00:00Z")..utc("2025-03-16T00:00:00Z"));
+  }
+
+  #[test]
+  fn dates_are_written_the_locales_way() {
+    let at = |locale: &str| UserPreferences { locale: locale.to_string(), ..preferences(chrono_tz::Europe::Berlin, WeekStart::Monday) };
+    let now = utc("2025-03-10T23:30:00Z");
+    assert_eq!(at("en").format_datetime(now), "03/11/2025 00:30 (Europe/Berlin)");
+    assert_eq!(at("en-GB").format_datetime(now), "11/03/2025 00:30 (Europe/Berlin)");
+    assert_eq!(at("de-DE").format_datetime(now), "11.03.2025 00:30 (Europe/Berlin)");
+    assert_eq!(at("zh-Hant-TW").format_datetime(now), "2025-03-11 00:30 (Europe/Berlin)");
+    assert_eq!(at("vi-VN").format_datetime(now), "11/03/2025 00:30 (Europe/Berlin)");
+  }
+
+  #[test]
+  fn missing_preferences_use_defaults() {
+    let preferences: UserPreferences = serde_json::from_str(r#"{ "timezone": "Europe/Berlin" }"#).unwrap();
+    assert_eq!(preferences, UserPreferences { timezone: chrono_tz::Europe::Berlin, ..Default::default() });
+  }
+}
@@ -0,0 +1,19 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// Requests counted against one key in the current window
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  // e.g. `password_reset:ip:<address>`
+  pub key: String,
+
+  pub count: i64,
+
+  // End of the window; MongoDB removes the document then
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,30 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RefreshToken {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub user_id: ObjectId,
+
+  // Every token rotated from the same login shares a family
+  pub family_id: String,
+
+  // SHA-256 of the token; the token itself is only ever sent to the client
+  pub token_hash: String,
+
+  // Stored as a BSON date so the TTL index can purge expired tokens
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+
+  // Set once the token has been exchanged; presenting it again revokes the family
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub used_at: Option<DateTime<Utc>>,
+
+  #[serde(default)]
+  pub revoked: bool,
+
+  pub created_at: DateTime<Utc>,
+}
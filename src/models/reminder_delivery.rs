@@ -0,0 +1,20 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// The due-date reminder of one user for one local day; inserting it claims the day,
+// so several instances never send the same reminder twice
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReminderDelivery {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub user_id: ObjectId,
+
+  // The user's local date, `YYYY-MM-DD`
+  pub date: String,
+
+  // MongoDB removes the record a few days later
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+}
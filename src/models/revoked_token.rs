@@ -0,0 +1,17 @@
+use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// An access token that was revoked before its natural expiry
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevokedToken {
+  // The token's `jti` claim
+  #[serde(rename = "_id")]
+  pub jti: String,
+
+  pub user_id: String,
+
+  // Copied from the token; the TTL index drops the entry once the token could no longer be used anyway
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+}
@@ -0,0 +1,117 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+  // The caller's own tasks
+  #[serde(rename = "tasks:read")]
+  TasksRead,
+  #[serde(rename = "tasks:write")]
+  TasksWrite,
+  // Everyone's tasks
+  #[serde(rename = "tasks:read:any")]
+  TasksReadAny,
+  #[serde(rename = "tasks:write:any")]
+  TasksWriteAny,
+  #[serde(rename = "users:read")]
+  UsersRead,
+  #[serde(rename = "users:write")]
+  UsersWrite,
+  // Act as another user to see what they see
+  #[serde(rename = "users:impersonate")]
+  UsersImpersonate,
+  #[serde(rename = "roles:read")]
+  RolesRead,
+  #[serde(rename = "roles:write")]
+  RolesWrite,
+}
+
+impl Permission {
+  pub const ALL: [Permission; 9] = [
+    Permission::TasksRead,
+    Permission::TasksWrite,
+    Permission::TasksReadAny,
+    Permission::TasksWriteAny,
+    Permission::UsersRead,
+    Permission::UsersWrite,
+    Permission::UsersImpersonate,
+    Permission::RolesRead,
+    Permission::RolesWrite,
+  ];
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Permission::TasksRead => "tasks:read",
+      Permission::TasksWrite => "tasks:write",
+      Permission::TasksReadAny => "tasks:read:any",
+      Permission::TasksWriteAny => "tasks:write:any",
+      Permission::UsersRead => "users:read",
+      Permission::UsersWrite => "users:write",
+      Permission::UsersImpersonate => "users:impersonate",
+      Permission::RolesRead => "roles:read",
+      Permission::RolesWrite => "roles:write",
+    }
+  }
+}
+
+impl fmt::Display for Permission {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+// Built-in role names; numeric roles from before RBAC map to these
+pub const ADMIN_ROLE: &str = "admin";
+pub const USER_ROLE: &str = "user";
+
+// A named set of permissions, assigned to users through `User::role`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Role {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub name: String,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+
+  pub permissions: Vec<Permission>,
+
+  // Built-in roles cannot be deleted, and `admin` cannot be edited
+  #[serde(default)]
+  pub built_in: bool,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub created_at: Option<DateTime<Utc>>,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Role {
+  /// Roles seeded on startup
+  pub fn built_in() -> Vec<Role> {
+    vec![
+      Role {
+        id: None,
+        name: ADMIN_ROLE.to_string(),
+        description: Some("Full access".to_string()),
+        permissions: Permission::ALL.to_vec(),
+        built_in: true,
+        created_at: Some(Utc::now()),
+        updated_at: Some(Utc::now()),
+      },
+      Role {
+        id: None,
+        name: USER_ROLE.to_string(),
+        description: Some("Manages their own tasks".to_string()),
+        permissions: vec![Permission::TasksRead, Permission::TasksWrite],
+        built_in: true,
+        created_at: Some(Utc::now()),
+        updated_at: Some(Utc::now()),
+      },
+    ]
+  }
+}
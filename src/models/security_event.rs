@@ -0,0 +1,74 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+  AccountLocked,
+  IpLocked,
+  AccountUnlocked,
+  AccountDeleted,
+  AccountAnonymized,
+  ImpersonationStarted,
+  // A write made through an impersonation token
+  ImpersonatedRequest,
+}
+
+impl SecurityEventKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::AccountLocked => "account_locked",
+      Self::IpLocked => "ip_locked",
+      Self::AccountUnlocked => "account_unlocked",
+      Self::AccountDeleted => "account_deleted",
+      Self::AccountAnonymized => "account_anonymized",
+      Self::ImpersonationStarted => "impersonation_started",
+      Self::ImpersonatedRequest => "impersonated_request",
+    }
+  }
+}
+
+// Audit trail of security-relevant things that happened to accounts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecurityEvent {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub kind: SecurityEventKind,
+
+  // Account the event is about, when it is known
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub user_id: Option<ObjectId>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub email: Option<String>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ip: Option<String>,
+
+  // Who caused it, for actions taken by an admin
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub actor_id: Option<ObjectId>,
+
+  // What happened, e.g. the method, path and status of an impersonated request
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub detail: Option<String>,
+
+  pub created_at: DateTime<Utc>,
+}
+
+impl SecurityEvent {
+  pub fn new(kind: SecurityEventKind) -> Self {
+    Self {
+      id: None,
+      kind,
+      user_id: None,
+      email: None,
+      ip: None,
+      actor_id: None,
+      detail: None,
+      created_at: Utc::now(),
+    }
+  }
+}
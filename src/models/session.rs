@@ -0,0 +1,35 @@
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+
+// One login on one device; its ID is the refresh token family and the `sid` of its access tokens
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+  #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<ObjectId>,
+
+  pub user_id: ObjectId,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub user_agent: Option<String>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ip: Option<String>,
+
+  pub created_at: DateTime<Utc>,
+
+  // Refreshed at most once a minute by authenticated requests
+  pub last_seen_at: DateTime<Utc>,
+
+  // Pushed back on every refresh; MongoDB removes the session once its refresh tokens are gone
+  #[serde(with = "chrono_datetime_as_bson_datetime")]
+  pub expires_at: DateTime<Utc>,
+
+  // Set when the session is logged out or killed; its tokens stop working immediately
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub revoked_at: Option<DateTime<Utc>>,
+
+  // Admin acting as `user_id`; such sessions are hidden from the user's own session list
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub impersonator_id: Option<ObjectId>,
+}
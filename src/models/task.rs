@@ -44,6 +44,10 @@ pub struct Task {
   
   #[serde(default)]
   pub priority: TaskPriority,
+
+  // Free-form labels; todo.txt contexts keep their leading `@`
+  #[serde(default)]
+  pub tags: Vec<String>,
   
   #[serde(skip_serializing_if = "Option::is_none")]
   pub created_by: Option<ObjectId>,
@@ -56,5 +60,118 @@ pub struct Task {
 
   #[serde(skip_serializing_if = "Option::is_none")]
   pub updated_at: Option<DateTime<Utc>>,
+
+  // When the task last moved to `inprogress` / `completed`, used for cycle and lead time
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub started_at: Option<DateTime<Utc>>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub completed_at: Option<DateTime<Utc>>,
+
+  // Set on tasks whose `started_at` / `completed_at` were backfilled from `updated_at`
+  // rather than recorded; their durations are left out of the stats
+  #[serde(default)]
+  pub status_timestamps_estimated: bool,
+
+  // UID and resource name chosen by a CalDAV client that created or last wrote the task
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub ical_uid: Option<String>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub dav_resource: Option<String>,
+}
+
+impl Task {
+  /// Keep `started_at` / `completed_at` in line with the current status
+  pub fn track_status_change(&mut self, now: DateTime<Utc>) {
+    match self.status {
+      TaskStatus::InProgress => {
+        self.started_at.get_or_insert(now);
+        self.completed_at = None;
+      }
+      TaskStatus::Completed => {
+        self.completed_at.get_or_insert(now);
+      }
+      TaskStatus::Pending | TaskStatus::Cancelled => {
+        self.completed_at = None;
+      }
+    }
+  }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  fn at(hour: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2025, 1, 1, hour, 0, 0).unwrap()
+  }
+
+  fn task() -> Task {
+    Task {
+      id: None,
+      user_id: ObjectId::new(),
+      title: "Write report".to_string(),
+      description: None,
+      due_date: None,
+      status: TaskStatus::Pending,
+      deleted: false,
+      priority: TaskPriority::Low,
+      tags: Vec::new(),
+      created_by: None,
+      updated_by: None,
+      created_at: Some(at(8)),
+      updated_at: Some(at(8)),
+      started_at: None,
+      completed_at: None,
+      status_timestamps_estimated: false,
+      ical_uid: None,
+      dav_resource: None,
+    }
+  }
+
+  fn move_to(task: &mut Task, status: TaskStatus, now: DateTime<Utc>) {
+    task.status = status;
+    task.track_status_change(now);
+  }
+
+  #[test]
+  fn records_start_and_completion() {
+    let mut task = task();
+    move_to(&mut task, TaskStatus::InProgress, at(9));
+    move_to(&mut task, TaskStatus::Completed, at(11));
+    assert_eq!(task.started_at, Some(at(9)));
+    assert_eq!(task.completed_at, Some(at(11)));
+
+    // Saving a completed task again keeps the original completion time
+    move_to(&mut task, TaskStatus::Completed, at(12));
+    assert_eq!(task.completed_at, Some(at(11)));
+  }
+
+  #[test]
+  fn reopening_clears_completion_but_keeps_the_start() {
+    let mut task = task();
+    move_to(&mut task, TaskStatus::InProgress, at(9));
+    move_to(&mut task, TaskStatus::Completed, at(10));
+    move_to(&mut task, TaskStatus::InProgress, at(11));
+    assert_eq!(task.started_at, Some(at(9)));
+    assert_eq!(task.completed_at, None);
+
+    move_to(&mut task, TaskStatus::Completed, at(12));
+    assert_eq!(task.completed_at, Some(at(12)));
+    move_to(&mut task, TaskStatus::Pending, at(13));
+    assert_eq!(task.completed_at, None);
+  }
+
+  #[test]
+  fn completing_directly_leaves_start_unset() {
+    let mut task = task();
+    move_to(&mut task, TaskStatus::Completed, at(10));
+    assert_eq!(task.started_at, None);
+    assert_eq!(task.completed_at, Some(at(10)));
+
+    move_to(&mut task, TaskStatus::Cancelled, at(11));
+    assert_eq!(task.completed_at, None);
+  }
+}
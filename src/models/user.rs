@@ -1,6 +1,7 @@
-use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime_optional};
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use crate::models::UserPreferences;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
@@ -12,7 +13,8 @@ pub struct User {
   
   pub password: String,
   
-  pub role:i16,
+  // Name of a `Role`
+  pub role: String,
   
   #[serde(skip_serializing_if = "Option::is_none")]
   pub created_by: Option<ObjectId>,
@@ -21,6 +23,40 @@ pub struct User {
   pub updated_by: Option<ObjectId>,
   
   pub deleted: bool,
+
+  // When the account was deleted; its personal data is erased once the grace period is over
+  #[serde(default, skip_serializing_if = "Option::is_none", with = "chrono_datetime_as_bson_datetime_optional")]
+  pub deleted_at: Option<DateTime<Utc>>,
+
+  #[serde(default, skip_serializing_if = "Option::is_none", with = "chrono_datetime_as_bson_datetime_optional")]
+  pub anonymized_at: Option<DateTime<Utc>>,
+
+  // Whether the owner has confirmed `email`; accounts created before verification existed count as verified
+  #[serde(default = "default_verified")]
+  pub verified: bool,
+
+  // When the last verification email went out, used to throttle resends
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub verification_sent_at: Option<DateTime<Utc>>,
+
+  // SHA-256 of the secret token in the user's calendar feed URL
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub calendar_token_hash: Option<String>,
+
+  // Embedded in every access token; incremented to log the user out everywhere
+  #[serde(default)]
+  pub token_generation: i64,
+
+  // TOTP second factor; present but not `enabled` while enrolment awaits its first code
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub mfa: Option<Mfa>,
+
+  // Account at the OpenID Connect provider this user signs in with
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub oidc_identity: Option<OidcIdentity>,
+
+  #[serde(default)]
+  pub preferences: UserPreferences,
   
   #[serde(skip_serializing_if = "Option::is_none")]
   pub created_at: Option<DateTime<Utc>>,
@@ -28,3 +64,30 @@ pub struct User {
   #[serde(skip_serializing_if = "Option::is_none")]
   pub updated_at: Option<DateTime<Utc>>,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Mfa {
+  // Base32 TOTP secret
+  pub secret: String,
+
+  pub enabled: bool,
+
+  // SHA-256 of each unused recovery code
+  #[serde(default)]
+  pub recovery_codes: Vec<String>,
+
+  // Last accepted TOTP time step, so a code cannot be used twice
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub last_step: Option<i64>,
+}
+
+// The provider's stable identifier for a user; emails can change, `sub` cannot
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OidcIdentity {
+  pub issuer: String,
+  pub subject: String,
+}
+
+pub(crate) fn default_verified() -> bool {
+  true
+}
@@ -0,0 +1,282 @@
+use chrono::{DateTime, Duration, Timelike, Utc};
+use futures_util::TryStreamExt;
+use mongodb::bson::{self, doc};
+use crate::{
+  db::{is_duplicate_key, AppState},
+  handlers::to_date,
+  mailer::Email,
+  models::{ReminderDelivery, Task, User},
+  utils::{ResultExt, AppError},
+};
+
+// How often users whose reminder hour has come are looked for
+const REMINDER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+// How long a sent reminder is remembered; only the current local day matters
+const DELIVERY_RETENTION_DAYS: i64 = 3;
+
+/// Something that happened to an account that its owner should hear about
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecurityAlert {
+  AccountLocked,
+  PasswordChanged,
+  // Goes to the old address, which may be the only one the owner still controls
+  EmailChanged { new_email: String },
+}
+
+/// Mail `user` about `alert`, unless they turned security alerts off
+/// A failed delivery is only logged; the change it reports has already happened
+pub async fn send_security_alert(app_state: &AppState, user: &User, alert: SecurityAlert) {
+  let Some(email) = security_alert_email(user, &alert, Utc::now()) else {
+    return;
+  };
+  if let Err(e) = app_state.mailer.send(email).await {
+    tracing::error!(error = %e.message, ?alert, "failed to send security alert");
+  }
+}
+
+fn security_alert_email(user: &User, alert: &SecurityAlert, now: DateTime<Utc>) -> Option<Email> {
+  if !user.preferences.notifications.security_alerts {
+    return None;
+  }
+  let when = user.preferences.format_datetime(now);
+  let (subject, what) = match alert {
+    SecurityAlert::AccountLocked => (
+      "Your account was locked",
+      format!("Your To-Do List account was locked at {when} after too many failed sign-in attempts."),
+    ),
+    SecurityAlert::PasswordChanged => (
+      "Your password was changed",
+      format!("The password of your To-Do List account was changed at {when}."),
+    ),
+    SecurityAlert::EmailChanged { new_email } => (
+      "Your email address was changed",
+      format!("The email address of your To-Do List account was changed to {new_email} at {when}."),
+    ),
+  };
+  let body = format!(
+    "Hi {},\n\n{what}\n\nIf it wasn't you, reset your password and contact an administrator.\n",
+    user.full_name,
+  );
+  Some(Email { to: user.email.clone(), subject: subject.to_string(), body })
+}
+
+/// Mail every user who asked for reminders the open tasks due today or earlier, once per local
+/// day from their `reminder_hour` on; returns how many reminders were sent
+pub async fn send_due_reminders(app_state: &AppState, now: DateTime<Utc>) -> Result<u64, AppError> {
+  let mut users = app_state.db.collection::<User>("users")
+    .find(doc! { "deleted": false, "verified": true, "preferences.notifications.due_reminders": true })
+    .await
+    .internal_error("Failed to query database")?;
+
+  let mut sent = 0;
+  while let Some(user) = users.try_next().await.internal_error("Failed to query database")? {
+    match send_reminder(app_state, &user, now).await {
+      Ok(true) => sent += 1,
+      Ok(false) => {}
+      Err(e) => tracing::error!(user_id = ?user.id, error = %e.message, "failed to send due reminder"),
+    }
+  }
+  Ok(sent)
+}
+
+// Whether a reminder went out
+async fn send_reminder(app_state: &AppState, user: &User, now: DateTime<Utc>) -> Result<bool, AppError> {
+  let user_id = user.id
+      .ok_or_else(|| AppError::internal_error("User has no ID"))?;
+  let preferences = &user.preferences;
+  let local = now.with_timezone(&preferences.timezone);
+  if local.hour() < u32::from(preferences.notifications.reminder_hour) {
+    return Ok(false);
+  }
+
+  let tasks: Vec<Task> = app_state.db.collection::<Task>("tasks")
+    .find(doc! {
+      "user_id": user_id,
+      "deleted": false,
+      "due_date": { "$ne": null },
+      "status": { "$nin": ["completed", "cancelled"] },
+      "$expr": { "$lt": [to_date("due_date"), bson::DateTime::from_chrono(preferences.today(now).end)] },
+    })
+    .sort(doc! { "due_date": 1 })
+    .await
+    .internal_error("Failed to query tasks")?
+    .try_collect()
+    .await
+    .internal_error("Failed to query tasks")?;
+  if tasks.is_empty() {
+    return Ok(false);
+  }
+
+  // Claim the day first, so another instance running the same tick stays quiet
+  let deliveries = app_state.db.collection::<ReminderDelivery>("reminder_deliveries");
+  let delivery = ReminderDelivery {
+    id: None,
+    user_id,
+    date: local.date_naive().to_string(),
+    expires_at: now + Duration::days(DELIVERY_RETENTION_DAYS),
+  };
+  match deliveries.insert_one(&delivery).await {
+    Ok(_) => {}
+    Err(e) if is_duplicate_key(&e) => return Ok(false),
+    Err(_) => return Err(AppError::internal_error("Failed to record reminder")),
+  }
+
+  if let Err(e) = app_state.mailer.send(reminder_email(user, &tasks, now)).await {
+    // Give the next tick another go
+    deliveries.delete_one(doc! { "user_id": user_id, "date": &delivery.date })
+      .await
+      .internal_error("Failed to release reminder")?;
+    return Err(e);
+  }
+  Ok(true)
+}
+
+fn reminder_email(user: &User, tasks: &[Task], now: DateTime<Utc>) -> Email {
+  let preferences = &user.preferences;
+  let start_of_today = preferences.today(now).start;
+  let lines: Vec<String> = tasks.iter()
+    .map(|task| {
+      let due = task.due_date.unwrap_or(now);
+      let overdue = if due < start_of_today { ", overdue" } else { "" };
+      let date = preferences.format_date(due.with_timezone(&preferences.timezone).date_naive());
+      format!("- {} (due {date}{overdue})", task.title)
+    })
+    .collect();
+  let body = format!(
+    "Hi {},\n\n\
+    These tasks are due today or overdue:\n\n{}\n\n\
+    You can turn these reminders off in your preferences.\n",
+    user.full_name,
+    lines.join("\n"),
+  );
+  Email {
+    to: user.email.clone(),
+    subject: format!("{} task(s) due", tasks.len()),
+    body,
+  }
+}
+
+pub fn spawn_reminders(app_state: AppState) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+    loop {
+      interval.tick().await;
+      match send_due_reminders(&app_state, Utc::now()).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(count, "sent due reminders"),
+        Err(e) => tracing::error!(error = %e.message, "failed to send due reminders"),
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    models::{NotificationSettings, UserPreferences, USER_ROLE},
+    test_support::{create_user, test_state, Outbox},
+  };
+
+  fn user(preferences: UserPreferences) -> User {
+    let mut user: User = bson::from_document(doc! {
+      "full_name": "Ana",
+      "email": "ana@example.com",
+      "password": "hash",
+      "role": "user",
+      "deleted": false,
+    }).unwrap();
+    user.preferences = preferences;
+    user
+  }
+
+  fn task(title: &str, due_date: &str) -> Task {
+    bson::from_document(doc! {
+      "user_id": bson::oid::ObjectId::new(),
+      "title": title,
+      "due_date": due_date,
+      "deleted": false,
+    }).unwrap()
+  }
+
+  fn utc(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn security_alerts_respect_the_setting() {
+    let alert = SecurityAlert::EmailChanged { new_email: "new@example.com".to_string() };
+    let email = security_alert_email(&user(UserPreferences::default()), &alert, utc("2025-03-10T08:05:00Z")).unwrap();
+    assert_eq!(email.to, "ana@example.com");
+    assert_eq!(email.subject, "Your email address was changed");
+    assert!(email.body.contains("changed to new@example.com at 03/10/2025 08:05 (UTC)"));
+
+    let quiet = UserPreferences {
+      notifications: NotificationSettings { security_alerts: false, ..Default::default() },
+      ..Default::default()
+    };
+    assert!(security_alert_email(&user(quiet), &SecurityAlert::AccountLocked, Utc::now()).is_none());
+  }
+
+  #[test]
+  fn reminders_list_due_dates_in_the_users_zone_and_locale() {
+    let preferences = UserPreferences {
+      timezone: chrono_tz::Europe::Berlin,
+      locale: "de-DE".to_string(),
+      ..Default::default()
+    };
+    // 23:30 UTC on the 10th is already the 11th in Berlin
+    let tasks = [task("Pay rent", "2025-03-08T12:00:00Z"), task("Call Bob", "2025-03-10T23:30:00Z")];
+    let email = reminder_email(&user(preferences), &tasks, utc("2025-03-11T08:00:00Z"));
+    assert_eq!(email.subject, "2 task(s) due");
+    assert!(email.body.contains("- Pay rent (due 08.03.2025, overdue)\n- Call Bob (due 11.03.2025)\n"));
+  }
+
+  async fn add_task(app_state: &AppState, user: &User, title: &str, due_date: DateTime<Utc>, status: &str) {
+    app_state.db.collection::<bson::Document>("tasks")
+      .insert_one(doc! {
+        "user_id": user.id,
+        "title": title,
+        "due_date": bson::to_bson(&due_date).unwrap(),
+        "status": status,
+        "deleted": false,
+      })
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  #[ignore = "needs MongoDB at TEST_DATABASE_URL"]
+  async fn reminders_go_out_once_a_day_to_users_who_want_them() {
+    let mut app_state = test_state().await;
+    let outbox = Outbox::install(&mut app_state);
+    let wants = create_user(&app_state, "wants@example.com", USER_ROLE).await;
+    let quiet = create_user(&app_state, "quiet@example.com", USER_ROLE).await;
+    app_state.db.collection::<User>("users")
+      .update_one(doc! { "_id": wants.id }, doc! { "$set": { "preferences.notifications.due_reminders": true } })
+      .await
+      .unwrap();
+
+    let now = utc("2025-03-10T09:00:00Z");
+    add_task(&app_state, &wants, "Overdue", utc("2025-03-07T10:00:00Z"), "todo").await;
+    add_task(&app_state, &wants, "Due today", utc("2025-03-10T18:00:00Z"), "inprogress").await;
+    add_task(&app_state, &wants, "Done", utc("2025-03-10T18:00:00Z"), "completed").await;
+    add_task(&app_state, &wants, "Tomorrow", utc("2025-03-11T18:00:00Z"), "todo").await;
+    add_task(&app_state, &quiet, "Not asked for", utc("2025-03-10T18:00:00Z"), "todo").await;
+
+    // Not before the reminder hour, 08:00 by default
+    assert_eq!(send_due_reminders(&app_state, utc("2025-03-10T07:00:00Z")).await.unwrap(), 0);
+
+    assert_eq!(send_due_reminders(&app_state, now).await.unwrap(), 1);
+    let sent = outbox.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "wants@example.com");
+    assert_eq!(sent[0].subject, "2 task(s) due");
+    assert!(sent[0].body.contains("- Overdue (due 03/07/2025, overdue)\n- Due today (due 03/10/2025)\n"));
+
+    // The next tick on the same day stays quiet, the next day sends again
+    assert_eq!(send_due_reminders(&app_state, now + Duration::minutes(15)).await.unwrap(), 0);
+    assert_eq!(send_due_reminders(&app_state, now + Duration::days(1)).await.unwrap(), 1);
+  }
+}
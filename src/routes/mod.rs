@@ -1,35 +1,100 @@
 use axum::{
   Router,
-  routing::{get, post, delete, put},
+  routing::{any, get, post, delete, put, patch},
   middleware,
 };
 
 use crate:: {
   db::AppState,
   handlers,
-  auth::middleware::auth_middleware,
+  auth::middleware::{auth_middleware, require_permission},
+  models::Permission,
 };
 
 pub fn create_router(app_state: AppState) -> Router {
+  // Per-route permission check, runs after `auth_middleware`
+  let need = |permission: Permission| middleware::from_fn(require_permission(permission));
+
   // Public routes (không cần authentication)
   let public_routes = Router::new()
     .route("/api/auth/login", post(handlers::login))
-    .route("/api/users", post(handlers::create_user))
-    .route("/api/users", get(handlers::list_users))
-    .route("/api/users/:id", put(handlers::update_user));
+    .route("/api/auth/register", post(handlers::register))
+    .route("/api/auth/accept-invitation", post(handlers::accept_invitation))
+    .route("/api/auth/refresh", post(handlers::refresh_token))
+    .route("/api/auth/forgot-password", post(handlers::forgot_password))
+    .route("/api/auth/reset-password", post(handlers::reset_password))
+    .route("/api/auth/verify-email", get(handlers::verify_email))
+    .route("/api/auth/resend-verification", post(handlers::resend_verification))
+    .route("/api/auth/mfa", post(handlers::mfa_login))
+    .route("/api/auth/oidc/login", get(handlers::oidc_login))
+    .route("/api/auth/oidc/callback", get(handlers::oidc_callback))
+    .route("/api/auth/oidc/link", post(handlers::confirm_oidc_link))
+    .route("/api/calendar/:token/tasks.ics", get(handlers::calendar_feed))
+    .route("/.well-known/jwks.json", get(handlers::jwks));
 
   // Protected routes (cần authentication)
   let protected_routes = Router::new()
-    .route("/api/users/:id", get(handlers::get_user))
-    .route("/api/tasks", post(handlers::create_task))
-    .route("/api/tasks/:id", get(handlers::get_task))
-    .route("/api/tasks", get(handlers::list_tasks))
-    .route("/api/tasks/:id", put(handlers::update_task))
-    .route("/api/tasks/:id", delete(handlers::delete_task))
-    .layer(middleware::from_fn(auth_middleware)); 
+    .route("/api/users", post(handlers::create_user).route_layer(need(Permission::UsersWrite)))
+    .route("/api/users", get(handlers::list_users).route_layer(need(Permission::UsersRead)))
+    .route("/api/users/:id", get(handlers::get_user).route_layer(need(Permission::UsersRead)))
+    .route("/api/users/:id", put(handlers::update_user).route_layer(need(Permission::UsersWrite)))
+    .route("/api/users/:id", delete(handlers::delete_user).route_layer(need(Permission::UsersWrite)))
+    .route("/api/users/:id/mfa", delete(handlers::reset_user_mfa).route_layer(need(Permission::UsersWrite)))
+    .route("/api/users/:id/lockout", delete(handlers::unlock_user).route_layer(need(Permission::UsersWrite)))
+    .route("/api/admin/impersonate/:user_id", post(handlers::impersonate_user).route_layer(need(Permission::UsersImpersonate)))
+    .route("/api/invitations", post(handlers::create_invitation).route_layer(need(Permission::UsersWrite)))
+    .route("/api/invitations", get(handlers::list_invitations).route_layer(need(Permission::UsersRead)))
+    .route("/api/invitations/:id", delete(handlers::revoke_invitation).route_layer(need(Permission::UsersWrite)))
+    .route("/api/roles", get(handlers::list_roles).route_layer(need(Permission::RolesRead)))
+    .route("/api/roles", post(handlers::create_role).route_layer(need(Permission::RolesWrite)))
+    .route("/api/roles/:name", put(handlers::update_role).route_layer(need(Permission::RolesWrite)))
+    .route("/api/roles/:name", delete(handlers::delete_role).route_layer(need(Permission::RolesWrite)))
+    .route("/api/tasks", post(handlers::create_task).route_layer(need(Permission::TasksWrite)))
+    .route("/api/tasks/:id", get(handlers::get_task).route_layer(need(Permission::TasksRead)))
+    .route("/api/tasks", get(handlers::list_tasks).route_layer(need(Permission::TasksRead)))
+    .route("/api/tasks/export", get(handlers::export_tasks).route_layer(need(Permission::TasksRead)))
+    .route("/api/tasks/import", post(handlers::import_tasks).route_layer(need(Permission::TasksWrite)))
+    .route("/api/tasks/:id", put(handlers::update_task).route_layer(need(Permission::TasksWrite)))
+    .route("/api/tasks/:id", delete(handlers::delete_task).route_layer(need(Permission::TasksWrite)))
+    .route("/api/stats", get(handlers::get_stats).route_layer(need(Permission::TasksRead)))
+    .route("/api/calendar/feed-token", post(handlers::regenerate_calendar_token).route_layer(need(Permission::TasksRead)))
+    .route("/api/calendar/feed-token", delete(handlers::revoke_calendar_token).route_layer(need(Permission::TasksRead)))
+    .route("/api/me", get(handlers::get_me))
+    .route("/api/me", patch(handlers::update_me))
+    .route("/api/me", delete(handlers::delete_me))
+    .route("/api/me/export", get(handlers::export_me))
+    .route("/api/me/password", post(handlers::change_my_password))
+    .route("/api/me/email", post(handlers::change_my_email))
+    .route("/api/me/preferences", get(handlers::get_my_preferences))
+    .route("/api/me/preferences", patch(handlers::update_my_preferences))
+    .route("/api/auth/logout", post(handlers::logout))
+    .route("/api/auth/logout-all", post(handlers::logout_all))
+    .route("/api/auth/sessions", get(handlers::list_sessions))
+    .route("/api/auth/sessions/:id", delete(handlers::delete_session))
+    .route("/api/auth/mfa/setup", post(handlers::mfa_setup))
+    .route("/api/auth/mfa/confirm", post(handlers::mfa_confirm))
+    .route("/api/auth/mfa/disable", post(handlers::mfa_disable))
+    .route("/api/tokens", post(handlers::create_api_token))
+    .route("/api/tokens", get(handlers::list_api_tokens))
+    .route("/api/tokens/:id", delete(handlers::revoke_api_token))
+    .layer(middleware::from_fn_with_state(app_state.clone(), auth_middleware)); 
+
+  // CalDAV routes (HTTP Basic authentication, custom methods such as PROPFIND/REPORT)
+  let caldav_routes = Router::new()
+    .route("/.well-known/caldav", any(handlers::caldav_well_known))
+    .route("/dav", any(handlers::caldav_root))
+    .route("/dav/", any(handlers::caldav_root))
+    .route("/dav/principals/:user_id", any(handlers::caldav_principal))
+    .route("/dav/principals/:user_id/", any(handlers::caldav_principal))
+    .route("/dav/calendars/:user_id", any(handlers::caldav_home))
+    .route("/dav/calendars/:user_id/", any(handlers::caldav_home))
+    .route("/dav/calendars/:user_id/tasks", any(handlers::caldav_collection))
+    .route("/dav/calendars/:user_id/tasks/", any(handlers::caldav_collection))
+    .route("/dav/calendars/:user_id/tasks/:resource", any(handlers::caldav_resource));
 
   Router::new()
     .merge(public_routes)
+    .merge(caldav_routes)
     .merge(protected_routes)
     .with_state(app_state)
 }
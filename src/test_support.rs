@@ -0,0 +1,203 @@
+// Helpers for handler tests that need a real MongoDB, and a stand-in identity provider
+// Tests that need MongoDB are ignored by default; run them with
+// `TEST_DATABASE_URL=mongodb://127.0.0.1:27017 cargo test -- --ignored`
+use axum::{
+  body::{to_bytes, Body},
+  http::{header, Request, StatusCode},
+  response::Response,
+};
+use ed25519_dalek::{pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey}, SigningKey};
+use httpmock::{Method::{GET, POST}, MockServer};
+use axum::async_trait;
+use mongodb::{bson::oid::ObjectId, Client};
+use std::sync::{Arc, Mutex};
+use tower::ServiceExt;
+use crate::{
+  accounts::{create_account, NewAccount},
+  auth::{start_session, ClientInfo, JwtKeys, TokenKind},
+  config::{AppConfig, JwtAlgorithm, JwtConfig, MailerBackend, OidcConfig, RevocationBackend},
+  db::{ensure_indexes, run_migrations, AppState},
+  mailer::{Email, Mailer},
+  models::{User, USER_ROLE},
+  routes::create_router,
+  utils::AppError,
+};
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+/// A state backed by a new, empty database with indexes and built-in roles
+pub async fn test_state() -> AppState {
+  test_state_with(|_| {}).await
+}
+
+pub async fn test_state_with(configure: impl FnOnce(&mut AppConfig)) -> AppState {
+  let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+  let client = Client::with_uri_str(&url).await.expect("Failed to connect to the test database");
+  let db = client.database(&format!("to_do_list_test_{}", ObjectId::new()));
+
+  let mut config = AppConfig::from_env();
+  config.jwt.secret = Some("test-secret".to_string());
+  config.revocation_store = RevocationBackend::Memory;
+  config.mailer = MailerBackend::Log;
+  configure(&mut config);
+
+  ensure_indexes(&db).await.expect("Failed to create indexes");
+  run_migrations(&db, &config).await.expect("Failed to migrate");
+  AppState::new(db, config)
+}
+
+/// A mailer that keeps what it is given; install it with `Outbox::install`
+#[derive(Default)]
+pub struct Outbox {
+  sent: Mutex<Vec<Email>>,
+}
+
+impl Outbox {
+  pub fn install(app_state: &mut AppState) -> Arc<Self> {
+    let outbox = Arc::new(Self::default());
+    app_state.mailer = outbox.clone();
+    outbox
+  }
+
+  /// Everything sent so far, oldest first
+  pub fn sent(&self) -> Vec<Email> {
+    self.sent.lock().unwrap().clone()
+  }
+}
+
+#[async_trait]
+impl Mailer for Outbox {
+  async fn send(&self, email: Email) -> Result<(), AppError> {
+    self.sent.lock().unwrap().push(email);
+    Ok(())
+  }
+}
+
+/// A verified account with `PASSWORD`
+pub async fn create_user(app_state: &AppState, email: &str, role: &str) -> User {
+  let account = NewAccount {
+    full_name: "Test User".to_string(),
+    email: email.to_string(),
+    password: PASSWORD.to_string(),
+    role: role.to_string(),
+    verified: true,
+  };
+  create_account(app_state, account, None).await.expect("Failed to create user")
+}
+
+/// An access token from a fresh login session
+pub async fn login_token(app_state: &AppState, user: &User) -> String {
+  start_session(app_state, user, &ClientInfo::default())
+    .await
+    .expect("Failed to start session")
+    .token
+}
+
+/// Send a request through the full router
+pub async fn request(app_state: &AppState, request: Request<Body>) -> Response {
+  create_router(app_state.clone())
+    .oneshot(request)
+    .await
+    .expect("Router failed")
+}
+
+/// Send a request through the full router; the body is JSON when there is one
+pub async fn send(
+  app_state: &AppState,
+  method: &str,
+  uri: &str,
+  token: Option<&str>,
+  body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+  let mut request = Request::builder().method(method).uri(uri);
+  if let Some(token) = token {
+    request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+  }
+  let request = match body {
+    Some(body) => request
+      .header(header::CONTENT_TYPE, "application/json")
+      .body(Body::from(body.to_string())),
+    None => request.body(Body::empty()),
+  }.expect("Failed to build request");
+
+  let response = self::request(app_state, request).await;
+  let status = response.status();
+  let bytes = to_bytes(response.into_body(), usize::MAX).await.expect("Failed to read body");
+  let body = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+  (status, body)
+}
+
+/// An OpenID Connect provider on a local port: it serves discovery and its signing key, and
+/// its token endpoint answers with whatever `issue_id_token` was last given
+pub struct MockIdp {
+  pub server: MockServer,
+  keys: JwtKeys,
+}
+
+impl MockIdp {
+  pub const CLIENT_ID: &str = "todo";
+
+  pub async fn start() -> Self {
+    let server = MockServer::start_async().await;
+    let key_file = std::env::temp_dir().join(format!("mock-idp-{}-{}.pem", std::process::id(), server.port()));
+    let pem = SigningKey::from_bytes(&[7; 32]).to_pkcs8_pem(LineEnding::LF).expect("Failed to encode key");
+    std::fs::write(&key_file, pem.as_bytes()).expect("Failed to write key");
+    let keys = JwtKeys::from_config(&JwtConfig {
+      algorithm: JwtAlgorithm::EdDsa,
+      secret: None,
+      private_key_file: Some(key_file),
+      key_id: None,
+      verification_key_files: Vec::new(),
+      issuer: server.base_url(),
+      audience: Self::CLIENT_ID.to_string(),
+      validate_issuer: true,
+      validate_audience: true,
+      leeway_seconds: 0,
+    }).expect("Failed to load key");
+
+    let base_url = server.base_url();
+    server.mock_async(|when, then| {
+      when.method(GET).path("/.well-known/openid-configuration");
+      then.status(200).json_body(serde_json::json!({
+        "issuer": base_url,
+        "authorization_endpoint": format!("{base_url}/authorize"),
+        "token_endpoint": format!("{base_url}/token"),
+        "jwks_uri": format!("{base_url}/jwks"),
+      }));
+    }).await;
+    let jwks = serde_json::to_value(keys.jwks()).expect("Failed to encode JWKS");
+    server.mock_async(|when, then| {
+      when.method(GET).path("/jwks");
+      then.status(200).json_body(jwks);
+    }).await;
+
+    Self { server, keys }
+  }
+
+  pub fn config(&self) -> OidcConfig {
+    OidcConfig {
+      issuer_url: self.server.base_url(),
+      client_id: Self::CLIENT_ID.to_string(),
+      client_secret: None,
+      redirect_url: "http://127.0.0.1:3000/api/auth/oidc/callback".to_string(),
+      scopes: "openid email".to_string(),
+      auto_create_users: true,
+      require_verified_email: true,
+      role_claim: None,
+      role_mapping: Vec::new(),
+      default_role: USER_ROLE.to_string(),
+    }
+  }
+
+  /// Answer the next code exchanges with an ID token carrying `claims`, plus `iss`, `aud` and `exp`
+  pub async fn issue_id_token(&self, claims: serde_json::Value) {
+    let mut claims = claims;
+    claims["exp"] = serde_json::json!(chrono::Utc::now().timestamp() + 300);
+    // The client does not look at `typ`, so the access token kind does for an ID token
+    let id_token = self.keys.sign(TokenKind::Access, &claims).expect("Failed to sign ID token");
+    self.server.mock_async(|when, then| {
+      when.method(POST).path("/token");
+      then.status(200).json_body(serde_json::json!({ "id_token": id_token, "token_type": "Bearer" }));
+    }).await;
+  }
+}
@@ -7,11 +7,29 @@ use serde::Serialize;
 #[derive(Serialize)]
 pub struct ErrorResponse {
   pub message: String,  
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub errors: Vec<FieldError>,
 }
 
+// Why one request field was rejected; `code` is stable, `message` is for people
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FieldError {
+  pub field: String,
+  pub code: String,
+  pub message: String,
+}
+
+impl FieldError {
+  pub fn new(field: &str, code: &str, message: impl Into<String>) -> Self {
+    Self { field: field.to_string(), code: code.to_string(), message: message.into() }
+  }
+}
+
+#[derive(Debug)]
 pub struct AppError {
   pub status: StatusCode,
   pub message: String, 
+  pub errors: Vec<FieldError>,
 }
 
 impl AppError {
@@ -19,6 +37,14 @@ impl AppError {
     Self {
       status,
       message: message.into(),
+      errors: Vec::new(),
+    }
+  }
+
+  pub fn validation(errors: Vec<FieldError>) -> Self {
+    Self {
+      errors,
+      ..Self::new(StatusCode::UNPROCESSABLE_ENTITY, "Validation failed")
     }
   }
 
@@ -52,6 +78,7 @@ impl IntoResponse for AppError {
   fn into_response(self) -> Response {
     let body = Json(ErrorResponse {
       message: self.message,
+      errors: self.errors,
     });
     (self.status, body).into_response()
   }
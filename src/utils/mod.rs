@@ -1,5 +1,7 @@
 pub mod errors;
 pub mod result_ext;
+pub mod token;
 
-pub use errors::{AppError, ErrorResponse};
-pub use result_ext::ResultExt;
\ No newline at end of file
+pub use errors::{AppError, FieldError};
+pub use result_ext::ResultExt;
+pub use token::{generate_token, hash_token};
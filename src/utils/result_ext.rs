@@ -1,6 +1,7 @@
 use super::errors::AppError;   // use Struct
 use axum::http::StatusCode;
 
+#[allow(dead_code)]
 pub trait ResultExt<T, E> {
   fn app_error(self, status: StatusCode, error: impl Into<String>) -> Result<T, AppError>;
   fn internal_error(self, msg: impl Into<String>) -> Result<T, AppError>;
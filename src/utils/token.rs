@@ -0,0 +1,14 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Generate a random, URL-safe secret token (32 bytes, hex encoded)
+pub fn generate_token() -> String {
+  let mut bytes = [0u8; 32];
+  rand::thread_rng().fill_bytes(&mut bytes);
+  hex::encode(bytes)
+}
+
+/// Hash a secret token before it is stored, so a database leak does not leak usable tokens
+pub fn hash_token(token: &str) -> String {
+  hex::encode(Sha256::digest(token.as_bytes()))
+}